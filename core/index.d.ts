@@ -1,9 +1,122 @@
 /* auto-generated by fix-core-types.js */
 export interface RouteOptions {
-  rate_limit_limit?: number | null
-  rate_limit_window?: number | null
-  cache_ttl?: number | null
-  jwt_auth?: boolean | null
+  rateLimitLimit?: number | null
+  rateLimitWindow?: number | null
+  rateLimitAlgorithm?: string | null
+  rateLimitRate?: number | null
+  rateLimitBurst?: number | null
+  rateLimitKey?: string | null
+  rateLimitPerRoute?: boolean | null
+  rateLimits?: RateLimitOptions[] | null
+  cacheTtl?: number | null
+  cacheQuery?: string | null
+  cacheQueryParams?: string[] | null
+  cacheIgnoreParams?: string[] | null
+  cacheVary?: string[] | null
+  cacheVaryUser?: boolean | null
+  cacheStaleWhileRevalidate?: number | null
+  cacheStaleIfError?: number | null
+  jwtAuth?: boolean | null
+  apiKeyAuth?: boolean | null
+  basicAuth?: string | null
+  authorize?: AuthorizeOptions[] | null
+  clientCert?: ClientCertOptions | null
+  securityHeaders?: SecurityHeadersOptions | null
+  cors?: CorsOptions | null
+  csrfExempt?: boolean | null
+  schema?: string | null
+  priority?: string | null
+  sloTarget?: number | null
+}
+
+export interface RateLimitOptions {
+  limit?: number | null
+  window?: number | null
+  algorithm?: string | null
+  rate?: number | null
+  burst?: number | null
+  key?: string | null
+  perRoute?: boolean | null
+}
+
+export interface AuthorizeOptions {
+  claim?: string | null
+  values: string[]
+  mode?: string | null
+}
+
+export interface ClientCertOptions {
+  subjects?: string[] | null
+  sans?: string[] | null
+  fingerprints?: string[] | null
+}
+
+export interface SecurityHeadersOptions {
+  contentSecurityPolicy?: string | null
+  cspReportOnly?: boolean | null
+  permissionsPolicy?: string | null
+  crossOriginOpenerPolicy?: string | null
+  crossOriginEmbedderPolicy?: string | null
+  crossOriginResourcePolicy?: string | null
+  referrerPolicy?: string | null
+  frameOptions?: string | null
+  nosniff?: boolean | null
+}
+
+export interface CorsOptions {
+  origins?: string[] | null
+  methods?: string[] | null
+  allowHeaders?: string[] | null
+  exposeHeaders?: string[] | null
+  credentials?: boolean | null
+  maxAge?: number | null
+  privateNetwork?: boolean | null
+}
+
+export interface TlsCertificateOptions {
+  hostnames: string[]
+  certPath: string
+  keyPath: string
+  ocspPath?: string | null
+}
+
+export interface HstsOptions {
+  maxAge?: number | null
+  includeSubdomains?: boolean | null
+  preload?: boolean | null
+}
+
+export interface HttpsRedirectOptions {
+  httpsPort?: number | null
+  host?: string | null
+  status?: number | null
+}
+
+export interface CsrfOptions {
+  secret?: string | null
+  cookieName?: string | null
+  headerName?: string | null
+  formField?: string | null
+  trustedOrigins?: string[] | null
+  secure?: boolean | null
+}
+
+export interface CookieOptions {
+  path?: string | null
+  domain?: string | null
+  maxAge?: number | null
+  secure?: boolean | null
+  httpOnly?: boolean | null
+  sameSite?: string | null
+}
+
+export interface SessionOptions {
+  cookieName?: string | null
+  ttl?: number | null
+  sliding?: boolean | null
+  store?: string | null
+  redisPrefix?: string | null
+  cookie?: CookieOptions | null
 }
 
 export class NativeEngine {
@@ -13,18 +126,32 @@ export class NativeEngine {
   registerJsonRoute(method: string, path: string, content: string, options?: RouteOptions | null): void
   registerUploadRoute(method: string, path: string, dir: string, handlerId?: number | null, options?: RouteOptions | null): void
   addStaticRoute(prefix: string, dir: string): void
-  setHandler(callback: (event: { handlerId: number, reqId: string, params: string[], query: string, headers: string[], method: string, url: string, body: Buffer, responseHandle: any }) => void): void
-  setWsHandler(callback: (event: { socketId: string, eventType: string, payload?: string }) => void): void
+  setHandler(callback: (event: { handlerId: number, reqId: string, params: string[], query: string, headers: string[], method: string, url: string, body: Buffer, user?: Record<string, any>, apiKey?: Record<string, any>, clientCert?: Record<string, any>, cspNonce?: string, csrfToken?: string, cookies: Record<string, string>, session?: Record<string, any>, responseHandle: any }) => void): void
+  setWsHandler(callback: (event: { socketId: string, eventType: string, payload?: string, path?: string, user?: Record<string, any> }) => void): void
   wsSend(socketId: string, message: string): void
   wsSubscribe(socketId: string, room: string): void
   wsUnsubscribe(socketId: string, room: string): void
   wsPublish(room: string, message: string): void
-  setCors(origin: string, methods: String, headers: string, credentials: boolean): void
+  setCors(options?: CorsOptions | null): void
+  setCsrf(options?: CsrfOptions | null): void
+  setCookieKeys(secrets?: string[] | null): void
+  signCookie(name: string, value: string): string
+  unsignCookie(name: string, value: string): string | null
+  encryptCookie(name: string, value: string): string
+  decryptCookie(name: string, value: string): string | null
+  serializeCookie(name: string, value: string, options?: CookieOptions | null): string
+  configureSessions(options?: SessionOptions | null): void
+  saveSession(reqId: string, data?: any | null, regenerate?: boolean | null): void
   setSecurityHeaders(enabled: boolean): void
-  setTls(certPath: string, keyPath: string): void
-  sendResponse(handle: any, status: number, body: string): void
-  sendJson(handle: any, status: number, body: any): void
-  sendHtml(handle: any, status: number, body: string): void
+  setTls(certPath: string, keyPath: string, ocspPath?: string | null): void
+  addTlsCertificate(options: TlsCertificateOptions): void
+  reloadTls(): number
+  watchTls(intervalMs?: number | null): void
+  setHsts(options?: HstsOptions | null): void
+  enableHttpsRedirect(port: number, options?: HttpsRedirectOptions | null): void
+  sendResponse(handle: any, status: number, body: string, headers?: string[] | null): void
+  sendJson(handle: any, status: number, body: any, headers?: string[] | null): void
+  sendHtml(handle: any, status: number, body: string, headers?: string[] | null): void
   connectPostgres(url: string): Promise<void>
   connectSqlite(url: string): Promise<void>
   connectRedis(url: string): void
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use hyper::header::HeaderMap;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// A known API key. Only the SHA-256 of the key is ever held in memory.
+#[derive(Debug)]
+pub struct ApiKey {
+    pub id: String, // Stable identity for rate limiting: `id`, else `owner`, else a hash prefix
+    pub metadata: Value, // Object with owner, scopes, tier and any extra fields, forwarded to JS
+}
+
+/// One entry as handed over from JS, SQL rows or Redis hashes, before hashing.
+pub struct ApiKeyRecord {
+    pub key: Option<String>, // Plaintext, hashed on load
+    pub hash: Option<String>, // Hex SHA-256, for stores that never held the plaintext
+    pub metadata: Map<String, Value>,
+}
+
+impl ApiKeyRecord {
+    /// Splits a row-like object: `key`/`api_key` and `key_hash`/`hash` identify the key,
+    /// every other field is metadata. `scopes` may be an array or a space-delimited string.
+    pub fn from_fields(mut fields: Map<String, Value>) -> Self {
+        let mut take = |names: &[&str]| names.iter()
+            .find_map(|name| fields.remove(*name))
+            .and_then(|value| value.as_str().map(str::to_string));
+        let key = take(&["key", "api_key"]);
+        let hash = take(&["key_hash", "hash"]);
+
+        if let Some(Value::String(scopes)) = fields.get("scopes") {
+            let list = scopes.split_whitespace().map(|s| Value::String(s.to_string())).collect();
+            fields.insert("scopes".to_string(), Value::Array(list));
+        }
+        Self { key, hash, metadata: fields }
+    }
+}
+
+pub fn hash_key(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// API keys accepted by `api_key_auth` routes, looked up by hash.
+pub struct ApiKeyStore {
+    keys: RwLock<Arc<HashMap<[u8; 32], Arc<ApiKey>>>>,
+    header: RwLock<String>,
+}
+
+impl ApiKeyStore {
+    pub fn new() -> Self {
+        Self {
+            keys: RwLock::new(Arc::new(HashMap::new())),
+            header: RwLock::new("x-api-key".to_string()),
+        }
+    }
+
+    pub fn set_header(&self, header: &str) {
+        *self.header.write().unwrap() = header.trim().to_ascii_lowercase();
+    }
+
+    /// Loads `records`, replacing the current set or adding to it. Returns the number of keys held.
+    pub fn load(&self, records: Vec<ApiKeyRecord>, replace: bool) -> Result<usize, String> {
+        let mut loaded = Vec::with_capacity(records.len());
+        for record in records {
+            let hash = match (record.key, record.hash) {
+                (Some(key), _) => hash_key(&key),
+                (None, Some(hex_hash)) => {
+                    let bytes = hex::decode(hex_hash.trim()).map_err(|_| "API key hash must be hex-encoded SHA-256".to_string())?;
+                    bytes.try_into().map_err(|_| "API key hash must be a 32-byte SHA-256".to_string())?
+                }
+                (None, None) => return Err("API key record needs a key or a key hash".to_string()),
+            };
+            let id = ["id", "owner"].iter()
+                .find_map(|field| record.metadata.get(*field).and_then(Value::as_str).map(str::to_string))
+                .unwrap_or_else(|| hex::encode(&hash[..8]));
+            loaded.push((hash, Arc::new(ApiKey { id, metadata: Value::Object(record.metadata) })));
+        }
+
+        let mut keys = self.keys.write().unwrap();
+        let mut next = if replace { HashMap::new() } else { keys.as_ref().clone() };
+        next.extend(loaded);
+        let count = next.len();
+        *keys = Arc::new(next);
+        Ok(count)
+    }
+
+    pub fn revoke(&self, key: &str) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        let mut next = keys.as_ref().clone();
+        let removed = next.remove(&hash_key(key)).is_some();
+        *keys = Arc::new(next);
+        removed
+    }
+
+    /// The presented key, if the request carries one in the configured header.
+    pub fn extract<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        let header = self.header.read().unwrap();
+        headers.get(header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<Arc<ApiKey>> {
+        self.keys.read().unwrap().get(&hash_key(key)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(fields: Value) -> ApiKeyRecord {
+        ApiKeyRecord::from_fields(fields.as_object().unwrap().clone())
+    }
+
+    #[test]
+    fn looks_up_plain_and_prehashed_keys() {
+        let store = ApiKeyStore::new();
+        let prehashed = hex::encode(hash_key("partner-b-key"));
+        let count = store.load(vec![
+            record(json!({ "key": "partner-a-key", "owner": "partner-a", "scopes": "orders:read orders:write", "tier": "gold" })),
+            record(json!({ "key_hash": prehashed })),
+        ], true).unwrap();
+        assert_eq!(count, 2);
+
+        let a = store.lookup("partner-a-key").unwrap();
+        assert_eq!(a.id, "partner-a");
+        assert_eq!(a.metadata["scopes"], json!(["orders:read", "orders:write"]));
+        assert!(a.metadata.get("key").is_none());
+        assert_eq!(store.lookup("partner-b-key").unwrap().id.len(), 16);
+        assert!(store.lookup("partner-c-key").is_none());
+
+        assert!(store.revoke("partner-a-key"));
+        assert!(store.lookup("partner-a-key").is_none());
+        assert!(store.load(vec![record(json!({ "key_hash": "abc" }))], false).is_err());
+        assert_eq!(store.load(Vec::new(), false), Ok(1));
+    }
+}
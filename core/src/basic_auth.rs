@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper::header::{HeaderMap, AUTHORIZATION};
+use sha2::{Digest, Sha256};
+
+// Verified credentials are remembered this long, so browsers and scrapers that send
+// Basic auth on every request do not pay for a bcrypt/argon2 hash each time
+const VERIFIED_TTL: Duration = Duration::from_secs(300);
+const VERIFIED_MAX: usize = 1024;
+
+/// Users of one realm, from an htpasswd-style file with bcrypt or argon2 hashes.
+///
+/// Digest auth is deliberately not offered: it needs MD5(user:realm:password) on the
+/// server, which cannot be derived from bcrypt/argon2 hashes.
+pub struct Realm {
+    pub name: String,
+    source: Option<String>, // File path, re-read by reload
+    users: HashMap<String, String>,
+    verified: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl Realm {
+    /// Parses "user:hash" lines; blank lines and `#` comments are skipped.
+    pub fn parse(name: &str, content: &str, source: Option<String>) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line.split_once(':')
+                .ok_or_else(|| format!("htpasswd line {}: expected user:hash", number + 1))?;
+            if !is_supported_hash(hash) {
+                return Err(format!("htpasswd line {}: only bcrypt ($2y$) and argon2 hashes are supported", number + 1));
+            }
+            users.insert(user.to_string(), hash.to_string());
+        }
+        Ok(Self { name: name.to_string(), source, users, verified: Mutex::new(HashMap::new()) })
+    }
+
+    pub fn load(name: &str, path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read htpasswd file {}: {}", path, e))?;
+        Self::parse(name, &content, Some(path.to_string())).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Checks the request's Basic credentials. Hashing runs off the async workers.
+    pub async fn check(self: &Arc<Self>, headers: &HeaderMap) -> Option<String> {
+        let (user, password) = credentials(headers)?;
+        let hash = self.users.get(&user)?.clone();
+
+        let fingerprint: [u8; 32] = Sha256::digest(format!("{}:{}", user, password).as_bytes()).into();
+        if self.verified.lock().unwrap().get(&fingerprint).is_some_and(|at| at.elapsed() < VERIFIED_TTL) {
+            return Some(user);
+        }
+
+        let valid = tokio::task::spawn_blocking(move || verify_password(&password, &hash)).await.unwrap_or(false);
+        if !valid {
+            return None;
+        }
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= VERIFIED_MAX {
+            verified.retain(|_, at| at.elapsed() < VERIFIED_TTL);
+            if verified.len() >= VERIFIED_MAX {
+                verified.clear();
+            }
+        }
+        verified.insert(fingerprint, Instant::now());
+        Some(user)
+    }
+
+    pub fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.name.replace('"', "'"))
+    }
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$", "$argon2"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        use argon2::password_hash::{PasswordHash, PasswordVerifier};
+        PasswordHash::new(hash)
+            .is_ok_and(|parsed| argon2::Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+fn credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Named realms referenced by the `basic_auth` route policy and the metrics endpoint.
+pub struct BasicAuth {
+    realms: RwLock<HashMap<String, Arc<Realm>>>,
+    metrics_realm: RwLock<Option<String>>,
+}
+
+impl BasicAuth {
+    pub fn new() -> Self {
+        Self {
+            realms: RwLock::new(HashMap::new()),
+            metrics_realm: RwLock::new(None),
+        }
+    }
+
+    pub fn add_realm(&self, realm: Realm) {
+        self.realms.write().unwrap().insert(realm.name.clone(), Arc::new(realm));
+    }
+
+    pub fn realm(&self, name: &str) -> Option<Arc<Realm>> {
+        self.realms.read().unwrap().get(name).cloned()
+    }
+
+    pub fn has_realm(&self, name: &str) -> bool {
+        self.realms.read().unwrap().contains_key(name)
+    }
+
+    /// Re-reads every file-backed realm. On error all realms keep their current users.
+    pub fn reload(&self) -> Result<usize, String> {
+        let reloaded = {
+            let realms = self.realms.read().unwrap();
+            realms.values()
+                .filter_map(|realm| realm.source.as_ref().map(|path| Realm::load(&realm.name, path)))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let count = reloaded.len();
+        for realm in reloaded {
+            self.add_realm(realm);
+        }
+        Ok(count)
+    }
+
+    pub fn protect_metrics(&self, realm: Option<String>) {
+        *self.metrics_realm.write().unwrap() = realm;
+    }
+
+    pub fn metrics_realm(&self) -> Option<String> {
+        self.metrics_realm.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(user: &str, password: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password)));
+        headers.insert(AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn checks_bcrypt_and_argon2_users() {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+        let bcrypt_hash = bcrypt::hash("hunter2", 4).unwrap();
+        let salt = SaltString::encode_b64(b"qhttpx-test-salt").unwrap();
+        let argon_hash = argon2::Argon2::default().hash_password(b"correct horse", &salt).unwrap().to_string();
+        let file = format!("# ops\nalice:{}\n\nbob:{}\n", bcrypt_hash, argon_hash);
+        let realm = Arc::new(Realm::parse("Internal", &file, None).unwrap());
+
+        assert_eq!(realm.check(&basic("alice", "hunter2")).await.as_deref(), Some("alice"));
+        assert_eq!(realm.check(&basic("alice", "hunter2")).await.as_deref(), Some("alice"));
+        assert_eq!(realm.check(&basic("bob", "correct horse")).await.as_deref(), Some("bob"));
+        assert_eq!(realm.check(&basic("alice", "hunter3")).await, None);
+        assert_eq!(realm.check(&basic("carol", "hunter2")).await, None);
+        assert_eq!(realm.check(&HeaderMap::new()).await, None);
+
+        assert!(Realm::parse("Internal", "eve:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=", None).is_err());
+    }
+}
@@ -0,0 +1,853 @@
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use hyper::StatusCode;
+use hyper::header::{self, HeaderMap};
+use tokio::sync::watch;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum QueryKey {
+    #[default]
+    All,                // Every query param, order-insensitive
+    Ignore,             // Query string is not part of the key
+    Only(Vec<String>),  // Just these params
+    Except(Vec<String>), // Everything but these (e.g. utm_* tracking params)
+}
+
+/// Describes which parts of a request make two cached responses distinct.
+#[derive(Clone, Debug, Default)]
+pub struct CacheKeyPolicy {
+    pub query: QueryKey,
+    pub vary_headers: Vec<String>, // Lowercase header names
+    pub vary_user: bool,           // Include the caller: JWT `sub` or API key id
+}
+
+impl CacheKeyPolicy {
+    pub fn new(query: Option<String>, only: Option<Vec<String>>, except: Option<Vec<String>>, vary: Option<Vec<String>>, vary_user: bool) -> Self {
+        let query = match (query.as_deref(), only, except) {
+            (Some("none"), _, _) => QueryKey::Ignore,
+            (_, Some(only), _) => QueryKey::Only(only),
+            (_, _, Some(except)) => QueryKey::Except(except),
+            _ => QueryKey::All,
+        };
+        Self {
+            query,
+            vary_headers: vary
+                .unwrap_or_default()
+                .into_iter()
+                .map(|h| h.to_ascii_lowercase())
+                .collect(),
+            vary_user,
+        }
+    }
+
+    /// Builds the cache key: "GET /search?a=1&b=2|accept=text/html|sub=42".
+    pub fn key(&self, method: &str, path: &str, query: Option<&str>, headers: &HeaderMap, subject: Option<&str>, api_key: Option<&str>) -> String {
+        let mut key = format!("{} {}", method, path);
+
+        let mut pairs: Vec<&str> = match &self.query {
+            QueryKey::Ignore => Vec::new(),
+            _ => query
+                .unwrap_or("")
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .filter(|pair| {
+                    let name = pair.split('=').next().unwrap_or("");
+                    match &self.query {
+                        QueryKey::Only(names) => names.iter().any(|n| n == name),
+                        QueryKey::Except(names) => !names.iter().any(|n| n == name),
+                        _ => true,
+                    }
+                })
+                .collect(),
+        };
+        if !pairs.is_empty() {
+            pairs.sort_unstable();
+            key.push('?');
+            key.push_str(&pairs.join("&"));
+        }
+
+        for name in &self.vary_headers {
+            let value = headers.get(name.as_str()).and_then(|v| v.to_str().ok()).unwrap_or("");
+            key.push_str(&format!("|{}={}", name, value));
+        }
+
+        if self.vary_user {
+            match (subject, api_key) {
+                (Some(sub), _) => key.push_str(&format!("|sub={}", sub)),
+                (None, Some(id)) => key.push_str(&format!("|key={}", id)),
+                (None, None) => key.push_str("|sub="),
+            }
+        }
+
+        key
+    }
+}
+
+/// A shared cache must not store responses the handler marked `no-store` or `private`.
+pub fn is_storable(headers: &HeaderMap) -> bool {
+    !headers.get_all(header::CACHE_CONTROL).iter().any(|value| {
+        value.to_str().unwrap_or("").split(',').any(|directive| {
+            let directive = directive.trim().to_ascii_lowercase();
+            directive == "no-store" || directive == "private" || directive.starts_with("private=")
+        })
+    })
+}
+
+/// Approximate heap footprint of a cached value, used against the byte budget.
+pub trait Weigh {
+    fn weight(&self) -> usize;
+}
+
+impl Weigh for Bytes {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weigh for String {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Weigh> Weigh for Arc<T> {
+    fn weight(&self) -> usize {
+        self.as_ref().weight()
+    }
+}
+
+/// How long a cached response is served fresh, then stale while a background refresh
+/// runs, then stale only when the handler fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheLifetime {
+    pub ttl: Duration,
+    pub stale_while_revalidate: Duration,
+    pub stale_if_error: Duration,
+}
+
+impl CacheLifetime {
+    pub fn new(ttl_sec: u64, stale_while_revalidate_sec: Option<u64>, stale_if_error_sec: Option<u64>) -> Self {
+        Self {
+            ttl: Duration::from_secs(ttl_sec),
+            stale_while_revalidate: Duration::from_secs(stale_while_revalidate_sec.unwrap_or(0)),
+            stale_if_error: Duration::from_secs(stale_if_error_sec.unwrap_or(0)),
+        }
+    }
+
+    /// How long the entry must be kept in the store to serve every stale window.
+    pub fn retention(&self) -> Duration {
+        self.ttl + self.stale_while_revalidate.max(self.stale_if_error)
+    }
+
+    /// The `Cache-Control` value advertised when the handler did not set one.
+    pub fn cache_control(&self) -> String {
+        let mut value = format!("public, max-age={}", self.ttl.as_secs());
+        if !self.stale_while_revalidate.is_zero() {
+            value.push_str(&format!(", stale-while-revalidate={}", self.stale_while_revalidate.as_secs()));
+        }
+        if !self.stale_if_error.is_zero() {
+            value.push_str(&format!(", stale-if-error={}", self.stale_if_error.as_secs()));
+        }
+        value
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    Revalidate,   // Serve stale, refresh in the background
+    StaleIfError, // Only usable if the handler fails
+    Expired,
+}
+
+/// Statuses a shared cache may store without explicit freshness (RFC 9110 heuristically cacheable).
+pub fn is_cacheable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414)
+}
+
+// Not replayed from the cache: per-connection, per-client or recomputed on every hit
+const UNCACHED_HEADERS: [&str; 7] = ["connection", "keep-alive", "transfer-encoding", "set-cookie", "age", "x-cache", CACHE_TAG];
+
+/// A cached route response. `path` and `tags` are what purges match against.
+#[derive(Debug)]
+pub struct CachedResponse {
+    pub path: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub tags: Vec<String>,
+    pub stored_at: Instant,
+    pub lifetime: CacheLifetime,
+}
+
+impl CachedResponse {
+    /// Captures a response for the cache. Hop-by-hop headers, `Set-Cookie` and
+    /// `Cache-Tag` are dropped; the tags are kept separately for purging.
+    pub fn new(path: String, status: StatusCode, headers: &HeaderMap, body: Bytes, lifetime: CacheLifetime) -> Self {
+        let tags = parse_tags(headers);
+        let mut headers = headers.clone();
+        for name in UNCACHED_HEADERS {
+            headers.remove(name);
+        }
+        Self { path, status, headers, body, tags, stored_at: Instant::now(), lifetime }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.stored_at.elapsed()
+    }
+
+    pub fn freshness(&self) -> Freshness {
+        let age = self.age();
+        let lifetime = &self.lifetime;
+        if age < lifetime.ttl {
+            Freshness::Fresh
+        } else if age < lifetime.ttl + lifetime.stale_while_revalidate {
+            Freshness::Revalidate
+        } else if age < lifetime.ttl + lifetime.stale_if_error {
+            Freshness::StaleIfError
+        } else {
+            Freshness::Expired
+        }
+    }
+}
+
+impl Weigh for CachedResponse {
+    fn weight(&self) -> usize {
+        let headers: usize = self.headers.iter().map(|(k, v)| k.as_str().len() + v.len()).sum();
+        self.path.len() + headers + self.body.len() + self.tags.iter().map(|t| t.len()).sum::<usize>()
+    }
+}
+
+impl Purgeable for CachedResponse {
+    fn subject(&self) -> &str {
+        &self.path
+    }
+
+    fn tags(&self) -> &[String] {
+        &self.tags
+    }
+}
+
+/// Collapses concurrent misses for the same key: the first caller leads and does the
+/// work, the others wait until the leader's lease is dropped and then re-check the cache.
+#[derive(Default)]
+pub struct SingleFlight {
+    inflight: Mutex<HashMap<String, watch::Receiver<()>>>,
+}
+
+pub enum Flight {
+    Leader(FlightLease),
+    Follower(FlightWait),
+}
+
+/// Held by the leader; dropping it wakes every follower.
+pub struct FlightLease {
+    key: String,
+    owner: Arc<SingleFlight>,
+    _done: watch::Sender<()>,
+}
+
+impl SingleFlight {
+    pub fn join(self: &Arc<Self>, key: &str) -> Flight {
+        let mut inflight = self.inflight.lock().unwrap();
+        if let Some(rx) = inflight.get(key) {
+            return Flight::Follower(FlightWait(rx.clone()));
+        }
+        let (tx, rx) = watch::channel(());
+        inflight.insert(key.to_string(), rx);
+        Flight::Leader(FlightLease { key: key.to_string(), owner: self.clone(), _done: tx })
+    }
+
+    /// Like `join`, but never waits: None if someone else is already on it.
+    pub fn try_lead(self: &Arc<Self>, key: &str) -> Option<FlightLease> {
+        match self.join(key) {
+            Flight::Leader(lease) => Some(lease),
+            Flight::Follower(_) => None,
+        }
+    }
+}
+
+pub struct FlightWait(watch::Receiver<()>);
+
+impl FlightWait {
+    /// Resolves once the leader is done, whether or not it managed to fill the cache.
+    pub async fn wait(mut self) {
+        // The sender is never written to, so this only returns once it is dropped
+        let _ = self.0.changed().await;
+    }
+}
+
+impl Drop for FlightLease {
+    fn drop(&mut self) {
+        self.owner.inflight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Values that can be invalidated by pattern or surrogate key.
+pub trait Purgeable {
+    /// What `purge` patterns are matched against (a path, a SQL statement...)
+    fn subject(&self) -> &str;
+    fn tags(&self) -> &[String];
+}
+
+impl<T: Purgeable> Purgeable for Arc<T> {
+    fn subject(&self) -> &str {
+        self.as_ref().subject()
+    }
+
+    fn tags(&self) -> &[String] {
+        self.as_ref().tags()
+    }
+}
+
+/// Splits a `Cache-Tag` header value ("post-1, author-7" or "post-1 author-7") into tags.
+pub fn parse_tags(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CACHE_TAG)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
+pub const CACHE_TAG: &str = "cache-tag";
+
+/// Matches `text` against a pattern where `*` stands for any run of characters.
+/// A pattern without `*` must match exactly.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !text.starts_with(first) || text.len() < first.len() + last.len() || !text.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// Per-entry bookkeeping (map slot, LRU node, key copy) not covered by Weigh
+const ENTRY_OVERHEAD: usize = 96;
+const SHARDS: usize = 16;
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    size: usize,
+    tick: u64,
+}
+
+struct Shard<V> {
+    map: HashMap<String, Entry<V>>,
+    lru: BTreeMap<u64, String>, // tick -> key, oldest first
+    bytes: usize,
+    tick: u64,
+}
+
+impl<V> Shard<V> {
+    fn new() -> Self {
+        Self { map: HashMap::new(), lru: BTreeMap::new(), bytes: 0, tick: 0 }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn touch(&mut self, key: &str) {
+        let tick = self.next_tick();
+        if let Some(entry) = self.map.get_mut(key) {
+            self.lru.remove(&entry.tick);
+            entry.tick = tick;
+            self.lru.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry<V>> {
+        let entry = self.map.remove(key)?;
+        self.lru.remove(&entry.tick);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CacheMetrics {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub evictions: AtomicU64,
+    pub expirations: AtomicU64,
+}
+
+/// A sharded, byte-budgeted LRU map with per-entry expiry. Expired entries are dropped
+/// lazily on access and eagerly by `spawn_sweeper`; when a shard exceeds its share of the
+/// budget the least recently used entries are evicted.
+pub struct BoundedCache<V> {
+    name: &'static str,
+    shards: Vec<Mutex<Shard<V>>>,
+    max_bytes: AtomicUsize,
+    generation: AtomicU64, // Bumped by every purge, see `insert_since`
+    sweeper_running: AtomicBool,
+    pub metrics: CacheMetrics,
+}
+
+impl<V: Clone + Weigh + Send + 'static> BoundedCache<V> {
+    pub fn new(name: &'static str, max_bytes: usize) -> Self {
+        Self {
+            name,
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::new())).collect(),
+            max_bytes: AtomicUsize::new(max_bytes),
+            generation: AtomicU64::new(0),
+            sweeper_running: AtomicBool::new(false),
+            metrics: CacheMetrics::default(),
+        }
+    }
+
+    pub fn set_max_bytes(&self, max_bytes: usize) {
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            self.evict_to_budget(&mut shard);
+        }
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<Shard<V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    fn shard_budget(&self) -> usize {
+        self.max_bytes.load(Ordering::Relaxed) / SHARDS
+    }
+
+    fn evict_to_budget(&self, shard: &mut Shard<V>) {
+        let budget = self.shard_budget();
+        while shard.bytes > budget {
+            let oldest = match shard.lru.iter().next() {
+                Some((_, key)) => key.clone(),
+                None => break,
+            };
+            shard.remove(&oldest);
+            self.metrics.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let expired = match shard.map.get(key) {
+            Some(entry) => entry.expires_at <= Instant::now(),
+            None => {
+                self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        if expired {
+            shard.remove(key);
+            self.metrics.expirations.fetch_add(1, Ordering::Relaxed);
+            self.metrics.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        shard.touch(key);
+        self.metrics.hits.fetch_add(1, Ordering::Relaxed);
+        shard.map.get(key).map(|entry| entry.value.clone())
+    }
+
+    pub fn insert(&self, key: String, value: V, ttl: Duration) {
+        self.upsert(&key, |_| (value, Instant::now() + ttl, ()));
+    }
+
+    /// Atomically replaces the entry for `key`. `f` receives the current live value (None
+    /// if absent or expired) and returns the new value, its deadline and a result.
+    pub fn upsert<R>(&self, key: &str, f: impl FnOnce(Option<V>) -> (V, Instant, R)) -> R {
+        let mut shard = self.shard(key).lock().unwrap();
+        self.upsert_locked(&mut shard, key, f)
+    }
+
+    /// The current purge generation. Take it before producing a value, then store it
+    /// with `insert_since`.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Starts a new purge generation: values produced before now can no longer be
+    /// stored with `insert_since`. `retain` (and so every purge) does this itself.
+    pub fn fence(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Inserts unless a purge started after `generation` was taken, so a value computed
+    /// from data that has since been purged is not put back. Returns whether it was stored.
+    pub fn insert_since(&self, generation: u64, key: String, value: V, ttl: Duration) -> bool {
+        let mut shard = self.shard(&key).lock().unwrap();
+        // Checked under the shard lock: a purge that bumped the generation after this
+        // point still has to take the lock, and then removes what is inserted here
+        if self.generation() != generation {
+            return false;
+        }
+        self.upsert_locked(&mut shard, &key, |_| (value, Instant::now() + ttl, ()));
+        true
+    }
+
+    fn upsert_locked<R>(&self, shard: &mut Shard<V>, key: &str, f: impl FnOnce(Option<V>) -> (V, Instant, R)) -> R {
+        let current = match shard.remove(key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.value),
+            Some(_) => {
+                self.metrics.expirations.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => None,
+        };
+
+        let (value, expires_at, result) = f(current);
+        let size = key.len() + value.weight() + ENTRY_OVERHEAD;
+        if size > self.shard_budget() {
+            // Would evict everything else and still not fit
+            self.metrics.evictions.fetch_add(1, Ordering::Relaxed);
+            return result;
+        }
+
+        let tick = shard.next_tick();
+        shard.bytes += size;
+        shard.lru.insert(tick, key.to_string());
+        shard.map.insert(key.to_string(), Entry { value, expires_at, size, tick });
+        self.evict_to_budget(shard);
+        result
+    }
+
+    pub fn remove(&self, key: &str) -> bool {
+        self.shard(key).lock().unwrap().remove(key).is_some()
+    }
+
+    /// Removes every entry for which `keep` returns false. Returns how many were removed.
+    pub fn retain(&self, mut keep: impl FnMut(&str, &V) -> bool) -> usize {
+        self.fence();
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            let doomed: Vec<String> = shard.map.iter()
+                .filter(|(key, entry)| !keep(key, &entry.value))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in doomed {
+                shard.remove(&key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Drops expired entries. Returns how many were removed.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            let expired: Vec<String> = shard.map.iter()
+                .filter(|(_, entry)| entry.expires_at <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                shard.remove(&key);
+                removed += 1;
+            }
+        }
+        self.metrics.expirations.fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
+
+    /// Starts a background task that sweeps expired entries every `interval`.
+    /// Only the first call has an effect; the task ends once the cache is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>, interval: Duration) {
+        if self.sweeper_running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let weak = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match weak.upgrade() {
+                    Some(cache) => {
+                        cache.sweep();
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().map.len()).sum()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().bytes).sum()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name,
+            hits: self.metrics.hits.load(Ordering::Relaxed),
+            misses: self.metrics.misses.load(Ordering::Relaxed),
+            evictions: self.metrics.evictions.load(Ordering::Relaxed),
+            expirations: self.metrics.expirations.load(Ordering::Relaxed),
+            entries: self.len() as u64,
+            bytes: self.bytes() as u64,
+            max_bytes: self.max_bytes.load(Ordering::Relaxed) as u64,
+        }
+    }
+}
+
+impl<V: Clone + Weigh + Purgeable + Send + 'static> BoundedCache<V> {
+    /// Removes entries whose subject matches `pattern` (see `glob_match`).
+    pub fn purge(&self, pattern: &str) -> usize {
+        self.retain(|_, value| !glob_match(pattern, value.subject()))
+    }
+
+    /// Removes entries carrying `tag`.
+    pub fn purge_tag(&self, tag: &str) -> usize {
+        self.retain(|_, value| !value.tags().iter().any(|t| t == tag))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub entries: u64,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+// (name, type, help, value)
+type MetricFamily = (&'static str, &'static str, &'static str, fn(&CacheStats) -> u64);
+
+/// Renders the stats of several caches in Prometheus text format, one family at a time.
+pub fn render_metrics(caches: &[CacheStats]) -> String {
+    let families: [MetricFamily; 7] = [
+        ("qhttpx_cache_hits_total", "counter", "Cache lookups that found a live entry", |c| c.hits),
+        ("qhttpx_cache_misses_total", "counter", "Cache lookups that found nothing or an expired entry", |c| c.misses),
+        ("qhttpx_cache_evictions_total", "counter", "Entries dropped to stay within the byte budget", |c| c.evictions),
+        ("qhttpx_cache_expirations_total", "counter", "Entries dropped after their TTL", |c| c.expirations),
+        ("qhttpx_cache_entries", "gauge", "Current number of entries", |c| c.entries),
+        ("qhttpx_cache_bytes", "gauge", "Approximate bytes held", |c| c.bytes),
+        ("qhttpx_cache_max_bytes", "gauge", "Configured byte budget", |c| c.max_bytes),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in families {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+        for cache in caches {
+            out.push_str(&format!("{}{{cache=\"{}\"}} {}\n", name, cache.name, value(cache)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_varies_by_caller() {
+        let headers = HeaderMap::new();
+        let policy = CacheKeyPolicy::new(None, None, None, None, true);
+        assert_eq!(policy.key("GET", "/me", None, &headers, Some("42"), Some("k1")), "GET /me|sub=42");
+        assert_eq!(policy.key("GET", "/me", None, &headers, None, Some("k1")), "GET /me|key=k1");
+        assert_eq!(policy.key("GET", "/me", None, &headers, None, None), "GET /me|sub=");
+        let shared = CacheKeyPolicy::new(None, None, None, None, false);
+        assert_eq!(shared.key("GET", "/me", None, &headers, Some("42"), None), "GET /me");
+    }
+
+    #[test]
+    fn key_normalises_query_and_vary_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("accept-language", "fr".parse().unwrap());
+        let all = CacheKeyPolicy::new(None, None, None, Some(vec!["Accept-Language".to_string()]), false);
+        assert_eq!(all.key("GET", "/s", Some("b=2&a=1&"), &headers, None, None), "GET /s?a=1&b=2|accept-language=fr");
+        let only = CacheKeyPolicy::new(None, Some(vec!["q".to_string()]), None, None, false);
+        assert_eq!(only.key("GET", "/s", Some("utm_source=x&q=rust"), &headers, None, None), "GET /s?q=rust");
+        let except = CacheKeyPolicy::new(None, None, Some(vec!["utm_source".to_string()]), None, false);
+        assert_eq!(except.key("GET", "/s", Some("utm_source=x&q=rust"), &headers, None, None), "GET /s?q=rust");
+        let ignore = CacheKeyPolicy::new(Some("none".to_string()), None, None, None, false);
+        assert_eq!(ignore.key("GET", "/s", Some("q=rust"), &headers, None, None), "GET /s");
+    }
+
+    #[test]
+    fn storable_unless_private_or_no_store() {
+        let with = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CACHE_CONTROL, value.parse().unwrap());
+            is_storable(&headers)
+        };
+        assert!(is_storable(&HeaderMap::new()));
+        assert!(with("public, max-age=60"));
+        assert!(!with("max-age=60, No-Store"));
+        assert!(!with("private"));
+        assert!(!with("private=\"set-cookie\""));
+    }
+
+    fn response(path: &str, tags: &'static str, age: Duration, lifetime: CacheLifetime) -> Arc<CachedResponse> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_TAG, tags.parse().unwrap());
+        headers.insert(header::SET_COOKIE, "a=b".parse().unwrap());
+        let response = CachedResponse::new(path.to_string(), StatusCode::OK, &headers, Bytes::from_static(b"body"), lifetime);
+        Arc::new(CachedResponse { stored_at: Instant::now() - age, ..response })
+    }
+
+    #[test]
+    fn freshness_windows() {
+        let lifetime = CacheLifetime::new(10, Some(5), Some(20));
+        assert_eq!(lifetime.retention(), Duration::from_secs(30));
+        assert_eq!(lifetime.cache_control(), "public, max-age=10, stale-while-revalidate=5, stale-if-error=20");
+        let at = |secs| response("/", "", Duration::from_secs(secs), lifetime).freshness();
+        assert_eq!(at(5), Freshness::Fresh);
+        assert_eq!(at(12), Freshness::Revalidate);
+        assert_eq!(at(20), Freshness::StaleIfError);
+        assert_eq!(at(40), Freshness::Expired);
+
+        let cached = response("/", "post-1, author-7 post-2", Duration::ZERO, lifetime);
+        assert_eq!(cached.tags, ["post-1", "author-7", "post-2"]);
+        assert!(!cached.headers.contains_key(header::SET_COOKIE) && !cached.headers.contains_key(CACHE_TAG));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("/blog/1", "/blog/1"));
+        assert!(!glob_match("/blog/1", "/blog/10"));
+        assert!(glob_match("/blog/*", "/blog/1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("/a/*/c/*", "/a/b/c/d"));
+        assert!(!glob_match("/a/*/c/*", "/a/b/d"));
+        assert!(glob_match("*.example.com", "app.example.com"));
+        // Prefix and suffix may not overlap
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    /// Keys that land in the same shard as `first`, so LRU order between them is observable.
+    fn same_shard(cache: &BoundedCache<String>, count: usize) -> Vec<String> {
+        let first = cache.shard("k0") as *const _;
+        (0..).map(|i| format!("k{}", i))
+            .filter(|key| std::ptr::eq(cache.shard(key), first))
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_within_byte_budget() {
+        // 1000 bytes per shard: two 300-byte values fit, a third does not
+        let cache = BoundedCache::new("test", 1000 * SHARDS);
+        let keys = same_shard(&cache, 3);
+        let value = "x".repeat(300);
+        cache.insert(keys[0].clone(), value.clone(), Duration::from_secs(60));
+        cache.insert(keys[1].clone(), value.clone(), Duration::from_secs(60));
+        assert!(cache.get(&keys[0]).is_some());
+        cache.insert(keys[2].clone(), value.clone(), Duration::from_secs(60));
+
+        assert!(cache.get(&keys[1]).is_none());
+        assert!(cache.get(&keys[0]).is_some() && cache.get(&keys[2]).is_some());
+        assert!(cache.bytes() <= 1000);
+
+        // Larger than a whole shard: refused rather than flushing everything else
+        cache.insert(keys[1].clone(), "x".repeat(2000), Duration::from_secs(60));
+        assert_eq!(cache.len(), 2);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions, stats.entries), (3, 1, 2, 2));
+        assert_eq!(stats.max_bytes, 16000);
+
+        cache.set_max_bytes(500 * SHARDS);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn expired_entries_are_missed_and_swept() {
+        let cache = BoundedCache::new("test", 1 << 20);
+        cache.insert("gone".to_string(), "a".to_string(), Duration::ZERO);
+        cache.insert("old".to_string(), "b".to_string(), Duration::ZERO);
+        cache.insert("live".to_string(), "c".to_string(), Duration::from_secs(60));
+        assert_eq!(cache.get("gone"), None);
+        assert_eq!(cache.sweep(), 1);
+        assert_eq!(cache.len(), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.expirations, stats.misses), (2, 1));
+
+        // upsert sees no current value for an expired entry
+        cache.insert("old".to_string(), "b".to_string(), Duration::ZERO);
+        let seen = cache.upsert("old", |current| (String::new(), Instant::now() + Duration::from_secs(1), current));
+        assert_eq!(seen, None);
+    }
+
+    #[test]
+    fn purges_by_pattern_and_tag() {
+        let cache = BoundedCache::new("test", 1 << 20);
+        let lifetime = CacheLifetime::new(60, None, None);
+        cache.insert("a".to_string(), response("/blog/1", "post-1", Duration::ZERO, lifetime), lifetime.ttl);
+        cache.insert("b".to_string(), response("/blog/2", "post-2 author-7", Duration::ZERO, lifetime), lifetime.ttl);
+        cache.insert("c".to_string(), response("/about", "author-7", Duration::ZERO, lifetime), lifetime.ttl);
+
+        assert_eq!(cache.purge_tag("author-7"), 2);
+        assert_eq!(cache.purge("/blog/*"), 1);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.bytes(), 0);
+    }
+
+    #[test]
+    fn purge_fences_off_earlier_writers() {
+        let cache = BoundedCache::new("test", 1 << 20);
+        let lifetime = CacheLifetime::new(60, None, None);
+        let before = cache.generation();
+        assert!(cache.insert_since(before, "a".to_string(), response("/blog/1", "", Duration::ZERO, lifetime), lifetime.ttl));
+
+        // A handler that started before the purge finishes after it
+        let started = cache.generation();
+        assert_eq!(cache.purge("/blog/*"), 1);
+        assert!(!cache.insert_since(started, "a".to_string(), response("/blog/1", "", Duration::ZERO, lifetime), lifetime.ttl));
+        assert_eq!(cache.len(), 0);
+        assert!(cache.insert_since(cache.generation(), "a".to_string(), response("/blog/1", "", Duration::ZERO, lifetime), lifetime.ttl));
+    }
+
+    #[tokio::test]
+    async fn single_flight_hands_off_to_followers() {
+        let flights = Arc::new(SingleFlight::default());
+        let lease = match flights.join("k") {
+            Flight::Leader(lease) => lease,
+            Flight::Follower(_) => panic!("first caller must lead"),
+        };
+        assert!(flights.try_lead("k").is_none());
+        assert!(flights.try_lead("other").is_some());
+        let wait = match flights.join("k") {
+            Flight::Follower(wait) => wait,
+            Flight::Leader(_) => panic!("second caller must follow"),
+        };
+        let follower = tokio::spawn(wait.wait());
+        tokio::task::yield_now().await;
+        assert!(!follower.is_finished());
+
+        drop(lease);
+        tokio::time::timeout(Duration::from_secs(1), follower).await.unwrap().unwrap();
+        assert!(flights.try_lead("k").is_some());
+    }
+}
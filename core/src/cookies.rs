@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hyper::header::{HeaderMap, HeaderValue, COOKIE};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Request cookies by name. When a name repeats, the first one wins: browsers send the
+/// most specific path first (RFC 6265 §5.4).
+pub fn parse(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for pair in headers.get_all(COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+    {
+        if let Some((name, value)) = pair.trim().split_once('=') {
+            let name = name.trim();
+            if !name.is_empty() {
+                cookies.entry(name.to_string()).or_insert_with(|| value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    cookies
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(format!("SameSite must be Strict, Lax or None, not \"{}\"", s)),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Everything in a `Set-Cookie` besides the name, value and lifetime.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CookieAttributes {
+    pub path: String,
+    pub domain: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+}
+
+impl Default for CookieAttributes {
+    fn default() -> Self {
+        Self { path: "/".to_string(), domain: None, secure: true, http_only: true, same_site: SameSite::Lax }
+    }
+}
+
+impl CookieAttributes {
+    /// `max_age` None makes a browser-session cookie; zero deletes it.
+    pub fn set_cookie(&self, name: &str, value: &str, max_age: Option<Duration>) -> Result<HeaderValue, String> {
+        let valid_name = !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b));
+        if !valid_name {
+            return Err(format!("Invalid cookie name: {}", name));
+        }
+        if value.bytes().any(|b| !b.is_ascii_graphic() || matches!(b, b'"' | b',' | b';' | b'\\')) {
+            return Err(format!("Invalid value for cookie {}", name));
+        }
+        let mut cookie = format!("{}={}; Path={}", name, value, self.path);
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        if let Some(max_age) = max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        // Browsers drop SameSite=None cookies that are not Secure
+        if self.secure || self.same_site == SameSite::None {
+            cookie.push_str("; Secure");
+        }
+        if self.http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        cookie.push_str("; SameSite=");
+        cookie.push_str(self.same_site.as_str());
+        HeaderValue::from_str(&cookie).map_err(|e| e.to_string())
+    }
+}
+
+struct CookieKey {
+    sign: hmac::Key,
+    seal: LessSafeKey,
+}
+
+impl CookieKey {
+    /// Separate keys for signing and encryption, both derived from one secret.
+    fn derive(secret: &[u8]) -> Self {
+        let master = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let sign = hmac::sign(&master, b"cookie signing");
+        let seal = hmac::sign(&master, b"cookie encryption");
+        Self {
+            sign: hmac::Key::new(hmac::HMAC_SHA256, sign.as_ref()),
+            seal: LessSafeKey::new(UnboundKey::new(&aead::AES_256_GCM, seal.as_ref()).expect("32-byte key")),
+        }
+    }
+}
+
+/// Keys for signed and encrypted cookies. The first key signs and encrypts; every key
+/// is tried when reading, so a new key can be put in front while cookies issued under
+/// the old ones stay valid until they expire.
+pub struct CookieKeys {
+    keys: Vec<CookieKey>,
+}
+
+impl CookieKeys {
+    pub fn new(secrets: &[Vec<u8>]) -> Result<Self, String> {
+        if secrets.is_empty() {
+            return Err("At least one cookie secret is required".to_string());
+        }
+        if secrets.iter().any(|s| s.len() < 32) {
+            return Err("Cookie secrets must be at least 32 bytes".to_string());
+        }
+        Ok(Self { keys: secrets.iter().map(|s| CookieKey::derive(s)).collect() })
+    }
+
+    /// `value.signature`. The name is signed too, so a value cannot be moved to another cookie.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let tag = hmac::sign(&self.keys[0].sign, format!("{}={}", name, value).as_bytes());
+        format!("{}.{}", value, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    pub fn unsign(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let message = format!("{}={}", name, value);
+        self.keys.iter()
+            .any(|key| hmac::verify(&key.sign, message.as_bytes(), &signature).is_ok())
+            .then(|| value.to_string())
+    }
+
+    /// AES-256-GCM under a random nonce, bound to the cookie name; base64url of nonce and ciphertext.
+    pub fn encrypt(&self, name: &str, value: &str) -> Option<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).ok()?;
+        let mut sealed = value.as_bytes().to_vec();
+        self.keys[0].seal
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(name.as_bytes()), &mut sealed)
+            .ok()?;
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&sealed);
+        Some(URL_SAFE_NO_PAD.encode(out))
+    }
+
+    pub fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let bytes = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if bytes.len() < NONCE_LEN + aead::AES_256_GCM.tag_len() {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.keys.iter().find_map(|key| {
+            let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+            let mut buffer = ciphertext.to_vec();
+            let plain = key.seal.open_in_place(nonce, Aad::from(name.as_bytes()), &mut buffer).ok()?;
+            String::from_utf8(plain.to_vec()).ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_serializes() {
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, "a=1; b=\"two\"; a=shadowed".parse().unwrap());
+        headers.append(COOKIE, "c=3".parse().unwrap());
+        let cookies = parse(&headers);
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["b"], "two");
+        assert_eq!(cookies["c"], "3");
+
+        let attrs = CookieAttributes { same_site: SameSite::Strict, ..CookieAttributes::default() };
+        assert_eq!(
+            attrs.set_cookie("sid", "abc", Some(Duration::from_secs(60))).unwrap(),
+            "sid=abc; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=Strict",
+        );
+        assert!(attrs.set_cookie("bad name", "x", None).is_err());
+        assert!(attrs.set_cookie("ok", "a;b", None).is_err());
+    }
+
+    #[test]
+    fn signs_and_encrypts_across_key_rotation() {
+        let old = CookieKeys::new(&[vec![1u8; 32]]).unwrap();
+        let rotated = CookieKeys::new(&[vec![2u8; 32], vec![1u8; 32]]).unwrap();
+        let fresh = CookieKeys::new(&[vec![2u8; 32]]).unwrap();
+        assert!(CookieKeys::new(&[b"short".to_vec()]).is_err());
+
+        let signed = old.sign("theme", "dark");
+        assert_eq!(rotated.unsign("theme", &signed).as_deref(), Some("dark"));
+        assert_eq!(rotated.unsign("other", &signed), None);
+        assert_eq!(fresh.unsign("theme", &signed), None);
+        assert_eq!(old.unsign("theme", &signed.replace("dark", "light")), None);
+
+        let sealed = old.encrypt("cart", "{\"items\":3}").unwrap();
+        assert!(!sealed.contains("items"));
+        assert_eq!(rotated.decrypt("cart", &sealed).as_deref(), Some("{\"items\":3}"));
+        assert_eq!(rotated.decrypt("wishlist", &sealed), None);
+        assert_eq!(fresh.decrypt("cart", &sealed), None);
+        assert_eq!(rotated.decrypt("cart", &rotated.encrypt("cart", "x").unwrap()).as_deref(), Some("x"));
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use crate::cache::glob_match;
+
+/// A CORS policy. Origins are exact (`https://app.example.com`) or patterns with `*`
+/// (`https://*.example.com`); a lone `*` allows any origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsPolicy {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>, // "*" allows whatever the preflight asks for
+    pub allow_headers: Vec<String>, // "*" allows whatever the preflight asks for
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u64>, // seconds browsers may cache a preflight
+    pub private_network: bool, // Answer Private Network Access preflights
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            origins: vec!["*".to_string()],
+            methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"].map(String::from).to_vec(),
+            allow_headers: vec!["Content-Type".to_string(), "Authorization".to_string()],
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: Some(86400),
+            private_network: false,
+        }
+    }
+}
+
+impl CorsPolicy {
+    /// Browsers refuse `*` together with credentials, so it is refused here instead.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.credentials && self.any_origin() {
+            return Err("CORS credentials cannot be combined with origin \"*\"; list the allowed origins".to_string());
+        }
+        for name in self.methods.iter().chain(&self.allow_headers).chain(&self.expose_headers) {
+            if name != "*" && header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("Invalid CORS method or header name: {}", name));
+            }
+        }
+        for origin in &self.origins {
+            HeaderValue::from_str(origin).map_err(|_| format!("Invalid CORS origin: {}", origin))?;
+        }
+        Ok(())
+    }
+
+    fn any_origin(&self) -> bool {
+        self.origins.iter().any(|o| o == "*")
+    }
+
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| {
+            allowed == "*" || allowed.eq_ignore_ascii_case(origin) || (allowed.contains('*') && glob_match(&allowed.to_ascii_lowercase(), &origin.to_ascii_lowercase()))
+        })
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m == "*" || m.eq_ignore_ascii_case(method))
+    }
+
+    fn allows_header(&self, name: &str) -> bool {
+        self.allow_headers.iter().any(|h| h == "*" || h.eq_ignore_ascii_case(name))
+    }
+
+    /// `Access-Control-Allow-Origin` for an allowed origin: `*` only when nothing is
+    /// tied to the origin, otherwise the origin itself.
+    fn allow_origin(&self, origin: &HeaderValue) -> HeaderValue {
+        if self.any_origin() && !self.credentials {
+            HeaderValue::from_static("*")
+        } else {
+            origin.clone()
+        }
+    }
+
+    /// Answers a preflight from its `Access-Control-Request-*` headers, or says why it is refused.
+    pub fn preflight(&self, origin: &HeaderValue, request: &HeaderMap) -> Result<HeaderMap, String> {
+        let origin_str = origin.to_str().map_err(|_| "Invalid Origin".to_string())?;
+        if !self.allows_origin(origin_str) {
+            return Err(format!("Origin {} is not allowed", origin_str));
+        }
+        let method = request.get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .unwrap_or_default();
+        if !self.allows_method(method) {
+            return Err(format!("Method {} is not allowed", method));
+        }
+        let requested: Vec<&str> = request.get_all(header::ACCESS_CONTROL_REQUEST_HEADERS).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .collect();
+        if let Some(header) = requested.iter().find(|h| !self.allows_header(h)) {
+            return Err(format!("Header {} is not allowed", header));
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, self.allow_origin(origin));
+        if self.credentials {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+        // Wildcards are answered with what was asked for, which also works with credentials
+        if let Ok(value) = HeaderValue::from_str(method) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, value);
+        }
+        if !requested.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&requested.join(", ")) {
+                headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, value);
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+        let private_network = request.get("access-control-request-private-network").is_some_and(|v| v == "true");
+        if private_network && self.private_network {
+            headers.insert("access-control-allow-private-network", HeaderValue::from_static("true"));
+        }
+        headers.insert(header::VARY, HeaderValue::from_static("Origin, Access-Control-Request-Method, Access-Control-Request-Headers"));
+        Ok(headers)
+    }
+}
+
+/// The CORS policy one request ends up with: the global one, or the matched route's.
+#[derive(Default)]
+pub struct RequestCors {
+    policy: Option<Arc<CorsPolicy>>,
+    origin: Option<HeaderValue>,
+}
+
+impl RequestCors {
+    pub fn new(global: Option<Arc<CorsPolicy>>, request: &HeaderMap) -> Self {
+        Self { policy: global, origin: request.get(header::ORIGIN).cloned() }
+    }
+
+    pub fn route(&mut self, route: Option<&Arc<CorsPolicy>>) {
+        if let Some(route) = route {
+            self.policy = Some(route.clone());
+        }
+    }
+
+    /// A CORS preflight: OPTIONS with an Origin and `Access-Control-Request-Method`.
+    pub fn is_preflight(method: &hyper::Method, request: &HeaderMap) -> bool {
+        method == hyper::Method::OPTIONS
+            && request.contains_key(header::ORIGIN)
+            && request.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// None when no policy applies. A refused preflight gets no CORS headers at all.
+    pub fn preflight(&mut self, request: &HeaderMap) -> Option<Result<HeaderMap, String>> {
+        let policy = self.policy.clone()?;
+        let origin = self.origin.as_ref()?;
+        let result = policy.preflight(origin, request);
+        // Answered in full here; nothing more to add to the response
+        self.policy = None;
+        Some(result)
+    }
+
+    /// Adds the headers for an actual (non-preflight) response. A handler that set
+    /// `Access-Control-Allow-Origin` itself is left alone.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let Some(policy) = &self.policy else {
+            return;
+        };
+        if headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN) {
+            return;
+        }
+        // The answer depends on Origin unless every origin gets "*"
+        if policy.credentials || !policy.any_origin() {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        let Some(origin) = self.origin.as_ref().filter(|o| o.to_str().is_ok_and(|o| policy.allows_origin(o))) else {
+            return;
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, policy.allow_origin(origin));
+        if policy.credentials {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+        if !policy.expose_headers.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&policy.expose_headers.join(", ")) {
+                headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn echoes_allowed_origins_and_refuses_the_rest() {
+        let policy = Arc::new(CorsPolicy {
+            origins: vec!["https://app.example.com".to_string(), "https://*.preview.example.com".to_string()],
+            expose_headers: vec!["X-Request-Id".to_string()],
+            credentials: true,
+            ..CorsPolicy::default()
+        });
+        assert!(policy.validate().is_ok());
+        assert!(CorsPolicy { credentials: true, ..CorsPolicy::default() }.validate().is_err());
+
+        let mut headers = HeaderMap::new();
+        RequestCors::new(Some(policy.clone()), &request(&[("origin", "https://pr-7.preview.example.com")])).apply(&mut headers);
+        assert_eq!(headers["access-control-allow-origin"], "https://pr-7.preview.example.com");
+        assert_eq!(headers["access-control-allow-credentials"], "true");
+        assert_eq!(headers["access-control-expose-headers"], "X-Request-Id");
+        assert_eq!(headers["vary"], "Origin");
+
+        let mut headers = HeaderMap::new();
+        RequestCors::new(Some(policy), &request(&[("origin", "https://evil.com")])).apply(&mut headers);
+        assert!(!headers.contains_key("access-control-allow-origin"));
+        assert_eq!(headers["vary"], "Origin");
+
+        let mut headers = HeaderMap::new();
+        RequestCors::new(Some(Arc::new(CorsPolicy::default())), &request(&[("origin", "https://a.com")])).apply(&mut headers);
+        assert_eq!(headers["access-control-allow-origin"], "*");
+        assert!(!headers.contains_key("vary"));
+    }
+
+    #[test]
+    fn preflight_checks_method_headers_and_private_network() {
+        let policy = Arc::new(CorsPolicy {
+            origins: vec!["https://app.example.com".to_string()],
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            allow_headers: vec!["content-type".to_string(), "x-api-key".to_string()],
+            private_network: true,
+            max_age: Some(600),
+            ..CorsPolicy::default()
+        });
+        let ok = request(&[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "POST"),
+            ("access-control-request-headers", "Content-Type, X-Api-Key"),
+            ("access-control-request-private-network", "true"),
+        ]);
+        assert!(RequestCors::is_preflight(&hyper::Method::OPTIONS, &ok));
+        let mut cors = RequestCors::new(None, &ok);
+        cors.route(Some(&policy));
+        let headers = cors.preflight(&ok).unwrap().unwrap();
+        assert_eq!(headers["access-control-allow-origin"], "https://app.example.com");
+        assert_eq!(headers["access-control-allow-methods"], "POST");
+        assert_eq!(headers["access-control-allow-headers"], "Content-Type, X-Api-Key");
+        assert_eq!(headers["access-control-max-age"], "600");
+        assert_eq!(headers["access-control-allow-private-network"], "true");
+
+        let mut after = HeaderMap::new();
+        cors.apply(&mut after);
+        assert!(after.is_empty());
+
+        let bad_method = request(&[("origin", "https://app.example.com"), ("access-control-request-method", "DELETE")]);
+        assert!(RequestCors::new(Some(policy.clone()), &bad_method).preflight(&bad_method).unwrap().is_err());
+        let bad_header = request(&[("origin", "https://app.example.com"), ("access-control-request-method", "GET"), ("access-control-request-headers", "x-other")]);
+        assert!(RequestCors::new(Some(policy.clone()), &bad_header).preflight(&bad_header).unwrap().is_err());
+        let bad_origin = request(&[("origin", "https://evil.com"), ("access-control-request-method", "GET")]);
+        assert!(RequestCors::new(Some(policy), &bad_origin).preflight(&bad_origin).unwrap().is_err());
+        assert!(RequestCors::new(None, &bad_origin).preflight(&bad_origin).is_none());
+    }
+}
@@ -0,0 +1,292 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE, HOST, ORIGIN, REFERER};
+use hyper::Method;
+use percent_encoding::percent_decode_str;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::cache::glob_match;
+use crate::cookies;
+
+/// Signed double-submit CSRF protection. The token lives in a cookie readable by the
+/// page, and unsafe requests must echo it in a header or form field.
+///
+/// Each token is signed together with a binding to whoever it was issued to (see
+/// `binding`). A sibling subdomain can still plant a cookie, but a token it obtained for
+/// its own session does not verify for the victim's. Requests with nothing to bind to
+/// share the empty binding and rely on the origin checks.
+pub struct CsrfPolicy {
+    key: hmac::Key,
+    pub cookie_name: String,
+    pub header_name: String,
+    pub form_field: String,
+    pub trusted_origins: Vec<String>, // Other origins allowed to post, exact or with "*"
+    pub secure: bool, // Secure cookie attribute
+}
+
+/// Why an unsafe request was refused; the message goes in the 403 body.
+#[derive(Debug, PartialEq)]
+pub enum CsrfError {
+    CrossSite,
+    UntrustedOrigin(String),
+    MissingToken,
+    InvalidToken,
+}
+
+impl std::fmt::Display for CsrfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsrfError::CrossSite => write!(f, "Cross-site request refused"),
+            CsrfError::UntrustedOrigin(origin) => write!(f, "Origin {} is not trusted", origin),
+            CsrfError::MissingToken => write!(f, "Missing CSRF token"),
+            CsrfError::InvalidToken => write!(f, "Invalid CSRF token"),
+        }
+    }
+}
+
+impl CsrfPolicy {
+    /// `secret` signs the tokens; without one a random key is used, which is fine for a
+    /// single process but invalidates tokens on restart.
+    pub fn new(secret: Option<&[u8]>) -> Result<Self, String> {
+        let key = match secret {
+            Some(secret) if secret.len() < 32 => return Err("CSRF secret must be at least 32 bytes".to_string()),
+            Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret),
+            None => hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).map_err(|_| "No randomness for the CSRF key".to_string())?,
+        };
+        Ok(Self {
+            key,
+            cookie_name: "csrf_token".to_string(),
+            header_name: "x-csrf-token".to_string(),
+            form_field: "_csrf".to_string(),
+            trusted_origins: Vec::new(),
+            secure: true,
+        })
+    }
+
+    pub fn is_safe(method: &Method) -> bool {
+        matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+    }
+
+    /// What a token is bound to: the session cookie, else the authenticated caller, else
+    /// nothing. A token issued before a session starts (or before login) stops verifying
+    /// once it does; the next safe request hands out a new one.
+    pub fn binding(session: Option<&str>, subject: Option<&str>, api_key: Option<&str>) -> String {
+        match (session, subject, api_key) {
+            (Some(session), _, _) => format!("sid:{}", session),
+            (None, Some(sub), _) => format!("sub:{}", sub),
+            (None, None, Some(id)) => format!("key:{}", id),
+            (None, None, None) => String::new(),
+        }
+    }
+
+    /// `random.signature`, both base64url; the signature covers `binding|random`.
+    pub fn issue(&self, binding: &str) -> Option<String> {
+        let mut random = [0u8; 18];
+        SystemRandom::new().fill(&mut random).ok()?;
+        let random = URL_SAFE_NO_PAD.encode(random);
+        let tag = hmac::sign(&self.key, format!("{}|{}", binding, random).as_bytes());
+        Some(format!("{}.{}", random, URL_SAFE_NO_PAD.encode(tag.as_ref())))
+    }
+
+    fn is_signed(&self, token: &str, binding: &str) -> bool {
+        let Some((random, signature)) = token.split_once('.') else {
+            return false;
+        };
+        URL_SAFE_NO_PAD.decode(signature)
+            .is_ok_and(|signature| hmac::verify(&self.key, format!("{}|{}", binding, random).as_bytes(), &signature).is_ok())
+    }
+
+    /// The request's valid token, if its cookie carries one issued under `binding`.
+    pub fn cookie_token(&self, headers: &HeaderMap, binding: &str) -> Option<String> {
+        cookies::parse(headers).remove(&self.cookie_name)
+            .filter(|token| self.is_signed(token, binding))
+    }
+
+    pub fn set_cookie(&self, token: &str) -> Option<HeaderValue> {
+        // Readable by scripts on purpose: the page has to copy it into the header
+        let secure = if self.secure { "; Secure" } else { "" };
+        HeaderValue::from_str(&format!("{}={}; Path=/; SameSite=Strict{}", self.cookie_name, token, secure)).ok()
+    }
+
+    /// Checks an unsafe request that arrived over `scheme` ("http" or "https"). `body` is
+    /// the form to search for the token field when the header is absent; None where the
+    /// body is not available.
+    pub fn verify(&self, headers: &HeaderMap, body: Option<&[u8]>, scheme: &str, binding: &str) -> Result<(), CsrfError> {
+        self.check_origin(headers, scheme)?;
+        let expected = self.cookie_token(headers, binding).ok_or(CsrfError::MissingToken)?;
+        let submitted = headers.get(self.header_name.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| body.filter(|_| is_form(headers)).and_then(|body| self.form_token(body)))
+            .ok_or(CsrfError::MissingToken)?;
+        if !constant_time_eq(expected.as_bytes(), submitted.trim().as_bytes()) {
+            return Err(CsrfError::InvalidToken);
+        }
+        Ok(())
+    }
+
+    /// Fetch metadata and Origin (or Referer) must point at this site, same scheme and
+    /// host, or a trusted origin. Requests without either, like old clients and curl, are
+    /// left to the token check.
+    fn check_origin(&self, headers: &HeaderMap, scheme: &str) -> Result<(), CsrfError> {
+        let origin = headers.get(ORIGIN).and_then(|v| v.to_str().ok())
+            .or_else(|| headers.get(REFERER).and_then(|v| v.to_str().ok()).map(origin_of));
+        let trusted = origin.is_some_and(|origin| self.trusted_origins.iter().any(|t| glob_match(&t.to_ascii_lowercase(), &origin.to_ascii_lowercase())));
+        if trusted {
+            return Ok(());
+        }
+        // Same-site is not enough: a compromised sibling subdomain is exactly the threat
+        let fetch_site = headers.get("sec-fetch-site").and_then(|v| v.to_str().ok());
+        if matches!(fetch_site, Some("cross-site" | "same-site")) {
+            return Err(CsrfError::CrossSite);
+        }
+        let Some(origin) = origin else {
+            return Ok(());
+        };
+        let host = headers.get(HOST).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        let same_origin = match origin.split_once("://") {
+            // An http page must not be able to post to the https site, or the reverse
+            Some((origin_scheme, origin_host)) => !host.is_empty()
+                && origin_scheme.eq_ignore_ascii_case(scheme)
+                && without_default_port(origin_host, scheme).eq_ignore_ascii_case(without_default_port(host, scheme)),
+            None => false,
+        };
+        if !same_origin {
+            return Err(CsrfError::UntrustedOrigin(origin.to_string()));
+        }
+        Ok(())
+    }
+
+    fn form_token(&self, body: &[u8]) -> Option<String> {
+        std::str::from_utf8(body).ok()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| percent_decode_str(key).decode_utf8().is_ok_and(|key| key == self.form_field.as_str()))
+            .and_then(|(_, value)| percent_decode_str(&value.replace('+', " ")).decode_utf8().ok().map(|v| v.into_owned()))
+    }
+}
+
+fn is_form(headers: &HeaderMap) -> bool {
+    headers.get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.trim_start().to_ascii_lowercase().starts_with("application/x-www-form-urlencoded"))
+}
+
+/// "https://a.com:8443/x?y" -> "https://a.com:8443".
+fn origin_of(url: &str) -> &str {
+    let start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    match url[start..].find('/') {
+        Some(end) => &url[..start + end],
+        None => url,
+    }
+}
+
+/// "a.com:443" -> "a.com" for https; browsers leave the default port out of Origin.
+fn without_default_port<'a>(host: &'a str, scheme: &str) -> &'a str {
+    let default = if scheme.eq_ignore_ascii_case("https") { ":443" } else { ":80" };
+    host.strip_suffix(default).unwrap_or(host)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn double_submit_with_signed_cookie() {
+        let policy = CsrfPolicy::new(Some(&[7u8; 32])).unwrap();
+        assert!(CsrfPolicy::new(Some(b"short")).is_err());
+        let token = policy.issue("").unwrap();
+        let cookie = format!("theme=dark; csrf_token={}", token);
+        assert_eq!(policy.set_cookie(&token).unwrap(), format!("csrf_token={}; Path=/; SameSite=Strict; Secure", token).as_str());
+
+        let ok = request(&[("host", "app.example.com"), ("origin", "https://app.example.com"), ("cookie", &cookie), ("x-csrf-token", &token)]);
+        assert_eq!(policy.verify(&ok, None, "https", ""), Ok(()));
+
+        let form = request(&[("host", "app.example.com"), ("cookie", &cookie), ("content-type", "application/x-www-form-urlencoded")]);
+        let body = format!("name=a+b&_csrf={}", token);
+        assert_eq!(policy.verify(&form, Some(body.as_bytes()), "https", ""), Ok(()));
+        assert_eq!(policy.verify(&form, None, "https", ""), Err(CsrfError::MissingToken));
+
+        // A cookie the server never signed is worthless, even when echoed back
+        let forged = request(&[("host", "app.example.com"), ("cookie", "csrf_token=abc.def"), ("x-csrf-token", "abc.def")]);
+        assert_eq!(policy.verify(&forged, None, "https", ""), Err(CsrfError::MissingToken));
+        let other = policy.issue("").unwrap();
+        let mismatch = request(&[("host", "app.example.com"), ("cookie", &cookie), ("x-csrf-token", &other)]);
+        assert_eq!(policy.verify(&mismatch, None, "https", ""), Err(CsrfError::InvalidToken));
+    }
+
+    #[test]
+    fn tokens_are_bound_to_their_session() {
+        let policy = CsrfPolicy::new(Some(&[7u8; 32])).unwrap();
+        let victim = CsrfPolicy::binding(Some("victim-sid"), Some("alice"), None);
+        assert_eq!(victim, "sid:victim-sid");
+        assert_eq!(CsrfPolicy::binding(None, Some("alice"), Some("k1")), "sub:alice");
+        assert_eq!(CsrfPolicy::binding(None, None, Some("k1")), "key:k1");
+
+        // A sibling subdomain plants a validly signed token from its own session
+        let planted = policy.issue(&CsrfPolicy::binding(Some("attacker-sid"), None, None)).unwrap();
+        let cookie = format!("sid=victim-sid; csrf_token={}", planted);
+        let attack = request(&[("host", "app.example.com"), ("cookie", &cookie), ("x-csrf-token", &planted)]);
+        assert_eq!(policy.verify(&attack, None, "https", &victim), Err(CsrfError::MissingToken));
+        assert_eq!(policy.cookie_token(&attack, &victim), None);
+
+        let own = policy.issue(&victim).unwrap();
+        let cookie = format!("sid=victim-sid; csrf_token={}", own);
+        let ok = request(&[("host", "app.example.com"), ("cookie", &cookie), ("x-csrf-token", &own)]);
+        assert_eq!(policy.verify(&ok, None, "https", &victim), Ok(()));
+        // After login the anonymous token is no longer accepted
+        assert_eq!(policy.verify(&ok, None, "https", ""), Err(CsrfError::MissingToken));
+    }
+
+    #[test]
+    fn origin_and_fetch_metadata() {
+        let mut policy = CsrfPolicy::new(None).unwrap();
+        policy.trusted_origins = vec!["https://*.partner.com".to_string()];
+        let token = policy.issue("").unwrap();
+        let cookie = format!("csrf_token={}", token);
+        let with = |extra: &[(&'static str, &str)]| {
+            let mut pairs = vec![("host", "app.example.com"), ("cookie", cookie.as_str()), ("x-csrf-token", token.as_str())];
+            pairs.extend_from_slice(extra);
+            policy.verify(&request(&pairs), None, "https", "")
+        };
+        assert_eq!(with(&[("sec-fetch-site", "cross-site")]), Err(CsrfError::CrossSite));
+        assert_eq!(with(&[("sec-fetch-site", "same-site"), ("origin", "https://admin.example.com")]), Err(CsrfError::CrossSite));
+        assert_eq!(with(&[("origin", "https://evil.com")]), Err(CsrfError::UntrustedOrigin("https://evil.com".to_string())));
+        assert_eq!(with(&[("referer", "https://evil.com/page")]), Err(CsrfError::UntrustedOrigin("https://evil.com".to_string())));
+        assert_eq!(with(&[("origin", "null")]), Err(CsrfError::UntrustedOrigin("null".to_string())));
+        assert_eq!(with(&[("sec-fetch-site", "cross-site"), ("origin", "https://app.partner.com")]), Ok(()));
+        assert_eq!(with(&[("sec-fetch-site", "same-origin"), ("origin", "https://app.example.com")]), Ok(()));
+        assert_eq!(with(&[]), Ok(()));
+        assert!(CsrfPolicy::is_safe(&Method::GET) && !CsrfPolicy::is_safe(&Method::POST));
+    }
+
+    #[test]
+    fn origin_must_match_scheme_and_host() {
+        let policy = CsrfPolicy::new(None).unwrap();
+        let token = policy.issue("").unwrap();
+        let cookie = format!("csrf_token={}", token);
+        let from = |origin: &str, host: &str, scheme: &str| {
+            let pairs = [("host", host), ("origin", origin), ("cookie", cookie.as_str()), ("x-csrf-token", token.as_str())];
+            policy.verify(&request(&pairs), None, scheme, "")
+        };
+        assert_eq!(from("https://app.example.com", "app.example.com", "https"), Ok(()));
+        assert_eq!(from("https://APP.example.com", "app.example.com:443", "https"), Ok(()));
+        assert_eq!(from("http://app.example.com:8080", "app.example.com:8080", "http"), Ok(()));
+        // Same host, other scheme: a page served over plain http cannot post to the https site
+        assert_eq!(from("http://app.example.com", "app.example.com", "https"), Err(CsrfError::UntrustedOrigin("http://app.example.com".to_string())));
+        assert_eq!(from("https://app.example.com", "app.example.com", "http"), Err(CsrfError::UntrustedOrigin("https://app.example.com".to_string())));
+        assert_eq!(from("https://app.example.com:8443", "app.example.com", "https"), Err(CsrfError::UntrustedOrigin("https://app.example.com:8443".to_string())));
+    }
+}
@@ -1,12 +1,13 @@
 
 use sqlx::{postgres::PgPoolOptions, sqlite::SqlitePoolOptions, Pool, Postgres, Sqlite, Row, Column, TypeInfo};
+use std::collections::HashMap;
 use std::sync::Arc;
 use redis::AsyncCommands;
 use napi::Result;
 use mongodb::{Client, options::ClientOptions};
 use futures_util::stream::TryStreamExt;
-use dashmap::DashMap;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use crate::cache::{BoundedCache, Purgeable, Weigh};
 use serde_json::{Map, Value};
 
 #[derive(Clone)]
@@ -16,12 +17,36 @@ pub enum DatabasePool {
     None,
 }
 
+/// A cached query result. Purges match patterns against the SQL text.
+#[derive(Debug)]
+pub struct CachedQuery {
+    pub sql: String,
+    pub result: String,
+    pub tags: Vec<String>,
+}
+
+impl Weigh for CachedQuery {
+    fn weight(&self) -> usize {
+        self.sql.len() + self.result.len() + self.tags.iter().map(|t| t.len()).sum::<usize>()
+    }
+}
+
+impl Purgeable for CachedQuery {
+    fn subject(&self) -> &str {
+        &self.sql
+    }
+
+    fn tags(&self) -> &[String] {
+        &self.tags
+    }
+}
+
 #[derive(Clone)]
 pub struct DatabaseManager {
     pool: DatabasePool,
     redis: Option<redis::Client>,
     mongo: Option<Client>,
-    query_cache: Arc<DashMap<String, (String, Instant)>>,
+    query_cache: Arc<BoundedCache<Arc<CachedQuery>>>,
 }
 
 impl DatabaseManager {
@@ -30,10 +55,14 @@ impl DatabaseManager {
             pool: DatabasePool::None,
             redis: None,
             mongo: None,
-            query_cache: Arc::new(DashMap::new()),
+            query_cache: Arc::new(BoundedCache::new("query", 32 * 1024 * 1024)),
         }
     }
 
+    pub fn query_cache(&self) -> Arc<BoundedCache<Arc<CachedQuery>>> {
+        self.query_cache.clone()
+    }
+
     pub async fn connect_mongo(&mut self, url: &str) -> Result<()> {
         let client_options = ClientOptions::parse(url).await
             .map_err(|e| napi::Error::from_reason(e.to_string()))?;
@@ -102,7 +131,7 @@ impl DatabaseManager {
                 .map_err(|e| napi::Error::from_reason(e.to_string()))?;
             
             if let Some(ttl) = ttl_sec {
-                let _: () = con.set_ex(key, value, ttl as u64).await
+                let _: () = con.set_ex(key, value, ttl).await
                     .map_err(|e| napi::Error::from_reason(e.to_string()))?;
             } else {
                 let _: () = con.set(key, value).await
@@ -127,6 +156,37 @@ impl DatabaseManager {
         }
     }
 
+    /// Every hash whose key starts with `prefix`, as (key without the prefix, fields). Used
+    /// for bulk loads such as API key sets, not on the request path.
+    pub async fn redis_hashes(&self, prefix: &str) -> Result<Vec<(String, HashMap<String, String>)>> {
+        let client = self.redis.as_ref().ok_or_else(|| napi::Error::from_reason("No Redis connected"))?;
+        let mut con = client.get_multiplexed_async_connection().await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+
+        let mut keys = Vec::new();
+        {
+            let pattern = format!("{}*", redis_glob_escape(prefix));
+            let mut iter = con.scan_match::<_, String>(pattern).await
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+            while let Some(key) = iter.next_item().await {
+                // SCAN patterns are globs; keep only keys that really start with the prefix
+                if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        let mut hashes = Vec::with_capacity(keys.len());
+        for key in keys {
+            let fields: HashMap<String, String> = con.hgetall(&key).await
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+            if let (Some(rest), false) = (key.strip_prefix(prefix), fields.is_empty()) {
+                hashes.push((rest.to_string(), fields));
+            }
+        }
+        Ok(hashes)
+    }
+
     // Legacy query method wrapper
     #[allow(dead_code)]
     pub async fn query(&self, sql: &str) -> Result<String> {
@@ -269,31 +329,54 @@ impl DatabaseManager {
         }
     }
 
-    pub async fn query_with_cache(&self, sql: &str, ttl_sec: Option<u32>) -> Result<String> {
-        self.query_with_params_and_cache(sql, vec![], ttl_sec).await
+    pub async fn query_with_cache(&self, sql: &str, ttl_sec: Option<u32>, tags: Vec<String>) -> Result<String> {
+        self.query_with_params_and_cache(sql, vec![], ttl_sec, tags).await
     }
 
-    pub async fn query_with_params_and_cache(&self, sql: &str, params: Vec<Value>, ttl_sec: Option<u32>) -> Result<String> {
+    /// `tags` are attached to the cached result so `query_cache_purge_tag` can drop it.
+    pub async fn query_with_params_and_cache(&self, sql: &str, params: Vec<Value>, ttl_sec: Option<u32>, tags: Vec<String>) -> Result<String> {
         let cache_key = if params.is_empty() {
             sql.to_string()
         } else {
              format!("{}|{:?}", sql, params)
         };
 
-        if let Some(ttl) = ttl_sec {
-            if let Some(entry) = self.query_cache.get(&cache_key) {
-                if entry.value().1.elapsed() < Duration::from_secs(ttl as u64) {
-                    return Ok(entry.value().0.clone());
-                }
+        if ttl_sec.is_some() {
+            if let Some(cached) = self.query_cache.get(&cache_key) {
+                return Ok(cached.result.clone());
             }
         }
 
         let result = self.query_with_params(sql, params).await?;
 
-        if let Some(_) = ttl_sec {
-             self.query_cache.insert(cache_key, (result.clone(), Instant::now()));
+        if let Some(ttl) = ttl_sec {
+             let cached = CachedQuery { sql: sql.to_string(), result: result.clone(), tags };
+             self.query_cache.insert(cache_key, Arc::new(cached), Duration::from_secs(ttl as u64));
         }
         
         Ok(result)
     }
 }
+
+/// Escapes the characters Redis treats as glob syntax, so `s` matches only itself.
+fn redis_glob_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_metacharacters_are_escaped() {
+        assert_eq!(redis_glob_escape("apikeys:"), "apikeys:");
+        assert_eq!(redis_glob_escape("keys[prod]*?\\"), "keys\\[prod\\]\\*\\?\\\\");
+    }
+}
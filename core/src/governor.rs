@@ -101,19 +101,9 @@ impl TrafficGovernor {
             rx
         };
 
-        // Wait for permit
-        if let Ok(_) = rx.await {
-            // We have been woken up, and inflight was already incremented by the waker
-            return;
-        } else {
-            // Sender dropped? Should not happen unless logic bug or shutdown
-            // If it happens, we treat it as admitted? No, if dropped, we were not admitted?
-            // Actually, if sender is dropped without sending, it means we were NOT woken up?
-            // But if we are here, we are awoken.
-            // oneshot::RecvError means the Sender was dropped.
-            // If sender is dropped, it means the Governor was dropped?
-            // Safe to return.
-        }
+        // Wait for permit. On success inflight was already incremented by the waker;
+        // a RecvError only means the sender was dropped (governor shut down), safe to return.
+        let _ = rx.await;
     }
 
     pub fn release(&self, latency_ms: u64, target_override: Option<u64>) {
@@ -142,7 +132,7 @@ impl TrafficGovernor {
                 // For now, simple additive
                  if inner.inflight > limit / 2 {
                     let new_limit = limit + 1;
-                    if new_limit % 10 == 0 {
+                    if new_limit.is_multiple_of(10) {
                         // info!("System healthy: increasing concurrency limit to {}", new_limit);
                     }
                     inner.current_limit = new_limit;
@@ -164,7 +154,7 @@ impl TrafficGovernor {
             if inner.inflight < self.max_concurrency {
                 if let Some(tx) = inner.waiters_critical.pop_front() {
                     inner.inflight += 1;
-                    if let Err(_) = tx.send(()) {
+                    if tx.send(()).is_err() {
                         inner.inflight -= 1; // Waker dropped
                         continue;
                     }
@@ -176,7 +166,7 @@ impl TrafficGovernor {
             if inner.inflight < inner.current_limit {
                 if let Some(tx) = inner.waiters_interactive.pop_front() {
                     inner.inflight += 1;
-                    if let Err(_) = tx.send(()) {
+                    if tx.send(()).is_err() {
                         inner.inflight -= 1;
                         continue;
                     }
@@ -186,7 +176,7 @@ impl TrafficGovernor {
                 // Background (only if interactive empty)
                  if let Some(tx) = inner.waiters_background.pop_front() {
                     inner.inflight += 1;
-                    if let Err(_) = tx.send(()) {
+                    if tx.send(()).is_err() {
                         inner.inflight -= 1;
                         continue;
                     }
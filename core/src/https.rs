@@ -0,0 +1,114 @@
+use hyper::header::{HeaderMap, HOST};
+use hyper::{StatusCode, Uri};
+
+/// `Strict-Transport-Security`, sent only on responses over TLS (RFC 6797 §7.2).
+#[derive(Clone, Debug, PartialEq)]
+pub struct HstsPolicy {
+    pub max_age: u64, // seconds
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+impl HstsPolicy {
+    /// Preload lists only accept a year or more with includeSubDomains, so anything
+    /// less is refused here instead of being silently ignored by browsers.
+    pub fn new(max_age: u64, include_subdomains: bool, preload: bool) -> Result<Self, String> {
+        if preload && (max_age < 31_536_000 || !include_subdomains) {
+            return Err("HSTS preload requires max_age of at least 31536000 and include_subdomains".to_string());
+        }
+        Ok(Self { max_age, include_subdomains, preload })
+    }
+
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// A plain-HTTP listener whose only job is sending clients to the HTTPS one.
+#[derive(Clone, Debug)]
+pub struct HttpsRedirect {
+    pub port: u16, // Where the redirect listener binds
+    pub https_port: u16, // Put in the Location unless 443
+    pub host: Option<String>, // Canonical host; default the request's Host
+    pub status: StatusCode, // 301 | 302 | 307 | 308 (default, keeps the method)
+}
+
+impl HttpsRedirect {
+    pub fn status_from(code: Option<u16>) -> Result<StatusCode, String> {
+        match code.unwrap_or(308) {
+            code @ (301 | 302 | 307 | 308) => Ok(StatusCode::from_u16(code).unwrap()),
+            code => Err(format!("HTTPS redirect status must be 301, 302, 307 or 308, not {}", code)),
+        }
+    }
+
+    /// The HTTPS URL for the request, or None when it names no usable host.
+    pub fn location(&self, headers: &HeaderMap, uri: &Uri) -> Option<String> {
+        let host = match &self.host {
+            Some(host) => host.clone(),
+            None => {
+                let authority = headers.get(HOST).and_then(|h| h.to_str().ok())
+                    .or_else(|| uri.authority().map(|a| a.as_str()))?;
+                strip_port(authority.trim())?.to_ascii_lowercase()
+            }
+        };
+        let valid = !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '[' | ']' | ':'));
+        if !valid {
+            return None;
+        }
+        let port = if self.https_port == 443 { String::new() } else { format!(":{}", self.https_port) };
+        let path = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        Some(format!("https://{}{}{}", host, port, path))
+    }
+}
+
+/// "example.com:80" -> "example.com", "[::1]:80" -> "[::1]".
+fn strip_port(authority: &str) -> Option<&str> {
+    if authority.starts_with('[') {
+        let end = authority.find(']')?;
+        return Some(&authority[..=end]);
+    }
+    Some(authority.split(':').next().unwrap_or(authority))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn hsts_header_and_preload_rules() {
+        assert_eq!(HstsPolicy::new(300, false, false).unwrap().header_value(), "max-age=300");
+        assert_eq!(
+            HstsPolicy::new(63_072_000, true, true).unwrap().header_value(),
+            "max-age=63072000; includeSubDomains; preload",
+        );
+        assert!(HstsPolicy::new(86_400, true, true).is_err());
+        assert!(HstsPolicy::new(63_072_000, false, true).is_err());
+    }
+
+    #[test]
+    fn redirect_location_keeps_path_and_query() {
+        let uri: Uri = "/login?next=%2Fhome".parse().unwrap();
+        let redirect = HttpsRedirect { port: 80, https_port: 443, host: None, status: StatusCode::PERMANENT_REDIRECT };
+        assert_eq!(redirect.location(&host("Example.com:80"), &uri).as_deref(), Some("https://example.com/login?next=%2Fhome"));
+        assert_eq!(redirect.location(&host("[::1]:8080"), &uri).as_deref(), Some("https://[::1]/login?next=%2Fhome"));
+        assert_eq!(redirect.location(&host("evil.com/@x"), &uri), None);
+        assert_eq!(redirect.location(&HeaderMap::new(), &uri), None);
+
+        let pinned = HttpsRedirect { https_port: 8443, host: Some("app.example.com".to_string()), ..redirect };
+        assert_eq!(pinned.location(&host("10.0.0.1"), &"/".parse().unwrap()).as_deref(), Some("https://app.example.com:8443/"));
+        assert!(HttpsRedirect::status_from(Some(303)).is_err());
+    }
+}
@@ -0,0 +1,525 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, PublicKeyUse};
+use hyper::header::{HeaderMap, AUTHORIZATION, COOKIE};
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+const HMAC: [Algorithm; 3] = [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+const RSA: [Algorithm; 6] = [
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+];
+const EC: [Algorithm; 2] = [Algorithm::ES256, Algorithm::ES384];
+const ED: [Algorithm; 1] = [Algorithm::EdDSA];
+// Required claims the jsonwebtoken crate checks itself; the rest are checked after decoding
+const SPEC_CLAIMS: [&str; 5] = ["exp", "nbf", "aud", "iss", "sub"];
+
+/// Where verification keys come from. File sources are re-read on every refresh.
+#[derive(Clone, Debug)]
+pub enum KeySource {
+    Pem { pem: String, kid: Option<String>, algorithm: Option<Algorithm> },
+    PemFile { path: String, kid: Option<String>, algorithm: Option<Algorithm> },
+    Jwks(String), // JWKS document handed over from JS
+    JwksFile(String),
+}
+
+/// Where a request may carry its token, tried in configured order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenSource {
+    Header(String), // "authorization" expects the Bearer scheme; other headers hold the bare token
+    Cookie(String),
+    Query(String), // WebSocket upgrades only: browsers cannot set headers there, and URLs end up in logs
+}
+
+impl TokenSource {
+    /// "header" | "header:<name>" | "cookie:<name>" | "query:<name>"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, name) = spec.split_once(':').map(|(k, n)| (k, Some(n.trim()))).unwrap_or((spec, None));
+        match (kind.trim().to_ascii_lowercase().as_str(), name) {
+            ("header", None) => Ok(TokenSource::Header("authorization".to_string())),
+            ("header", Some(name)) if !name.is_empty() => Ok(TokenSource::Header(name.to_ascii_lowercase())),
+            ("cookie", Some(name)) if !name.is_empty() => Ok(TokenSource::Cookie(name.to_string())),
+            ("query", Some(name)) if !name.is_empty() => Ok(TokenSource::Query(name.to_string())),
+            _ => Err(format!("Invalid JWT token source: {} (expected header, header:<name>, cookie:<name> or query:<name>)", spec)),
+        }
+    }
+
+    fn extract(&self, headers: &HeaderMap, query: Option<&str>, upgrade: bool) -> Option<String> {
+        match self {
+            TokenSource::Header(name) => {
+                let value = headers.get(name.as_str())?.to_str().ok()?.trim();
+                let token = if name.as_str() == AUTHORIZATION.as_str() {
+                    value.strip_prefix("Bearer ")?
+                } else {
+                    value.strip_prefix("Bearer ").unwrap_or(value)
+                };
+                Some(token.trim().to_string())
+            }
+            TokenSource::Cookie(name) => headers.get_all(COOKIE).iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.trim_matches('"').to_string()),
+            TokenSource::Query(name) if upgrade => query?.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| percent_decode_str(key).decode_utf8().is_ok_and(|key| key == name.as_str()))
+                .and_then(|(_, value)| percent_decode_str(&value.replace('+', " ")).decode_utf8().ok().map(|v| v.into_owned())),
+            TokenSource::Query(_) => None,
+        }
+        .filter(|token| !token.is_empty())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JwtConfig {
+    pub secret: Option<String>, // HMAC
+    pub sources: Vec<KeySource>,
+    pub algorithms: Vec<Algorithm>, // Allow-list; empty accepts whatever the keys support
+    pub issuers: Vec<String>, // Accepted `iss` values; empty skips the check
+    pub audiences: Vec<String>, // Accepted `aud` values; empty skips the check
+    pub leeway: u64, // Seconds of clock skew tolerated on exp/nbf
+    pub validate_nbf: bool,
+    pub required_claims: Vec<String>,
+    pub token_sources: Vec<TokenSource>,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            sources: Vec::new(),
+            algorithms: Vec::new(),
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway: 60,
+            validate_nbf: true,
+            required_claims: vec!["exp".to_string()],
+            token_sources: vec![TokenSource::Header("authorization".to_string())],
+        }
+    }
+}
+
+impl JwtConfig {
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::default();
+        validation.leeway = self.leeway;
+        validation.validate_nbf = self.validate_nbf;
+        if !self.issuers.is_empty() {
+            validation.set_issuer(&self.issuers);
+        }
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+        }
+        let spec: Vec<&str> = self.required_claims.iter()
+            .map(String::as_str)
+            .filter(|claim| SPEC_CLAIMS.contains(claim))
+            .collect();
+        validation.set_required_spec_claims(&spec);
+        validation
+    }
+}
+
+pub fn parse_algorithm(name: &str) -> Result<Algorithm, String> {
+    Algorithm::from_str(name).map_err(|_| format!("Unknown JWT algorithm: {}", name))
+}
+
+#[derive(Debug)]
+pub enum JwtError {
+    NoKeys,
+    Malformed,
+    AlgorithmNotAllowed(Algorithm),
+    UnknownKey(String),
+    MissingClaim(String),
+    Invalid(jsonwebtoken::errors::Error),
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtError::NoKeys => write!(f, "no JWT verification keys are configured"),
+            JwtError::Malformed => write!(f, "malformed token"),
+            JwtError::AlgorithmNotAllowed(alg) => write!(f, "algorithm {:?} is not allowed", alg),
+            JwtError::UnknownKey(kid) => write!(f, "no key with kid {:?}", kid),
+            JwtError::MissingClaim(claim) => write!(f, "missing required claim {:?}", claim),
+            JwtError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+struct VerificationKey {
+    kid: Option<String>,
+    algorithms: Vec<Algorithm>,
+    key: DecodingKey,
+}
+
+/// Everything a verification needs, swapped in as one unit.
+struct KeySet {
+    keys: Vec<VerificationKey>,
+    validation: Validation,
+    required_claims: Vec<String>,
+    token_sources: Vec<TokenSource>,
+}
+
+/// Verifies bearer tokens against an HMAC secret, PEM public keys and JWKS documents.
+/// The key set is rebuilt as a whole and swapped in, so rotation never drops requests.
+pub struct JwtVerifier {
+    config: Mutex<JwtConfig>,
+    keys: RwLock<Arc<KeySet>>,
+}
+
+impl JwtVerifier {
+    pub fn new() -> Self {
+        let config = JwtConfig::default();
+        let keys = KeySet {
+            keys: Vec::new(),
+            validation: config.validation(),
+            required_claims: config.required_claims.clone(),
+            token_sources: config.token_sources.clone(),
+        };
+        Self {
+            config: Mutex::new(config),
+            keys: RwLock::new(Arc::new(keys)),
+        }
+    }
+
+    pub fn has_keys(&self) -> bool {
+        !self.keys.read().unwrap().keys.is_empty()
+    }
+
+    /// Replaces the whole configuration. On error the previous keys stay in place.
+    pub fn configure(&self, config: JwtConfig) -> Result<usize, String> {
+        let keys = KeySet {
+            keys: build_keys(&config)?,
+            validation: config.validation(),
+            required_claims: config.required_claims.clone(),
+            token_sources: config.token_sources.clone(),
+        };
+        let count = keys.keys.len();
+        *self.config.lock().unwrap() = config;
+        *self.keys.write().unwrap() = Arc::new(keys);
+        Ok(count)
+    }
+
+    pub fn set_secret(&self, secret: String) -> Result<usize, String> {
+        let mut config = self.config.lock().unwrap().clone();
+        config.secret = Some(secret);
+        self.configure(config)
+    }
+
+    /// Re-reads key files and, when `jwks` is given, replaces the JS-provided key set.
+    pub fn refresh(&self, jwks: Option<String>) -> Result<usize, String> {
+        let mut config = self.config.lock().unwrap().clone();
+        if let Some(jwks) = jwks {
+            config.sources.retain(|source| !matches!(source, KeySource::Jwks(_)));
+            config.sources.push(KeySource::Jwks(jwks));
+        }
+        self.configure(config)
+    }
+
+    /// The first token found in the configured sources. `upgrade` marks a WebSocket
+    /// handshake, the only request allowed to carry it in the query string.
+    pub fn extract_token(&self, headers: &HeaderMap, query: Option<&str>, upgrade: bool) -> Option<String> {
+        let set = self.keys.read().unwrap().clone();
+        set.token_sources.iter().find_map(|source| source.extract(headers, query, upgrade))
+    }
+
+    /// Checks the signature and claims of `token` and returns the full claim set.
+    ///
+    /// A token naming a `kid` is checked against that key only; keys configured
+    /// without a kid are the fallback when no key carries it.
+    pub fn verify(&self, token: &str) -> Result<Value, JwtError> {
+        let set = self.keys.read().unwrap().clone();
+        let keys = &set.keys;
+        if keys.is_empty() {
+            return Err(JwtError::NoKeys);
+        }
+        let header = decode_header(token).map_err(|_| JwtError::Malformed)?;
+        let alg = header.alg;
+        if !keys.iter().any(|k| k.algorithms.contains(&alg)) {
+            return Err(JwtError::AlgorithmNotAllowed(alg));
+        }
+
+        let usable = |k: &&VerificationKey| k.algorithms.contains(&alg);
+        let candidates: Vec<&VerificationKey> = match &header.kid {
+            Some(kid) if keys.iter().any(|k| k.kid.as_ref() == Some(kid)) => {
+                keys.iter().filter(usable).filter(|k| k.kid.as_ref() == Some(kid)).collect()
+            }
+            Some(kid) => {
+                let unnamed: Vec<_> = keys.iter().filter(usable).filter(|k| k.kid.is_none()).collect();
+                if unnamed.is_empty() {
+                    return Err(JwtError::UnknownKey(kid.clone()));
+                }
+                unnamed
+            }
+            None => keys.iter().filter(usable).collect(),
+        };
+
+        let mut validation = set.validation.clone();
+        validation.algorithms = vec![alg];
+        let mut last_error = JwtError::AlgorithmNotAllowed(alg);
+        for candidate in candidates {
+            match decode::<Value>(token, &candidate.key, &validation) {
+                Ok(data) => {
+                    if let Some(missing) = set.required_claims.iter().find(|claim| data.claims.get(claim.as_str()).is_none()) {
+                        return Err(JwtError::MissingClaim(missing.clone()));
+                    }
+                    return Ok(data.claims);
+                }
+                Err(e) => last_error = JwtError::Invalid(e),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// A route's demand on the verified claims, e.g. scopes ["orders:write"] in `scope`.
+#[derive(Clone, Debug)]
+pub struct ClaimRequirement {
+    pub claim: String, // Dotted path: "scope", "roles", "realm_access.roles"
+    pub fallbacks: &'static [&'static str], // Tried when `claim` is absent
+    pub values: Vec<String>,
+    pub any: bool, // One of `values` suffices; otherwise all are needed
+}
+
+impl ClaimRequirement {
+    /// An empty `values` list would let `all` pass every token and `any` none, so it is refused.
+    pub fn new(claim: Option<&str>, values: Vec<String>, mode: Option<&str>) -> Result<Self, String> {
+        if values.is_empty() || values.iter().any(|v| v.trim().is_empty()) {
+            return Err(format!("authorize on claim \"{}\" needs at least one non-empty value", claim.unwrap_or("scope")));
+        }
+        Ok(Self {
+            claim: claim.unwrap_or("scope").to_string(),
+            // Without an explicit path, also accept Azure's `scp` and API key `scopes`
+            fallbacks: if claim.is_none() { &["scp", "scopes"] } else { &[] },
+            values,
+            any: mode.is_some_and(|m| m.eq_ignore_ascii_case("any")),
+        })
+    }
+
+    /// Granted values at the claim path: an array of strings, or a space-delimited
+    /// string as in OAuth's `scope`.
+    fn granted<'a>(&self, claims: &'a Value) -> Vec<&'a str> {
+        let at = |path: &str| path.split('.').try_fold(claims, |value, segment| value.get(segment));
+        let found = at(&self.claim).or_else(|| self.fallbacks.iter().find_map(|path| at(path)));
+        match found {
+            Some(Value::String(s)) => s.split_whitespace().collect(),
+            Some(Value::Array(items)) => items.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn satisfied_by(&self, claims: &Value) -> bool {
+        let granted = self.granted(claims);
+        let has = |value: &String| granted.contains(&value.as_str());
+        if self.any {
+            self.values.iter().any(has)
+        } else {
+            self.values.iter().all(has)
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} of [{}] in claim \"{}\"", if self.any { "one" } else { "all" }, self.values.join(", "), self.claim)
+    }
+}
+
+fn build_keys(config: &JwtConfig) -> Result<Vec<VerificationKey>, String> {
+    let mut keys = Vec::new();
+    if let Some(secret) = &config.secret {
+        keys.push(VerificationKey { kid: None, algorithms: HMAC.to_vec(), key: DecodingKey::from_secret(secret.as_bytes()) });
+    }
+    for source in &config.sources {
+        match source {
+            KeySource::Pem { pem, kid, algorithm } => keys.push(pem_key(pem.as_bytes(), kid.clone(), *algorithm)?),
+            KeySource::PemFile { path, kid, algorithm } => {
+                let pem = std::fs::read(path).map_err(|e| format!("Failed to read JWT key {}: {}", path, e))?;
+                keys.push(pem_key(&pem, kid.clone(), *algorithm).map_err(|e| format!("{}: {}", path, e))?);
+            }
+            KeySource::Jwks(json) => keys.extend(jwks_keys(json)?),
+            KeySource::JwksFile(path) => {
+                let json = std::fs::read_to_string(path).map_err(|e| format!("Failed to read JWKS {}: {}", path, e))?;
+                keys.extend(jwks_keys(&json).map_err(|e| format!("{}: {}", path, e))?);
+            }
+        }
+    }
+
+    if !config.algorithms.is_empty() {
+        for key in &mut keys {
+            key.algorithms.retain(|alg| config.algorithms.contains(alg));
+        }
+        keys.retain(|key| !key.algorithms.is_empty());
+    }
+    Ok(keys)
+}
+
+/// Accepts RSA, EC and Ed25519 public keys (SPKI or PKCS#1) and X.509 certificates.
+fn pem_key(pem: &[u8], kid: Option<String>, algorithm: Option<Algorithm>) -> Result<VerificationKey, String> {
+    let (key, family): (DecodingKey, &[Algorithm]) = if let Ok(key) = DecodingKey::from_rsa_pem(pem) {
+        (key, &RSA)
+    } else if let Ok(key) = DecodingKey::from_ec_pem(pem) {
+        (key, &EC)
+    } else if let Ok(key) = DecodingKey::from_ed_pem(pem) {
+        (key, &ED)
+    } else {
+        return Err("Invalid PEM public key (expected RSA, EC or Ed25519)".to_string());
+    };
+
+    let algorithms = match algorithm {
+        Some(alg) if family.contains(&alg) => vec![alg],
+        Some(alg) => return Err(format!("Algorithm {:?} does not match the PEM key type", alg)),
+        None => family.to_vec(),
+    };
+    Ok(VerificationKey { kid, algorithms, key })
+}
+
+/// Signing keys from a JWKS document. Encryption keys and unsupported key types are skipped.
+fn jwks_keys(json: &str) -> Result<Vec<VerificationKey>, String> {
+    let set: JwkSet = serde_json::from_str(json).map_err(|e| format!("Invalid JWKS: {}", e))?;
+    Ok(set.keys.iter().filter_map(jwk_key).collect())
+}
+
+fn jwk_key(jwk: &Jwk) -> Option<VerificationKey> {
+    if jwk.common.public_key_use == Some(PublicKeyUse::Encryption) {
+        return None;
+    }
+    let family: Vec<Algorithm> = match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => RSA.to_vec(),
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => return None,
+        },
+        AlgorithmParameters::OctetKeyPair(_) => ED.to_vec(),
+        AlgorithmParameters::OctetKey(_) => HMAC.to_vec(),
+    };
+    let algorithms = match jwk.common.key_algorithm {
+        // "RSA-OAEP" and friends fail to parse and drop the key
+        Some(declared) => vec![Algorithm::from_str(&declared.to_string()).ok().filter(|alg| family.contains(alg))?],
+        None => family,
+    };
+    let key = DecodingKey::from_jwk(jwk).ok()?;
+    Some(VerificationKey { kid: jwk.common.key_id.clone(), algorithms, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    fn signed(alg: Algorithm, kid: Option<&str>, secret: &[u8], claims: Value) -> String {
+        let mut header = Header::new(alg);
+        header.kid = kid.map(str::to_string);
+        encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    fn token(alg: Algorithm, kid: Option<&str>, secret: &[u8]) -> String {
+        signed(alg, kid, secret, json!({ "sub": "42", "exp": 4_000_000_000u64 }))
+    }
+
+    // base64url("first") / base64url("second")
+    const JWKS: &str = r#"{"keys":[
+        {"kty":"oct","kid":"a","k":"Zmlyc3Q"},
+        {"kty":"oct","kid":"b","alg":"HS512","k":"c2Vjb25k"}
+    ]}"#;
+
+    #[test]
+    fn selects_jwks_key_by_kid() {
+        let verifier = JwtVerifier::new();
+        verifier.configure(JwtConfig { sources: vec![KeySource::Jwks(JWKS.into())], ..Default::default() }).unwrap();
+
+        let claims = verifier.verify(&token(Algorithm::HS256, Some("a"), b"first")).unwrap();
+        assert_eq!(claims["sub"], "42");
+        assert!(verifier.verify(&token(Algorithm::HS256, Some("b"), b"first")).is_err());
+        assert!(matches!(verifier.verify(&token(Algorithm::HS256, Some("c"), b"first")), Err(JwtError::UnknownKey(_))));
+        // Key "b" declares HS512 only
+        assert!(verifier.verify(&token(Algorithm::HS256, Some("b"), b"second")).is_err());
+        assert!(verifier.verify(&token(Algorithm::HS512, Some("b"), b"second")).is_ok());
+    }
+
+    #[test]
+    fn enforces_algorithm_allow_list() {
+        let verifier = JwtVerifier::new();
+        verifier.configure(JwtConfig { secret: Some("s3cret".into()), algorithms: vec![Algorithm::HS384], ..Default::default() }).unwrap();
+
+        assert!(matches!(verifier.verify(&token(Algorithm::HS256, None, b"s3cret")), Err(JwtError::AlgorithmNotAllowed(_))));
+        assert!(verifier.verify(&token(Algorithm::HS384, None, b"s3cret")).is_ok());
+    }
+
+    #[test]
+    fn refresh_swaps_inline_jwks() {
+        let verifier = JwtVerifier::new();
+        verifier.refresh(Some(JWKS.into())).unwrap();
+        assert!(verifier.verify(&token(Algorithm::HS256, Some("a"), b"first")).is_ok());
+
+        verifier.refresh(Some(r#"{"keys":[{"kty":"oct","kid":"c","k":"dGhpcmQ"}]}"#.into())).unwrap();
+        assert!(verifier.verify(&token(Algorithm::HS256, Some("a"), b"first")).is_err());
+        assert!(verifier.verify(&token(Algorithm::HS256, Some("c"), b"third")).is_ok());
+        // A broken document leaves the current keys in place
+        assert!(verifier.refresh(Some("{".into())).is_err());
+        assert!(verifier.verify(&token(Algorithm::HS256, Some("c"), b"third")).is_ok());
+    }
+
+    #[test]
+    fn checks_issuer_audience_and_required_claims() {
+        let verifier = JwtVerifier::new();
+        verifier.configure(JwtConfig {
+            secret: Some("s3cret".into()),
+            issuers: vec!["https://id.example.com".into()],
+            audiences: vec!["api".into()],
+            required_claims: vec!["exp".into(), "tenant".into()],
+            ..Default::default()
+        }).unwrap();
+        let with = |claims: Value| verifier.verify(&signed(Algorithm::HS256, None, b"s3cret", claims));
+
+        let claims = with(json!({ "sub": "42", "exp": 4_000_000_000u64, "iss": "https://id.example.com", "aud": ["api", "web"], "tenant": "t1", "roles": ["admin"] })).unwrap();
+        assert_eq!(claims["roles"][0], "admin");
+        assert!(with(json!({ "exp": 4_000_000_000u64, "iss": "https://evil.example.com", "aud": "api", "tenant": "t1" })).is_err());
+        assert!(with(json!({ "exp": 4_000_000_000u64, "iss": "https://id.example.com", "aud": "web", "tenant": "t1" })).is_err());
+        assert!(matches!(with(json!({ "exp": 4_000_000_000u64, "iss": "https://id.example.com", "aud": "api" })), Err(JwtError::MissingClaim(_))));
+        // Not valid for another hour, well past the leeway
+        let later = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() + 3600;
+        assert!(with(json!({ "exp": 4_000_000_000u64, "nbf": later, "iss": "https://id.example.com", "aud": "api", "tenant": "t1" })).is_err());
+    }
+
+    #[test]
+    fn claim_requirements_follow_paths_and_modes() {
+        let claims = json!({ "scope": "orders:read orders:write", "realm_access": { "roles": ["admin", "ops"] } });
+
+        assert!(ClaimRequirement::new(None, vec!["orders:read".into(), "orders:write".into()], None).unwrap().satisfied_by(&claims));
+        assert!(!ClaimRequirement::new(None, vec!["orders:read".into(), "users:write".into()], Some("all")).unwrap().satisfied_by(&claims));
+        assert!(ClaimRequirement::new(None, vec!["orders:read".into(), "users:write".into()], Some("any")).unwrap().satisfied_by(&claims));
+        assert!(ClaimRequirement::new(Some("realm_access.roles"), vec!["admin".into()], None).unwrap().satisfied_by(&claims));
+        assert!(!ClaimRequirement::new(Some("roles"), vec!["admin".into()], None).unwrap().satisfied_by(&claims));
+        assert!(ClaimRequirement::new(None, vec!["partner".into()], None).unwrap().satisfied_by(&json!({ "scopes": ["partner"] })));
+        assert!(ClaimRequirement::new(Some("roles"), Vec::new(), Some("any")).is_err());
+        assert!(ClaimRequirement::new(None, vec![" ".into()], None).is_err());
+    }
+
+    #[test]
+    fn extracts_tokens_from_configured_sources() {
+        let verifier = JwtVerifier::new();
+        verifier.configure(JwtConfig {
+            secret: Some("s3cret".into()),
+            token_sources: ["header", "cookie:session", "query:access_token"].iter().map(|s| TokenSource::parse(s).unwrap()).collect(),
+            ..Default::default()
+        }).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, "theme=dark; session=abc.def.ghi".parse().unwrap());
+        assert_eq!(verifier.extract_token(&headers, None, false).as_deref(), Some("abc.def.ghi"));
+        headers.insert(AUTHORIZATION, "Bearer hdr.tok.en".parse().unwrap());
+        assert_eq!(verifier.extract_token(&headers, None, false).as_deref(), Some("hdr.tok.en"));
+
+        let empty = HeaderMap::new();
+        assert_eq!(verifier.extract_token(&empty, Some("access_token=q.tok.en"), false), None);
+        assert_eq!(verifier.extract_token(&empty, Some("x=1&access_token=q.tok.en"), true).as_deref(), Some("q.tok.en"));
+        assert!(TokenSource::parse("cookie").is_err());
+    }
+}
@@ -6,16 +6,34 @@ extern crate napi_derive;
 use napi::bindgen_prelude::*;
 use napi::threadsafe_function::{ThreadsafeFunction, ErrorStrategy, ThreadSafeCallContext};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use server::{RequestEvent, WsEvent};
 mod database;
 mod server;
 mod router;
 mod governor;
+mod cache;
+mod static_files;
+mod shared_cache;
+mod rate_limit;
+mod redis_pool;
+mod jwt;
+mod api_key;
+mod basic_auth;
+mod tls;
+mod https;
+mod cors;
+mod csrf;
+mod cookies;
+mod session;
+mod security_headers;
 
 #[napi]
 pub struct NativeEngine {
     server: Arc<Mutex<server::NativeServer>>,
     db_manager: Arc<tokio::sync::Mutex<database::DatabaseManager>>,
+    // Held outside the db_manager lock so metrics never wait on a running query
+    query_cache: Arc<cache::BoundedCache<Arc<database::CachedQuery>>>,
 }
 
 use router::RouteAction;
@@ -24,29 +42,442 @@ use router::RouteAction;
 pub struct RouteOptions {
     pub rate_limit_limit: Option<u32>,
     pub rate_limit_window: Option<u32>,
+    pub rate_limit_algorithm: Option<String>, // "fixed_window" (default) | "sliding_log" | "sliding_window" | "token_bucket"
+    pub rate_limit_rate: Option<f64>, // Token bucket refill, tokens/sec (default limit / window)
+    pub rate_limit_burst: Option<u32>, // Token bucket capacity (default limit)
+    pub rate_limit_key: Option<String>, // "ip" (default) | "header:<name>" | "sub" | "global"
+    pub rate_limit_per_route: Option<bool>, // Count by route pattern ("/users/:id") instead of concrete path
+    pub rate_limits: Option<Vec<RateLimitOptions>>, // Additional limits stacked on top
     pub cache_ttl: Option<u32>,
+    pub cache_query: Option<String>, // "all" (default) | "none"
+    pub cache_query_params: Option<Vec<String>>, // Only these params are part of the key
+    pub cache_ignore_params: Option<Vec<String>>, // These params are left out of the key
+    pub cache_vary: Option<Vec<String>>, // Request headers that are part of the key
+    pub cache_vary_user: Option<bool>, // Include the JWT `sub` or API key id in the key; defaults to on for jwt_auth / api_key_auth routes
+    pub cache_stale_while_revalidate: Option<u32>, // Serve stale this long after ttl while refreshing
+    pub cache_stale_if_error: Option<u32>, // Serve stale this long after ttl if the handler fails
     pub jwt_auth: Option<bool>,
+    pub api_key_auth: Option<bool>,
+    pub basic_auth: Option<String>, // Realm name registered with add_basic_auth_realm
+    pub authorize: Option<Vec<AuthorizeOptions>>, // Scope/role requirements; implies jwt_auth unless api_key_auth or client_cert is set
+    pub client_cert: Option<ClientCertOptions>, // Require a verified mTLS client certificate ({} accepts any)
+    pub security_headers: Option<SecurityHeadersOptions>, // Overrides the global policy for this route
+    pub cors: Option<CorsOptions>, // Replaces the global CORS policy for this route
+    pub csrf_exempt: Option<bool>, // Skip CSRF checks on this route
     pub schema: Option<String>,
     pub priority: Option<String>,
     pub slo_target: Option<u32>,
 }
 
-impl From<Option<RouteOptions>> for router::RoutePolicies {
-    fn from(options: Option<RouteOptions>) -> Self {
-        match options {
-            Some(opts) => router::RoutePolicies {
-                rate_limit: match (opts.rate_limit_limit, opts.rate_limit_window) {
-                    (Some(limit), Some(window)) => Some((limit, window as u64)),
-                    _ => None,
-                },
-                cache_ttl: opts.cache_ttl.map(|ttl| ttl as u64),
-                jwt_auth: opts.jwt_auth.unwrap_or(false),
-                schema: opts.schema,
-                priority: opts.priority,
-                slo_target: opts.slo_target.map(|t| t as u64),
+#[napi(object)]
+pub struct RateLimitOptions {
+    pub limit: Option<u32>,
+    pub window: Option<u32>,
+    pub algorithm: Option<String>,
+    pub rate: Option<f64>,
+    pub burst: Option<u32>,
+    pub key: Option<String>,
+    pub per_route: Option<bool>,
+}
+
+impl RateLimitOptions {
+    fn into_limit(self) -> Result<Option<rate_limit::RateLimit>> {
+        let limit = rate_limit::RateLimit::from_options(self.algorithm.as_deref(), self.limit, self.window.map(|w| w as u64), self.rate, self.burst)
+            .map_err(Error::from_reason)?;
+        match limit {
+            Some(limit) => limit.keyed(self.key.as_deref(), self.per_route.unwrap_or(false)).map(Some).map_err(Error::from_reason),
+            None if self.key.is_some() || self.per_route.is_some() => {
+                Err(Error::from_reason("Rate limit key and per-route scope need a limit and window"))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[napi(object)]
+pub struct AuthorizeOptions {
+    pub claim: Option<String>, // Claim path, default "scope" (e.g. "roles", "realm_access.roles")
+    pub values: Vec<String>,
+    pub mode: Option<String>, // "all" (default) | "any"
+}
+
+#[napi(object)]
+pub struct ClientCertOptions {
+    pub subjects: Option<Vec<String>>, // e.g. "CN=billing-service, O=Acme"; `*` wildcards
+    pub sans: Option<Vec<String>>, // e.g. "DNS:*.internal", "URI:spiffe://acme/*"
+    pub fingerprints: Option<Vec<String>>, // SHA-256, hex with or without colons
+}
+
+#[napi(object)]
+pub struct TlsCertificateOptions {
+    pub hostnames: Vec<String>, // SNI names, "*.example.com" matches one label
+    pub cert_path: String,
+    pub key_path: String,
+    pub ocsp_path: Option<String>, // DER OCSP response to staple, re-read on reload
+}
+
+#[napi(object)]
+pub struct TlsPolicyOptions {
+    pub min_version: Option<String>, // "1.2" (default) | "1.3"
+    pub max_version: Option<String>, // "1.3" (default) | "1.2"
+    pub cipher_suites: Option<Vec<String>>, // rustls names, e.g. "TLS13_AES_256_GCM_SHA384"; default all
+    pub alpn: Option<Vec<String>>, // Default ["h2", "http/1.1"]
+    pub session_tickets: Option<bool>, // Stateless resumption tickets, default false
+    pub ticket_keys: Option<Vec<String>>, // 32-byte hex/base64 keys shared across instances; first seals
+    pub ticket_rotation_secs: Option<u32>, // Rotation of the generated key when no ticket_keys, default 21600
+    pub session_cache_size: Option<u32>, // Server-side session cache entries, default 256; 0 disables
+}
+
+impl TlsPolicyOptions {
+    fn into_policy(self) -> Result<tls::TlsPolicy> {
+        let defaults = tls::TlsPolicy::default();
+        let versions = tls::TlsPolicy::versions_between(self.min_version.as_deref(), self.max_version.as_deref())
+            .map_err(Error::from_reason)?;
+        let tickets = match (self.ticket_keys, self.session_tickets) {
+            (Some(keys), _) => tls::TicketPolicy::Keys(keys.iter()
+                .map(|key| tls::parse_ticket_key(key).map_err(Error::from_reason))
+                .collect::<Result<Vec<_>>>()?),
+            (None, Some(true)) => tls::TicketPolicy::Rotating(Duration::from_secs(self.ticket_rotation_secs.unwrap_or(6 * 60 * 60).max(60) as u64)),
+            (None, _) => tls::TicketPolicy::Disabled,
+        };
+        Ok(tls::TlsPolicy {
+            versions,
+            cipher_suites: self.cipher_suites.unwrap_or_default(),
+            alpn: self.alpn.unwrap_or(defaults.alpn),
+            tickets,
+            session_cache_size: self.session_cache_size.map(|n| n as usize).unwrap_or(defaults.session_cache_size),
+        })
+    }
+}
+
+#[napi(object)]
+pub struct SecurityHeadersOptions {
+    pub content_security_policy: Option<String>, // "{nonce}" is replaced per request, e.g. "script-src 'nonce-{nonce}'"
+    pub csp_report_only: Option<bool>,
+    pub permissions_policy: Option<String>, // e.g. "camera=(), geolocation=()"
+    pub cross_origin_opener_policy: Option<String>,
+    pub cross_origin_embedder_policy: Option<String>,
+    pub cross_origin_resource_policy: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub frame_options: Option<String>, // "DENY" | "SAMEORIGIN"
+    pub nosniff: Option<bool>,
+}
+
+impl SecurityHeadersOptions {
+    /// Unset fields stay unset; an empty string turns a header off.
+    fn into_policy(self) -> Result<security_headers::SecurityHeaders> {
+        let policy = security_headers::SecurityHeaders {
+            content_security_policy: self.content_security_policy,
+            csp_report_only: self.csp_report_only,
+            permissions_policy: self.permissions_policy,
+            cross_origin_opener_policy: self.cross_origin_opener_policy,
+            cross_origin_embedder_policy: self.cross_origin_embedder_policy,
+            cross_origin_resource_policy: self.cross_origin_resource_policy,
+            referrer_policy: self.referrer_policy,
+            frame_options: self.frame_options,
+            nosniff: self.nosniff,
+        };
+        policy.validate().map_err(Error::from_reason)?;
+        Ok(policy)
+    }
+}
+
+#[napi(object)]
+pub struct CorsOptions {
+    pub origins: Option<Vec<String>>, // Exact or with "*" ("https://*.example.com"); default ["*"]
+    pub methods: Option<Vec<String>>,
+    pub allow_headers: Option<Vec<String>>, // "*" allows any requested header
+    pub expose_headers: Option<Vec<String>>,
+    pub credentials: Option<bool>, // Not allowed with origin "*"
+    pub max_age: Option<u32>, // Preflight cache, seconds (default 86400)
+    pub private_network: Option<bool>, // Allow Private Network Access preflights
+}
+
+impl CorsOptions {
+    fn into_policy(self) -> Result<cors::CorsPolicy> {
+        let defaults = cors::CorsPolicy::default();
+        let policy = cors::CorsPolicy {
+            origins: self.origins.unwrap_or(defaults.origins),
+            methods: self.methods.unwrap_or(defaults.methods),
+            allow_headers: self.allow_headers.unwrap_or(defaults.allow_headers),
+            expose_headers: self.expose_headers.unwrap_or_default(),
+            credentials: self.credentials.unwrap_or(false),
+            max_age: self.max_age.map(u64::from).or(defaults.max_age),
+            private_network: self.private_network.unwrap_or(false),
+        };
+        policy.validate().map_err(Error::from_reason)?;
+        Ok(policy)
+    }
+}
+
+#[napi(object)]
+pub struct CsrfOptions {
+    pub secret: Option<String>, // Signs tokens, at least 32 bytes; random per process if unset
+    pub cookie_name: Option<String>, // default "csrf_token"
+    pub header_name: Option<String>, // default "x-csrf-token"
+    pub form_field: Option<String>, // default "_csrf", for urlencoded forms
+    pub trusted_origins: Option<Vec<String>>, // Other origins allowed to send unsafe requests
+    pub secure: Option<bool>, // Secure cookie attribute, default true
+}
+
+impl CsrfOptions {
+    fn into_policy(self) -> Result<csrf::CsrfPolicy> {
+        let mut policy = csrf::CsrfPolicy::new(self.secret.as_deref().map(str::as_bytes)).map_err(Error::from_reason)?;
+        if let Some(name) = self.cookie_name {
+            policy.cookie_name = name;
+        }
+        if let Some(name) = self.header_name {
+            hyper::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| Error::from_reason(format!("Invalid CSRF header name: {}", name)))?;
+            policy.header_name = name.to_ascii_lowercase();
+        }
+        if let Some(field) = self.form_field {
+            policy.form_field = field;
+        }
+        policy.trusted_origins = self.trusted_origins.unwrap_or_default();
+        policy.secure = self.secure.unwrap_or(true);
+        Ok(policy)
+    }
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct CookieOptions {
+    pub path: Option<String>, // default "/"
+    pub domain: Option<String>,
+    pub max_age: Option<u32>, // seconds; 0 deletes, unset lasts the browser session
+    pub secure: Option<bool>, // default true
+    pub http_only: Option<bool>, // default true
+    pub same_site: Option<String>, // "Strict" | "Lax" (default) | "None"
+}
+
+impl CookieOptions {
+    fn attributes(&self) -> Result<cookies::CookieAttributes> {
+        let defaults = cookies::CookieAttributes::default();
+        Ok(cookies::CookieAttributes {
+            path: self.path.clone().unwrap_or(defaults.path),
+            domain: self.domain.clone(),
+            secure: self.secure.unwrap_or(defaults.secure),
+            http_only: self.http_only.unwrap_or(defaults.http_only),
+            same_site: match &self.same_site {
+                Some(same_site) => cookies::SameSite::from_str(same_site).map_err(Error::from_reason)?,
+                None => defaults.same_site,
             },
-            None => router::RoutePolicies::default(),
+        })
+    }
+}
+
+#[napi(object)]
+pub struct SessionOptions {
+    pub cookie_name: Option<String>, // default "sid"
+    pub ttl: Option<u32>, // seconds, default 86400
+    pub sliding: Option<bool>, // Extend the expiry on every request, default true
+    pub store: Option<String>, // "memory" (default) | "redis" (needs connect_redis)
+    pub redis_prefix: Option<String>, // default "qhttpx:session:"
+    pub cookie: Option<CookieOptions>, // max_age is ignored; the ttl is used
+}
+
+#[napi(object)]
+pub struct HstsOptions {
+    pub max_age: Option<u32>, // seconds, default 31536000
+    pub include_subdomains: Option<bool>,
+    pub preload: Option<bool>, // Needs max_age >= 31536000 and include_subdomains
+}
+
+#[napi(object)]
+pub struct HttpsRedirectOptions {
+    pub https_port: Option<u16>, // Port in the Location; default the main listener's, omitted when 443
+    pub host: Option<String>, // Canonical host to redirect to instead of the request's Host
+    pub status: Option<u16>, // 301 | 302 | 307 | 308 (default)
+}
+
+#[napi(object)]
+pub struct ClientAuthOptions {
+    pub ca_path: String, // PEM bundle of CAs that sign client certificates
+    pub mode: Option<String>, // "required" (default) | "optional"
+    pub crl_paths: Option<Vec<String>>, // PEM CRLs checked for every certificate in the chain
+}
+
+#[napi(object)]
+pub struct ApiKeyOptions {
+    pub key: Option<String>, // Plaintext; hashed on load and never kept
+    pub hash: Option<String>, // Hex SHA-256 of the key, for stores that only hold hashes
+    pub id: Option<String>, // Rate-limit identity, default owner
+    pub owner: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub tier: Option<String>,
+    pub metadata: Option<serde_json::Value>, // Extra fields forwarded to handlers
+}
+
+impl ApiKeyOptions {
+    fn into_record(self) -> api_key::ApiKeyRecord {
+        let mut fields = match self.metadata {
+            Some(serde_json::Value::Object(fields)) => fields,
+            _ => serde_json::Map::new(),
+        };
+        for (name, value) in [("id", self.id), ("owner", self.owner), ("tier", self.tier)] {
+            if let Some(value) = value {
+                fields.insert(name.to_string(), value.into());
+            }
+        }
+        if let Some(scopes) = self.scopes {
+            fields.insert("scopes".to_string(), scopes.into());
+        }
+        api_key::ApiKeyRecord { key: self.key, hash: self.hash, metadata: fields }
+    }
+}
+
+#[napi(object)]
+pub struct CacheOptions {
+    pub response_max_bytes: Option<u32>,
+    pub rate_limit_max_bytes: Option<u32>,
+    pub query_max_bytes: Option<u32>,
+    pub sweep_interval_ms: Option<u32>,
+    pub redis: Option<bool>, // Share route responses across instances through Redis (needs connect_redis)
+    pub redis_prefix: Option<String>, // Key and channel prefix, default "qhttpx:cache:"
+}
+
+#[napi(object)]
+pub struct RedisOptions {
+    pub failure_mode: Option<String>, // Rate limiting while Redis is down: "local" (default) | "open" | "closed"
+    pub timeout_ms: Option<u32>, // Per call, default 250
+    pub breaker_threshold: Option<u32>, // Consecutive failures before Redis is skipped, default 5
+    pub breaker_cooldown_ms: Option<u32>, // How long it is skipped before a retry, default 5000
+}
+
+#[napi(object)]
+pub struct JwtKeyOptions {
+    pub pem: Option<String>, // Public key or certificate
+    pub path: Option<String>, // PEM file, re-read by refresh_jwt_keys
+    pub kid: Option<String>,
+    pub algorithm: Option<String>, // Narrows the key to one algorithm, e.g. "RS256"
+}
+
+#[napi(object)]
+pub struct JwtOptions {
+    pub secret: Option<String>, // HMAC (HS256/384/512)
+    pub public_keys: Option<Vec<JwtKeyOptions>>,
+    pub jwks: Option<String>, // JWKS document as JSON
+    pub jwks_path: Option<String>, // JWKS file, re-read by refresh_jwt_keys
+    pub algorithms: Option<Vec<String>>, // Allow-list, e.g. ["RS256", "ES256"]; default: whatever the keys support
+    pub issuer: Option<Vec<String>>, // Accepted `iss` values
+    pub audience: Option<Vec<String>>, // Accepted `aud` values
+    pub leeway: Option<u32>, // Clock skew tolerated on exp/nbf in seconds, default 60
+    pub validate_nbf: Option<bool>, // Default true
+    pub required_claims: Option<Vec<String>>, // Default ["exp"]
+    pub token_sources: Option<Vec<String>>, // Tried in order: "header" (default), "header:<name>", "cookie:<name>", "query:<name>" (WebSocket upgrades only)
+}
+
+impl JwtOptions {
+    fn into_config(self) -> Result<jwt::JwtConfig> {
+        let algorithm = |name: Option<String>| name.map(|n| jwt::parse_algorithm(&n)).transpose().map_err(Error::from_reason);
+
+        let mut sources = Vec::new();
+        for key in self.public_keys.unwrap_or_default() {
+            let algorithm = algorithm(key.algorithm)?;
+            sources.push(match (key.pem, key.path) {
+                (Some(pem), _) => jwt::KeySource::Pem { pem, kid: key.kid, algorithm },
+                (None, Some(path)) => jwt::KeySource::PemFile { path, kid: key.kid, algorithm },
+                (None, None) => return Err(Error::from_reason("JWT public key needs either pem or path")),
+            });
+        }
+        if let Some(jwks) = self.jwks {
+            sources.push(jwt::KeySource::Jwks(jwks));
         }
+        if let Some(path) = self.jwks_path {
+            sources.push(jwt::KeySource::JwksFile(path));
+        }
+
+        let algorithms = self.algorithms.unwrap_or_default().into_iter()
+            .map(|name| jwt::parse_algorithm(&name).map_err(Error::from_reason))
+            .collect::<Result<Vec<_>>>()?;
+        let defaults = jwt::JwtConfig::default();
+        let token_sources = match self.token_sources {
+            Some(specs) => specs.iter()
+                .map(|spec| jwt::TokenSource::parse(spec).map_err(Error::from_reason))
+                .collect::<Result<Vec<_>>>()?,
+            None => defaults.token_sources,
+        };
+        Ok(jwt::JwtConfig {
+            secret: self.secret,
+            sources,
+            algorithms,
+            issuers: self.issuer.unwrap_or_default(),
+            audiences: self.audience.unwrap_or_default(),
+            leeway: self.leeway.map(u64::from).unwrap_or(defaults.leeway),
+            validate_nbf: self.validate_nbf.unwrap_or(defaults.validate_nbf),
+            required_claims: self.required_claims.unwrap_or(defaults.required_claims),
+            token_sources,
+        })
+    }
+}
+
+#[napi(object)]
+pub struct StaticOptions {
+    pub symlinks: Option<String>, // "deny" | "within_root" (default) | "follow"
+}
+
+impl TryFrom<Option<RouteOptions>> for router::RoutePolicies {
+    type Error = Error;
+
+    fn try_from(options: Option<RouteOptions>) -> Result<Self> {
+        Ok(match options {
+            Some(opts) => {
+                let api_key_auth = opts.api_key_auth.unwrap_or(false);
+                let jwt_auth = opts.jwt_auth.unwrap_or(false)
+                    || (!api_key_auth && opts.client_cert.is_none() && opts.authorize.as_ref().is_some_and(|a| !a.is_empty()));
+                router::RoutePolicies {
+                    rate_limits: RateLimitOptions {
+                        limit: opts.rate_limit_limit,
+                        window: opts.rate_limit_window,
+                        algorithm: opts.rate_limit_algorithm,
+                        rate: opts.rate_limit_rate,
+                        burst: opts.rate_limit_burst,
+                        key: opts.rate_limit_key,
+                        per_route: opts.rate_limit_per_route,
+                    }
+                        .into_limit()?
+                        .into_iter()
+                        .chain(
+                            opts.rate_limits
+                                .unwrap_or_default()
+                                .into_iter()
+                                .map(|options| options.into_limit()?.ok_or_else(|| Error::from_reason("Stacked rate limit needs a limit and window")))
+                                .collect::<Result<Vec<_>>>()?,
+                        )
+                        .collect(),
+                    cache_ttl: opts.cache_ttl.map(|ttl| ttl as u64),
+                    // Responses behind auth are per caller unless the route says otherwise
+                    cache_key: cache::CacheKeyPolicy::new(
+                        opts.cache_query,
+                        opts.cache_query_params,
+                        opts.cache_ignore_params,
+                        opts.cache_vary,
+                        opts.cache_vary_user.unwrap_or(jwt_auth || api_key_auth),
+                    ),
+                    stale_while_revalidate: opts.cache_stale_while_revalidate.map(|s| s as u64),
+                    stale_if_error: opts.cache_stale_if_error.map(|s| s as u64),
+                    jwt_auth,
+                    api_key_auth,
+                    basic_auth: opts.basic_auth,
+                    authorize: opts.authorize.unwrap_or_default().into_iter()
+                        .map(|a| jwt::ClaimRequirement::new(a.claim.as_deref(), a.values, a.mode.as_deref()).map_err(Error::from_reason))
+                        .collect::<Result<_>>()?,
+                    client_cert: opts.client_cert.map(|c| tls::ClientCertPolicy::new(
+                        c.subjects.unwrap_or_default(),
+                        c.sans.unwrap_or_default(),
+                        c.fingerprints.unwrap_or_default(),
+                    )),
+                    schema: opts.schema,
+                    security_headers: opts.security_headers.map(SecurityHeadersOptions::into_policy).transpose()?.map(Arc::new),
+                    cors: opts.cors.map(CorsOptions::into_policy).transpose()?.map(Arc::new),
+                    csrf_exempt: opts.csrf_exempt.unwrap_or(false),
+                    priority: opts.priority,
+                    slo_target: opts.slo_target.map(|t| t as u64),
+                    pattern: String::new(),
+                }
+            }
+            None => router::RoutePolicies::default(),
+        })
     }
 }
 
@@ -54,9 +485,15 @@ impl From<Option<RouteOptions>> for router::RoutePolicies {
 impl NativeEngine {
     #[napi(constructor)]
     pub fn new(port: u16) -> Self {
+        let db_manager = database::DatabaseManager::new();
+        let server = server::NativeServer::new(port);
+        let query_cache = db_manager.query_cache();
+        let stats = query_cache.clone();
+        server.add_metrics_cache(move || stats.stats());
         Self {
-            server: Arc::new(Mutex::new(server::NativeServer::new(port))),
-            db_manager: Arc::new(tokio::sync::Mutex::new(database::DatabaseManager::new())),
+            server: Arc::new(Mutex::new(server)),
+            query_cache,
+            db_manager: Arc::new(tokio::sync::Mutex::new(db_manager)),
         }
     }
 
@@ -92,12 +529,65 @@ impl NativeEngine {
         Ok(())
     }
 
+    /// Tunes how rate limiting and the shared cache behave when Redis is slow or down.
+    #[napi]
+    pub fn configure_redis(&self, options: RedisOptions) -> Result<()> {
+        let defaults = redis_pool::RedisSettings::default();
+        let settings = redis_pool::RedisSettings {
+            failure_mode: options.failure_mode.as_deref()
+                .map(redis_pool::FailureMode::from_str)
+                .transpose()
+                .map_err(Error::from_reason)?
+                .unwrap_or(defaults.failure_mode),
+            timeout: options.timeout_ms.map(|ms| Duration::from_millis(ms as u64)).unwrap_or(defaults.timeout),
+            breaker_threshold: options.breaker_threshold.map(|n| n.max(1)).unwrap_or(defaults.breaker_threshold),
+            breaker_cooldown: options.breaker_cooldown_ms.map(|ms| Duration::from_millis(ms as u64)).unwrap_or(defaults.breaker_cooldown),
+        };
+        let server = self.server.lock().unwrap();
+        server.configure_redis(settings);
+        Ok(())
+    }
+
     #[napi]
     pub fn get_metrics(&self) -> String {
         let server = self.server.lock().unwrap();
         server.get_metrics()
     }
 
+    /// Evicts cached route responses whose path matches `pattern`: an exact path or
+    /// a `*` wildcard such as "/blog/*". Returns the number of entries removed. With the
+    /// Redis tier enabled the purge also reaches Redis and every other instance.
+    #[napi]
+    pub async fn cache_purge(&self, pattern: String) -> Result<u32> {
+        let server = self.server.lock().unwrap().clone();
+        server.cache_purge(&pattern).await.map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Evicts cached route responses whose handler set `tag` in the Cache-Tag header.
+    #[napi]
+    pub async fn cache_purge_tag(&self, tag: String) -> Result<u32> {
+        let server = self.server.lock().unwrap().clone();
+        server.cache_purge_tag(&tag).await.map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Sets byte budgets for the response, rate-limit and SQL query caches and how often
+    /// expired entries are swept. Shrinking a budget evicts immediately.
+    #[napi]
+    pub fn configure_cache(&self, options: CacheOptions) {
+        if let Some(max) = options.query_max_bytes {
+            self.query_cache.set_max_bytes(max as usize);
+        }
+        let server = self.server.lock().unwrap();
+        server.configure_cache(
+            options.response_max_bytes.map(|m| m as usize),
+            options.rate_limit_max_bytes.map(|m| m as usize),
+            options.sweep_interval_ms.map(|i| i as u64),
+        );
+        if options.redis.unwrap_or(false) {
+            server.enable_shared_cache(options.redis_prefix.unwrap_or_else(|| "qhttpx:cache:".to_string()));
+        }
+    }
+
     #[napi]
     pub async fn redis_set(&self, key: String, value: String, ttl: Option<u32>) -> Result<()> {
         let db = self.db_manager.lock().await;
@@ -117,15 +607,27 @@ impl NativeEngine {
     }
 
     #[napi]
-    pub async fn query_db(&self, sql: String, ttl: Option<u32>) -> Result<String> {
+    pub async fn query_db(&self, sql: String, ttl: Option<u32>, tags: Option<Vec<String>>) -> Result<String> {
         let db = self.db_manager.lock().await;
-        db.query_with_cache(&sql, ttl).await
+        db.query_with_cache(&sql, ttl, tags.unwrap_or_default()).await
     }
 
     #[napi]
-    pub async fn query_db_with_params(&self, sql: String, params: Vec<serde_json::Value>, ttl: Option<u32>) -> Result<String> {
+    pub async fn query_db_with_params(&self, sql: String, params: Vec<serde_json::Value>, ttl: Option<u32>, tags: Option<Vec<String>>) -> Result<String> {
         let db = self.db_manager.lock().await;
-        db.query_with_params_and_cache(&sql, params, ttl).await
+        db.query_with_params_and_cache(&sql, params, ttl, tags.unwrap_or_default()).await
+    }
+
+    /// Drops cached query results whose SQL matches `pattern` (`*` wildcard, e.g. "*FROM posts*").
+    #[napi]
+    pub fn query_cache_purge(&self, pattern: String) -> u32 {
+        self.query_cache.purge(&pattern) as u32
+    }
+
+    /// Drops cached query results tagged with `tag`.
+    #[napi]
+    pub fn query_cache_purge_tag(&self, tag: String) -> u32 {
+        self.query_cache.purge_tag(&tag) as u32
     }
 
     #[napi]
@@ -137,39 +639,143 @@ impl NativeEngine {
     #[napi]
     pub fn set_jwt_secret(&self, secret: String) -> Result<()> {
         let server = self.server.lock().unwrap();
-        server.set_jwt_secret(secret);
+        server.set_jwt_secret(secret).map_err(Error::from_reason)
+    }
+
+    /// Replaces all JWT verification keys: HMAC secret, PEM public keys and JWKS
+    /// documents, plus the algorithm allow-list. Returns the number of keys loaded.
+    #[napi]
+    pub fn configure_jwt(&self, options: JwtOptions) -> Result<u32> {
+        let config = options.into_config()?;
+        let server = self.server.lock().unwrap();
+        server.configure_jwt(config).map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Rotates keys without a restart: re-reads key files and, if `jwks` is given,
+    /// replaces the JWKS document passed in earlier. On error the current keys stay.
+    #[napi]
+    pub fn refresh_jwt_keys(&self, jwks: Option<String>) -> Result<u32> {
+        let server = self.server.lock().unwrap();
+        server.refresh_jwt_keys(jwks).map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Loads keys for `api_key_auth` routes, replacing the current set unless `replace`
+    /// is false. Returns the number of keys held.
+    #[napi]
+    pub fn set_api_keys(&self, keys: Vec<ApiKeyOptions>, replace: Option<bool>) -> Result<u32> {
+        let records = keys.into_iter().map(ApiKeyOptions::into_record).collect();
+        let server = self.server.lock().unwrap();
+        server.api_keys().load(records, replace.unwrap_or(true)).map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Loads API keys from the connected SQL database. Each row needs a `key` or
+    /// `key_hash` column (hex SHA-256); all other columns become key metadata.
+    #[napi]
+    pub async fn load_api_keys_from_sql(&self, sql: String, replace: Option<bool>) -> Result<u32> {
+        let rows = {
+            let db = self.db_manager.lock().await;
+            db.query_with_params(&sql, vec![]).await?
+        };
+        let rows: Vec<serde_json::Map<String, serde_json::Value>> = serde_json::from_str(&rows)
+            .map_err(|e| Error::from_reason(e.to_string()))?;
+        let records = rows.into_iter().map(api_key::ApiKeyRecord::from_fields).collect();
+        let server = self.server.lock().unwrap().clone();
+        server.api_keys().load(records, replace.unwrap_or(true)).map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Loads API keys from Redis hashes stored under `{prefix}{hex sha256}`; the hash
+    /// fields become key metadata.
+    #[napi]
+    pub async fn load_api_keys_from_redis(&self, prefix: String, replace: Option<bool>) -> Result<u32> {
+        let hashes = {
+            let db = self.db_manager.lock().await;
+            db.redis_hashes(&prefix).await?
+        };
+        let records = hashes.into_iter().map(|(key_hash, fields)| {
+            let mut fields: serde_json::Map<String, serde_json::Value> = fields.into_iter()
+                .map(|(name, value)| (name, value.into()))
+                .collect();
+            fields.entry("key_hash").or_insert_with(|| key_hash.into());
+            api_key::ApiKeyRecord::from_fields(fields)
+        }).collect();
+        let server = self.server.lock().unwrap().clone();
+        server.api_keys().load(records, replace.unwrap_or(true)).map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    #[napi]
+    pub fn revoke_api_key(&self, key: String) -> bool {
+        let server = self.server.lock().unwrap();
+        server.api_keys().revoke(&key)
+    }
+
+    /// Registers (or replaces) a Basic auth realm from an htpasswd file or its contents.
+    /// Only bcrypt and argon2 hashes are accepted.
+    #[napi]
+    pub fn add_basic_auth_realm(&self, realm: String, htpasswd_path: Option<String>, htpasswd: Option<String>) -> Result<()> {
+        let realm = match (htpasswd_path, htpasswd) {
+            (Some(path), _) => basic_auth::Realm::load(&realm, &path),
+            (None, Some(content)) => basic_auth::Realm::parse(&realm, &content, None),
+            (None, None) => Err("Basic auth realm needs an htpasswd path or contents".to_string()),
+        }.map_err(Error::from_reason)?;
+        let server = self.server.lock().unwrap();
+        server.basic_auth().add_realm(realm);
+        Ok(())
+    }
+
+    /// Re-reads the htpasswd files of all file-backed realms.
+    #[napi]
+    pub fn reload_basic_auth(&self) -> Result<u32> {
+        let server = self.server.lock().unwrap();
+        server.basic_auth().reload().map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Puts the built-in /metrics endpoint behind a Basic auth realm (`None` opens it again).
+    #[napi]
+    pub fn protect_metrics(&self, realm: Option<String>) -> Result<()> {
+        let server = self.server.lock().unwrap();
+        if let Some(name) = realm.as_deref().filter(|name| !server.basic_auth().has_realm(name)) {
+            return Err(Error::from_reason(format!("Unknown Basic auth realm: {}", name)));
+        }
+        server.basic_auth().protect_metrics(realm);
         Ok(())
     }
 
+    /// Header carrying the key on `api_key_auth` routes, default "X-API-Key".
+    #[napi]
+    pub fn set_api_key_header(&self, header: String) {
+        let server = self.server.lock().unwrap();
+        server.api_keys().set_header(&header);
+    }
+
     #[napi]
     pub fn register_route(&self, method: String, path: String, handler_id: u32, options: Option<RouteOptions>) -> Result<()> {
         let server = self.server.lock().unwrap();
-        let policies = options.into();
-        server.add_route(&method, &path, RouteAction::JsHandler { id: handler_id, policies }).map_err(|e| Error::from_reason(e))
+        let policies = options.try_into()?;
+        server.add_route(&method, &path, RouteAction::JsHandler { id: handler_id, policies }).map_err(Error::from_reason)
     }
 
     #[napi]
     pub fn register_static_route(&self, method: String, path: String, content: String, content_type: String, options: Option<RouteOptions>) -> Result<()> {
         let server = self.server.lock().unwrap();
-        let policies = options.into();
-        server.add_route(&method, &path, RouteAction::Static { content, content_type, policies }).map_err(|e| Error::from_reason(e))
+        let policies = options.try_into()?;
+        server.add_route(&method, &path, RouteAction::Static { content, content_type, policies }).map_err(Error::from_reason)
     }
 
     #[napi]
     pub fn register_json_route(&self, method: String, path: String, content: String, options: Option<RouteOptions>) -> Result<()> {
         let server = self.server.lock().unwrap();
-        let policies = options.into();
-        server.add_route(&method, &path, RouteAction::Json { content, policies }).map_err(|e| Error::from_reason(e))
+        let policies = options.try_into()?;
+        server.add_route(&method, &path, RouteAction::Json { content, policies }).map_err(Error::from_reason)
     }
 
     #[napi]
     pub fn register_upload_route(&self, method: String, path: String, dir: String, handler_id: Option<u32>, options: Option<RouteOptions>) -> Result<()> {
         let server = self.server.lock().unwrap();
-        let policies = options.into();
-        server.add_route(&method, &path, RouteAction::Upload { dir, handler_id, policies }).map_err(|e| Error::from_reason(e))
+        let policies = options.try_into()?;
+        server.add_route(&method, &path, RouteAction::Upload { dir, handler_id, policies }).map_err(Error::from_reason)
     }
 
-    #[napi(ts_args_type = "callback: (event: { handlerId: number, reqId: string, params: string[], query: string, headers: string[], method: string, url: string, body: Buffer, responseHandle: External }) => void")]
+    #[napi(ts_args_type = "callback: (event: { handlerId: number, reqId: string, params: string[], query: string, headers: string[], method: string, url: string, body: Buffer, user?: Record<string, any>, apiKey?: Record<string, any>, clientCert?: Record<string, any>, cspNonce?: string, csrfToken?: string, cookies: Record<string, string>, session?: Record<string, any>, responseHandle: External }) => void")]
     pub fn set_handler(&self, callback: JsFunction) -> Result<()> {
         let tsfn: ThreadsafeFunction<RequestEvent, ErrorStrategy::Fatal> = callback
             .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<RequestEvent>| {
@@ -199,6 +805,26 @@ impl NativeEngine {
                 let body_buffer = ctx.env.create_buffer_with_data(ctx.value.body)?.into_raw();
                 obj.set("body", body_buffer)?;
 
+                if let Some(user) = &ctx.value.user {
+                    obj.set("user", ctx.env.to_js_value(user)?)?;
+                }
+                if let Some(api_key) = &ctx.value.api_key {
+                    obj.set("apiKey", ctx.env.to_js_value(api_key)?)?;
+                }
+                if let Some(client_cert) = &ctx.value.client_cert {
+                    obj.set("clientCert", ctx.env.to_js_value(client_cert)?)?;
+                }
+                if let Some(nonce) = &ctx.value.csp_nonce {
+                    obj.set("cspNonce", nonce.as_str())?;
+                }
+                if let Some(token) = &ctx.value.csrf_token {
+                    obj.set("csrfToken", token.as_str())?;
+                }
+                obj.set("cookies", ctx.env.to_js_value(&ctx.value.cookies)?)?;
+                if let Some(session) = &ctx.value.session {
+                    obj.set("session", ctx.env.to_js_value(session)?)?;
+                }
+
                 let external = ctx.env.create_external(ctx.value.response_sender, None)?;
                 obj.set("responseHandle", external)?;
 
@@ -210,7 +836,7 @@ impl NativeEngine {
         Ok(())
     }
 
-    #[napi(ts_args_type = "callback: (event: { socketId: string, eventType: string, payload?: string, path?: string }) => void")]
+    #[napi(ts_args_type = "callback: (event: { socketId: string, eventType: string, payload?: string, path?: string, user?: Record<string, any> }) => void")]
     pub fn set_ws_handler(&self, callback: JsFunction) -> Result<()> {
         let tsfn: ThreadsafeFunction<WsEvent, ErrorStrategy::Fatal> = callback
             .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<WsEvent>| {
@@ -223,6 +849,9 @@ impl NativeEngine {
                 if let Some(path) = ctx.value.path {
                     obj.set("path", path.as_str())?;
                 }
+                if let Some(user) = &ctx.value.user {
+                    obj.set("user", ctx.env.to_js_value(user)?)?;
+                }
                 Ok(vec![obj])
             })?;
             
@@ -256,10 +885,120 @@ impl NativeEngine {
         Ok(())
     }
 
+    /// Checks unsafe requests on every route without `csrf_exempt`; null turns it off.
+    #[napi]
+    pub fn set_csrf(&self, options: Option<CsrfOptions>) -> Result<()> {
+        let policy = options.map(CsrfOptions::into_policy).transpose()?;
+        let server = self.server.lock().unwrap();
+        server.set_csrf(policy);
+        Ok(())
+    }
+
+    /// Sets the CORS policy for routes without their own; null turns CORS off.
     #[napi]
-    pub fn set_cors(&self, origin: String, methods: String, headers: String, credentials: bool) -> Result<()> {
+    pub fn set_cors(&self, options: Option<CorsOptions>) -> Result<()> {
+        let policy = options.map(CorsOptions::into_policy).transpose()?;
         let server = self.server.lock().unwrap();
-        server.set_cors(origin, methods, headers, credentials);
+        server.set_cors(policy);
+        Ok(())
+    }
+
+    /// Secrets (32+ bytes each) for signed and encrypted cookies and session ids. The
+    /// first one signs and encrypts; the rest are still accepted, for rotation.
+    #[napi]
+    pub fn set_cookie_keys(&self, secrets: Option<Vec<String>>) -> Result<()> {
+        let keys = match secrets {
+            Some(secrets) => {
+                let secrets: Vec<Vec<u8>> = secrets.into_iter().map(String::into_bytes).collect();
+                Some(cookies::CookieKeys::new(&secrets).map_err(Error::from_reason)?)
+            }
+            None => None,
+        };
+        let server = self.server.lock().unwrap();
+        server.sessions().set_keys(keys);
+        Ok(())
+    }
+
+    fn cookie_keys(&self) -> Result<Arc<cookies::CookieKeys>> {
+        let server = self.server.lock().unwrap();
+        server.sessions().keys().ok_or_else(|| Error::from_reason("Cookie keys are not set; call set_cookie_keys first"))
+    }
+
+    #[napi]
+    pub fn sign_cookie(&self, name: String, value: String) -> Result<String> {
+        Ok(self.cookie_keys()?.sign(&name, &value))
+    }
+
+    /// The original value, or null if the signature does not match any key.
+    #[napi]
+    pub fn unsign_cookie(&self, name: String, value: String) -> Result<Option<String>> {
+        Ok(self.cookie_keys()?.unsign(&name, &value))
+    }
+
+    #[napi]
+    pub fn encrypt_cookie(&self, name: String, value: String) -> Result<String> {
+        self.cookie_keys()?.encrypt(&name, &value).ok_or_else(|| Error::from_reason("Cookie encryption failed"))
+    }
+
+    /// The plaintext, or null if the value was tampered with or no key can open it.
+    #[napi]
+    pub fn decrypt_cookie(&self, name: String, value: String) -> Result<Option<String>> {
+        Ok(self.cookie_keys()?.decrypt(&name, &value))
+    }
+
+    /// A `Set-Cookie` header value.
+    #[napi]
+    pub fn serialize_cookie(&self, name: String, value: String, options: Option<CookieOptions>) -> Result<String> {
+        let options = options.unwrap_or_default();
+        let max_age = options.max_age.map(|s| Duration::from_secs(s as u64));
+        let header = options.attributes()?.set_cookie(&name, &value, max_age).map_err(Error::from_reason)?;
+        Ok(header.to_str().unwrap_or_default().to_string())
+    }
+
+    /// Loads a session for every JS handler and saves what it stages with save_session.
+    /// Null turns sessions off.
+    #[napi]
+    pub fn configure_sessions(&self, options: Option<SessionOptions>) -> Result<()> {
+        let config = match options {
+            Some(options) => {
+                let defaults = session::SessionConfig::default();
+                let backend = match options.store.as_deref().unwrap_or("memory") {
+                    "memory" => session::SessionBackend::Memory,
+                    "redis" => session::SessionBackend::Redis {
+                        prefix: options.redis_prefix.unwrap_or_else(|| "qhttpx:session:".to_string()),
+                    },
+                    other => return Err(Error::from_reason(format!("Unknown session store: {}", other))),
+                };
+                Some(session::SessionConfig {
+                    cookie_name: options.cookie_name.unwrap_or(defaults.cookie_name),
+                    ttl: options.ttl.map(|s| Duration::from_secs(s as u64)).unwrap_or(defaults.ttl),
+                    sliding: options.sliding.unwrap_or(defaults.sliding),
+                    attributes: options.cookie.as_ref().map(CookieOptions::attributes).transpose()?.unwrap_or(defaults.attributes),
+                    backend,
+                })
+            }
+            None => None,
+        };
+        let server = self.server.lock().unwrap();
+        if matches!(config, Some(session::SessionConfig { backend: session::SessionBackend::Redis { .. }, .. })) && !server.has_redis() {
+            return Err(Error::from_reason("Redis session store needs a Redis connection (call connect_redis first)"));
+        }
+        server.sessions().configure(config);
+        Ok(())
+    }
+
+    /// Stages the session for request `req_id`, written after the response. Null destroys
+    /// it; `regenerate` moves it to a new id (do this on login).
+    #[napi]
+    pub fn save_session(&self, req_id: String, data: Option<serde_json::Value>, regenerate: Option<bool>) -> Result<()> {
+        let update = match data {
+            Some(data) if data.is_object() => session::SessionUpdate::Save { data, regenerate: regenerate.unwrap_or(false) },
+            Some(_) => return Err(Error::from_reason("Session data must be an object")),
+            None => session::SessionUpdate::Destroy,
+        };
+        let server = self.server.lock().unwrap();
+        // A save that arrives after the response was sent has nothing left to attach to
+        server.sessions().stage(&req_id, update);
         Ok(())
     }
 
@@ -270,35 +1009,179 @@ impl NativeEngine {
         Ok(())
     }
 
+    /// Replaces the global security header policy. Fields left out keep the recommended
+    /// defaults (nosniff, SAMEORIGIN framing, strict-origin-when-cross-origin); null disables all.
+    #[napi]
+    pub fn set_security_policy(&self, options: Option<SecurityHeadersOptions>) -> Result<()> {
+        let policy = options.map(|o| o.into_policy()).transpose()?
+            .map(|policy| security_headers::SecurityHeaders::recommended().overlay(&policy));
+        let server = self.server.lock().unwrap();
+        server.set_security_policy(policy);
+        Ok(())
+    }
+
+    /// Proxies (addresses or CIDR ranges) whose `X-Forwarded-For` is believed when
+    /// resolving the client IP for rate limiting.
     #[napi]
-    pub fn set_tls(&self, cert_path: String, key_path: String) -> Result<()> {
+    pub fn set_trusted_proxies(&self, proxies: Vec<String>) -> Result<()> {
+        let proxies = rate_limit::TrustedProxies::parse(&proxies).map_err(Error::from_reason)?;
         let server = self.server.lock().unwrap();
-        server.set_tls(cert_path, key_path);
+        server.set_trusted_proxies(proxies);
         Ok(())
     }
 
+    /// The default certificate. `ocsp_path` is a DER OCSP response to staple.
+    #[napi]
+    pub fn set_tls(&self, cert_path: String, key_path: String, ocsp_path: Option<String>) -> Result<()> {
+        let server = self.server.lock().unwrap();
+        server.set_tls(cert_path, key_path, ocsp_path);
+        Ok(())
+    }
+
+    /// An extra certificate served to clients whose SNI matches one of `hostnames`.
+    #[napi]
+    pub fn add_tls_certificate(&self, options: TlsCertificateOptions) -> Result<()> {
+        if options.hostnames.is_empty() {
+            return Err(Error::from_reason("TLS certificate needs at least one hostname (use set_tls for the default)"));
+        }
+        let server = self.server.lock().unwrap();
+        server.add_tls_certificate(tls::CertSource {
+            hostnames: options.hostnames,
+            cert_path: options.cert_path,
+            key_path: options.key_path,
+            ocsp_path: options.ocsp_path,
+        });
+        Ok(())
+    }
+
+    /// Restricts protocol versions and cipher suites and configures ALPN and session
+    /// resumption. Takes effect at start() or the next reload_tls(). Passing new
+    /// ticket_keys here and reloading rotates them.
+    #[napi]
+    pub fn set_tls_policy(&self, options: TlsPolicyOptions) -> Result<()> {
+        let policy = options.into_policy()?;
+        let server = self.server.lock().unwrap();
+        server.set_tls_policy(policy).map_err(Error::from_reason)
+    }
+
+    /// Sends `Strict-Transport-Security` on every response served over TLS. Pass null to stop.
+    #[napi]
+    pub fn set_hsts(&self, options: Option<HstsOptions>) -> Result<()> {
+        let policy = options.map(|o| https::HstsPolicy::new(
+            o.max_age.unwrap_or(31_536_000) as u64,
+            o.include_subdomains.unwrap_or(false),
+            o.preload.unwrap_or(false),
+        )).transpose().map_err(Error::from_reason)?;
+        let server = self.server.lock().unwrap();
+        server.set_hsts(policy);
+        Ok(())
+    }
+
+    /// Also listens on `port` for plain HTTP and redirects every request there to HTTPS.
+    /// Bound at start().
+    #[napi]
+    pub fn enable_https_redirect(&self, port: u16, options: Option<HttpsRedirectOptions>) -> Result<()> {
+        let server = self.server.lock().unwrap();
+        let options = options.unwrap_or(HttpsRedirectOptions { https_port: None, host: None, status: None });
+        let redirect = https::HttpsRedirect {
+            port,
+            https_port: options.https_port.unwrap_or(server.port()),
+            host: options.host,
+            status: https::HttpsRedirect::status_from(options.status).map_err(Error::from_reason)?,
+        };
+        server.set_https_redirect(Some(redirect));
+        Ok(())
+    }
+
+    /// Re-reads every TLS certificate, key, OCSP response, client CA and CRL without a restart.
+    /// Returns the number of certificates loaded.
+    #[napi]
+    pub fn reload_tls(&self) -> Result<u32> {
+        let server = self.server.lock().unwrap();
+        server.reload_tls().map(|n| n as u32).map_err(Error::from_reason)
+    }
+
+    /// Reloads TLS files automatically when they change, polling every `interval_ms`
+    /// (default 5000). Pass 0 to turn it off. Takes effect at start().
+    #[napi]
+    pub fn watch_tls(&self, interval_ms: Option<u32>) -> Result<()> {
+        let interval = match interval_ms.unwrap_or(5000) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms as u64)),
+        };
+        let server = self.server.lock().unwrap();
+        server.watch_tls(interval);
+        Ok(())
+    }
+
+    /// Requires (or, with mode "optional", requests) client certificates on the HTTPS listener.
+    /// Pass null to turn mutual TLS off again. Takes effect at start().
+    #[napi]
+    pub fn set_client_auth(&self, options: Option<ClientAuthOptions>) -> Result<()> {
+        let config = options.map(|o| tls::ClientAuthConfig {
+            ca_path: o.ca_path,
+            mode: o.mode.as_deref().map(tls::ClientAuthMode::from_str).unwrap_or_default(),
+            crl_paths: o.crl_paths.unwrap_or_default(),
+        });
+        let server = self.server.lock().unwrap();
+        server.set_client_auth(config);
+        Ok(())
+    }
+
+    #[napi]
+    pub fn add_static_route(&self, prefix: String, dir: String, options: Option<StaticOptions>) -> Result<()> {
+        let symlinks = options
+            .and_then(|o| o.symlinks)
+            .map(|s| static_files::SymlinkPolicy::from_str(&s))
+            .transpose()
+            .map_err(Error::from_reason)?
+            .unwrap_or(static_files::SymlinkPolicy::WithinRoot);
+        let mount = static_files::StaticMount::new(prefix, &dir, symlinks)
+            .map_err(|e| Error::from_reason(format!("Invalid static directory {}: {}", dir, e)))?;
+        let server = self.server.lock().unwrap();
+        server.add_static_route(mount);
+        Ok(())
+    }
+
+    /// Serves a map of relative path -> contents from memory under `prefix`.
+    #[napi]
+    pub fn add_static_bundle(&self, prefix: String, files: std::collections::HashMap<String, Buffer>) -> Result<u32> {
+        let files = files.into_iter().map(|(path, data)| (path, data.to_vec())).collect();
+        let bundle = static_files::AssetBundle::from_files(files).map_err(Error::from_reason)?;
+        let count = bundle.len() as u32;
+        let server = self.server.lock().unwrap();
+        server.add_static_route(static_files::StaticMount::bundle(prefix, bundle));
+        Ok(count)
+    }
+
+    /// Serves the regular files of a tar archive from memory under `prefix`.
     #[napi]
-    pub fn add_static_route(&self, prefix: String, dir: String) {
+    pub fn add_static_bundle_tar(&self, prefix: String, archive: Buffer) -> Result<u32> {
+        let bundle = static_files::AssetBundle::from_tar(&archive).map_err(Error::from_reason)?;
+        let count = bundle.len() as u32;
         let server = self.server.lock().unwrap();
-        server.add_static_route(prefix, dir);
+        server.add_static_route(static_files::StaticMount::bundle(prefix, bundle));
+        Ok(count)
     }
 
+    /// `headers` lets the handler set response headers, e.g. `Cache-Tag` or `Cache-Control`,
+    /// as flat name/value pairs; a name may repeat.
     #[napi]
-    pub fn send_response(&self, handle: External<Mutex<Option<server::ResponseSender>>>, status: u16, body: String) -> Result<()> {
+    pub fn send_response(&self, handle: External<Mutex<Option<server::ResponseSender>>>, status: u16, body: String, headers: Option<Vec<String>>) -> Result<()> {
         let server = self.server.lock().unwrap();
-        server.send_response(&handle, status, body).map_err(|e| Error::from_reason(e))
+        server.send_response(&handle, status, body, headers).map_err(Error::from_reason)
     }
 
     #[napi]
-    pub fn send_json(&self, handle: External<Mutex<Option<server::ResponseSender>>>, status: u16, body: serde_json::Value) -> Result<()> {
+    pub fn send_json(&self, handle: External<Mutex<Option<server::ResponseSender>>>, status: u16, body: serde_json::Value, headers: Option<Vec<String>>) -> Result<()> {
         let json_string = serde_json::to_string(&body).map_err(|e| Error::from_reason(e.to_string()))?;
-        self.send_response(handle, status, json_string)
+        self.send_response(handle, status, json_string, headers)
     }
 
     #[napi]
-    pub fn send_html(&self, handle: External<Mutex<Option<server::ResponseSender>>>, status: u16, body: String) -> Result<()> {
+    pub fn send_html(&self, handle: External<Mutex<Option<server::ResponseSender>>>, status: u16, body: String, headers: Option<Vec<String>>) -> Result<()> {
         let server = self.server.lock().unwrap();
-        server.send_html(&handle, status, body).map_err(|e| Error::from_reason(e))
+        server.send_html(&handle, status, body, headers).map_err(Error::from_reason)
     }
 
     #[napi]
@@ -313,6 +1196,7 @@ impl NativeEngine {
             let guard = self.server.lock().unwrap();
             guard.clone()
         };
+        self.query_cache.spawn_sweeper(server.cache_sweep_interval());
 
         match server.start().await {
             Ok(_) => Ok(()),
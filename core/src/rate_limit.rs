@@ -0,0 +1,609 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use crate::cache::{BoundedCache, Weigh};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Algorithm {
+    #[default]
+    FixedWindow,   // `limit` per window, window starts at the first request
+    SlidingLog,    // `limit` in any trailing window, exact (one timestamp per request)
+    SlidingWindow, // Previous window weighted by overlap + current window, O(1) state
+    TokenBucket,   // Refills `rate` tokens/sec up to `burst`
+}
+
+impl Algorithm {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "fixed_window" => Ok(Algorithm::FixedWindow),
+            "sliding_log" => Ok(Algorithm::SlidingLog),
+            "sliding_window" => Ok(Algorithm::SlidingWindow),
+            "token_bucket" => Ok(Algorithm::TokenBucket),
+            _ => Err(format!("Unknown rate limit algorithm \"{}\" (expected fixed_window, sliding_window, sliding_log or token_bucket)", s)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::FixedWindow => "fixed",
+            Algorithm::SlidingLog => "log",
+            Algorithm::SlidingWindow => "sliding",
+            Algorithm::TokenBucket => "bucket",
+        }
+    }
+}
+
+/// What a limit counts against. Header, subject and API key keys fall back to the
+/// client IP when the request has no such header or is not authenticated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum LimitKey {
+    #[default]
+    Ip,
+    Header(String), // Lowercase name, e.g. "x-api-key"
+    Subject,        // JWT `sub` claim
+    ApiKey,         // The API key's id (or owner) on api_key_auth routes
+    Global,         // One bucket shared by every client of the route (or path)
+}
+
+impl LimitKey {
+    /// "ip" | "header:<name>" | "sub" | "api_key" | "global"
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            Some((kind, name)) if kind.trim().eq_ignore_ascii_case("header") => {
+                let name = name.trim().to_ascii_lowercase();
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("Invalid rate limit header name: \"{}\"", name))?;
+                Ok(LimitKey::Header(name))
+            }
+            _ => match s.trim().to_ascii_lowercase().as_str() {
+                "ip" => Ok(LimitKey::Ip),
+                "sub" | "jwt" | "subject" => Ok(LimitKey::Subject),
+                "api_key" | "apikey" | "key" => Ok(LimitKey::ApiKey),
+                "global" => Ok(LimitKey::Global),
+                _ => Err(format!("Unknown rate limit key \"{}\" (expected ip, header:<name>, sub, api_key or global)", s)),
+            },
+        }
+    }
+}
+
+/// The parts of a request a limit key can be built from.
+pub struct RequestIdentity<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub pattern: &'a str, // Matched route, e.g. "/users/:id"
+    pub ip: IpAddr,
+    pub headers: &'a HeaderMap,
+    pub subject: Option<&'a str>,
+    pub api_key: Option<&'a str>, // ApiKey::id
+}
+
+/// Proxies allowed to report the client address in `X-Forwarded-For`.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    /// Accepts addresses ("10.0.0.1") and CIDR ranges ("10.0.0.0/8", "fd00::/8").
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        entries.iter().map(|entry| {
+            let (addr, bits) = match entry.split_once('/') {
+                Some((addr, bits)) => (addr, Some(bits)),
+                None => (entry.as_str(), None),
+            };
+            let addr: IpAddr = addr.trim().parse().map_err(|_| format!("Invalid proxy address: {}", entry))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let bits = match bits {
+                Some(bits) => bits.trim().parse::<u8>().ok().filter(|b| *b <= max).ok_or(format!("Invalid prefix length: {}", entry))?,
+                None => max,
+            };
+            Ok((addr, bits))
+        }).collect::<Result<_, _>>().map(TrustedProxies)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.0.iter().any(|&(net, bits)| match (canonical(net), ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(&net.octets(), &ip.octets(), bits),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_eq(&net.octets(), &ip.octets(), bits),
+            _ => false,
+        })
+    }
+
+    /// The client address: the peer itself unless it is a trusted proxy, in which case
+    /// `X-Forwarded-For` is walked from the right, skipping trusted hops.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let hops: Vec<IpAddr> = headers.get_all("x-forwarded-for").iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|hop| hop.trim().parse().ok())
+            .collect();
+        hops.into_iter().rev().find(|hop| !self.contains(*hop)).unwrap_or(peer)
+    }
+
+    /// The scheme the client used: "https" over TLS, or when a trusted proxy reports it in
+    /// `X-Forwarded-Proto` (the first entry is the client-facing hop).
+    pub fn scheme(&self, peer: IpAddr, headers: &HeaderMap, tls: bool) -> &'static str {
+        let forwarded_https = self.contains(peer) && headers.get("x-forwarded-proto")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"));
+        if tls || forwarded_https { "https" } else { "http" }
+    }
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], bits: u8) -> bool {
+    let (whole, rest) = ((bits / 8) as usize, bits % 8);
+    if a[..whole] != b[..whole] {
+        return false;
+    }
+    rest == 0 || (a[whole] ^ b[whole]) >> (8 - rest) == 0
+}
+
+/// A route's rate limit. Every algorithm is implemented twice, in `check` for the
+/// in-process store and in a Lua script for Redis, with the same arithmetic on
+/// millisecond timestamps so both paths make the same decisions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    pub algorithm: Algorithm,
+    pub limit: u32,
+    pub window_ms: u64,
+    pub burst: u32,           // Token bucket capacity
+    pub refill_per_sec: f64,  // Token bucket refill rate
+    pub key: LimitKey,
+    pub per_route: bool,      // Count by route pattern instead of concrete path
+}
+
+/// Outcome of a single check; `remaining` is what is left after this request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_ms: u64,       // Until the quota is (partly) restored
+    pub retry_after_ms: u64, // 0 when allowed
+}
+
+impl Decision {
+    /// Sets `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` (IETF
+    /// draft-ietf-httpapi-ratelimit-headers, in seconds), plus `Retry-After` when rejected.
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        let seconds = |ms: u64| ms.div_ceil(1000);
+        headers.insert(HeaderName::from_static("ratelimit-limit"), HeaderValue::from(self.limit));
+        headers.insert(HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(self.remaining));
+        headers.insert(HeaderName::from_static("ratelimit-reset"), HeaderValue::from(seconds(self.reset_ms)));
+        if !self.allowed {
+            headers.insert(RETRY_AFTER, HeaderValue::from(seconds(self.retry_after_ms).max(1)));
+        }
+    }
+}
+
+/// Per-key limiter state kept in the in-process store.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LimiterState {
+    Window { start: u64, count: u32 },
+    Log(VecDeque<u64>),
+    Sliding { start: u64, previous: u32, current: u32 },
+    Bucket { tokens: f64, updated: u64 },
+}
+
+impl Weigh for LimiterState {
+    fn weight(&self) -> usize {
+        match self {
+            LimiterState::Log(stamps) => std::mem::size_of::<Self>() + stamps.len() * 8,
+            _ => std::mem::size_of::<Self>(),
+        }
+    }
+}
+
+impl RateLimit {
+    /// Window algorithms need `limit` and `window_sec`. The token bucket takes `rate` and
+    /// `burst`, defaulting to `limit / window_sec` and `limit`. Ok(None) when nothing is set;
+    /// a partial configuration is an error rather than no limit.
+    pub fn from_options(algorithm: Option<&str>, limit: Option<u32>, window_sec: Option<u64>, rate: Option<f64>, burst: Option<u32>) -> Result<Option<Self>, String> {
+        if algorithm.is_none() && limit.is_none() && window_sec.is_none() && rate.is_none() && burst.is_none() {
+            return Ok(None);
+        }
+        let algorithm = algorithm.map(Algorithm::from_str).transpose()?.unwrap_or_default();
+        let window_ms = window_sec.map(|w| w.max(1) * 1000);
+
+        if algorithm == Algorithm::TokenBucket {
+            let refill_per_sec = match (rate, limit, window_ms) {
+                (Some(rate), _, _) => rate,
+                (None, Some(limit), Some(window_ms)) => limit as f64 * 1000.0 / window_ms as f64,
+                _ => return Err("Token bucket rate limit needs a rate, or a limit and window".to_string()),
+            };
+            let burst = burst.or(limit).ok_or("Token bucket rate limit needs a burst or limit")?;
+            if !(refill_per_sec > 0.0 && refill_per_sec.is_finite()) || burst == 0 {
+                return Err("Token bucket rate and burst must be positive".to_string());
+            }
+            return Ok(Some(Self { algorithm, limit: burst, window_ms: window_ms.unwrap_or(1000), burst, refill_per_sec, key: LimitKey::Ip, per_route: false }));
+        }
+
+        match (limit, window_ms) {
+            (Some(limit), Some(window_ms)) => Ok(Some(Self { algorithm, limit, window_ms, burst: limit, refill_per_sec: 0.0, key: LimitKey::Ip, per_route: false })),
+            _ => Err("Rate limit needs both a limit and a window".to_string()),
+        }
+    }
+
+    pub fn keyed(mut self, key: Option<&str>, per_route: bool) -> Result<Self, String> {
+        self.key = key.map(LimitKey::from_str).transpose()?.unwrap_or_default();
+        self.per_route = per_route;
+        Ok(self)
+    }
+
+    /// Whether the key can only be built once the request is authenticated.
+    pub fn needs_subject(&self) -> bool {
+        matches!(self.key, LimitKey::Subject | LimitKey::ApiKey)
+    }
+
+    /// "GET /users/:id|sub=42|60000". The window is part of the key so stacked limits
+    /// on one route never share state.
+    pub fn key(&self, request: &RequestIdentity) -> String {
+        let scope = if self.per_route { request.pattern } else { request.path };
+        let identity = match &self.key {
+            LimitKey::Global => "*".to_string(),
+            LimitKey::Header(name) => match request.headers.get(name).and_then(|v| v.to_str().ok()) {
+                Some(value) => format!("{}={}", name, value),
+                None => format!("ip={}", request.ip),
+            },
+            LimitKey::Subject => match request.subject {
+                Some(sub) => format!("sub={}", sub),
+                None => format!("ip={}", request.ip),
+            },
+            LimitKey::ApiKey => match request.api_key {
+                Some(id) => format!("key={}", id),
+                None => format!("ip={}", request.ip),
+            },
+            LimitKey::Ip => format!("ip={}", request.ip),
+        };
+        format!("{} {}|{}|{}", request.method, scope, identity, self.window_ms)
+    }
+
+    /// Applies one request to `state` at `now` (unix ms). Returns the new state, how long
+    /// it must be kept and the decision.
+    pub fn check(&self, state: Option<LimiterState>, now: u64) -> (LimiterState, u64, Decision) {
+        let window = self.window_ms;
+        match self.algorithm {
+            Algorithm::FixedWindow => {
+                let (start, mut count) = match state {
+                    Some(LimiterState::Window { start, count }) if now < start + window => (start, count),
+                    _ => (now, 0),
+                };
+                let allowed = count < self.limit;
+                if allowed {
+                    count += 1;
+                }
+                let reset = start + window - now;
+                let decision = self.decision(allowed, self.limit - count, reset, reset);
+                (LimiterState::Window { start, count }, reset, decision)
+            }
+            Algorithm::SlidingLog => {
+                let mut stamps = match state {
+                    Some(LimiterState::Log(stamps)) => stamps,
+                    _ => VecDeque::new(),
+                };
+                while stamps.front().is_some_and(|&t| t + window <= now) {
+                    stamps.pop_front();
+                }
+                let allowed = (stamps.len() as u32) < self.limit;
+                if allowed {
+                    stamps.push_back(now);
+                }
+                // The oldest request leaving the window frees the next slot
+                let reset = stamps.front().map(|&t| t + window - now).unwrap_or(window);
+                let decision = self.decision(allowed, self.limit - stamps.len() as u32, reset, reset);
+                (LimiterState::Log(stamps), window, decision)
+            }
+            Algorithm::SlidingWindow => {
+                let current_start = now - now % window;
+                let (previous, mut current) = match state {
+                    Some(LimiterState::Sliding { start, current, .. }) if start + window == current_start => (current, 0),
+                    Some(LimiterState::Sliding { start, previous, current }) if start == current_start => (previous, current),
+                    _ => (0, 0),
+                };
+                let elapsed = now - current_start;
+                let weight = (window - elapsed) as f64 / window as f64;
+                let estimate = previous as f64 * weight + current as f64;
+                let allowed = estimate + 1.0 <= self.limit as f64;
+                if allowed {
+                    current += 1;
+                }
+                let used = previous as f64 * weight + current as f64;
+                let remaining = (self.limit as f64 - used).floor().max(0.0) as u32;
+                let reset = window - elapsed;
+                let decision = self.decision(allowed, remaining, reset, reset);
+                (LimiterState::Sliding { start: current_start, previous, current }, window + reset, decision)
+            }
+            Algorithm::TokenBucket => {
+                let burst = self.burst as f64;
+                let per_ms = self.refill_per_sec / 1000.0;
+                let mut tokens = match state {
+                    Some(LimiterState::Bucket { tokens, updated }) => (tokens + now.saturating_sub(updated) as f64 * per_ms).min(burst),
+                    _ => burst,
+                };
+                let allowed = tokens >= 1.0;
+                if allowed {
+                    tokens -= 1.0;
+                }
+                let reset = ((burst - tokens) / per_ms).ceil() as u64;
+                let retry = ((1.0 - tokens) / per_ms).ceil().max(0.0) as u64;
+                let decision = self.decision(allowed, tokens.floor() as u32, reset, retry);
+                (LimiterState::Bucket { tokens, updated: now }, reset.max(1), decision)
+            }
+        }
+    }
+
+    fn decision(&self, allowed: bool, remaining: u32, reset_ms: u64, retry_ms: u64) -> Decision {
+        Decision {
+            allowed,
+            limit: self.limit,
+            remaining,
+            reset_ms,
+            retry_after_ms: if allowed { 0 } else { retry_ms },
+        }
+    }
+
+    pub fn check_local(&self, store: &BoundedCache<LimiterState>, key: &str) -> Decision {
+        let key = format!("{}:{}", self.algorithm.name(), key);
+        let now = unix_millis();
+        store.upsert(&key, |state| {
+            let (state, keep_ms, decision) = self.check(state, now);
+            (state, std::time::Instant::now() + Duration::from_millis(keep_ms), decision)
+        })
+    }
+
+    pub async fn check_redis(&self, con: &mut redis::aio::MultiplexedConnection, key: &str) -> redis::RedisResult<Decision> {
+        let key = format!("qhttpx:rl:{}:{}", self.algorithm.name(), key);
+        let mut invocation = script(self.algorithm).key(key);
+        invocation.arg(self.limit).arg(self.window_ms);
+        match self.algorithm {
+            Algorithm::SlidingLog => {
+                invocation.arg(unique_member());
+            }
+            Algorithm::TokenBucket => {
+                invocation.arg(self.burst).arg(self.refill_per_sec / 1000.0);
+            }
+            _ => {}
+        }
+        let (allowed, remaining, reset, retry): (u32, u32, u64, u64) = invocation.invoke_async(con).await?;
+        Ok(self.decision(allowed == 1, remaining, reset, retry))
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// Sorted-set members must be unique even for requests in the same millisecond
+fn unique_member() -> String {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    format!("{}-{}-{}", unix_millis(), std::process::id(), SEQ.fetch_add(1, Ordering::Relaxed))
+}
+
+// Each script reads the clock with TIME so every instance shares Redis' notion of "now",
+// and mirrors the matching arm of `RateLimit::check`. Returns {allowed, remaining, reset_ms, retry_ms}.
+const PRELUDE: &str = r"
+if redis.replicate_commands then redis.replicate_commands() end
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+";
+
+const FIXED_WINDOW: &str = r"
+local state = redis.call('HMGET', KEYS[1], 'start', 'count')
+local start, count = tonumber(state[1]), tonumber(state[2])
+if not start or now >= start + window then start, count = now, 0 end
+local allowed = 0
+if count < limit then allowed = 1; count = count + 1 end
+local reset = start + window - now
+redis.call('HSET', KEYS[1], 'start', start, 'count', count)
+redis.call('PEXPIRE', KEYS[1], math.max(reset, 1))
+local retry = 0
+if allowed == 0 then retry = reset end
+return {allowed, limit - count, reset, retry}
+";
+
+const SLIDING_LOG: &str = r"
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+local allowed = 0
+if count < limit then
+    allowed = 1
+    count = count + 1
+    redis.call('ZADD', KEYS[1], now, ARGV[3])
+end
+local reset = window
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+if oldest[2] then reset = tonumber(oldest[2]) + window - now end
+redis.call('PEXPIRE', KEYS[1], window)
+local retry = 0
+if allowed == 0 then retry = reset end
+return {allowed, limit - count, reset, retry}
+";
+
+const SLIDING_WINDOW: &str = r"
+local current_start = now - (now % window)
+local state = redis.call('HMGET', KEYS[1], 'start', 'previous', 'current')
+local start, previous, current = tonumber(state[1]), tonumber(state[2]), tonumber(state[3])
+if start and start + window == current_start then
+    previous, current = current, 0
+elseif not (start and start == current_start) then
+    previous, current = 0, 0
+end
+local elapsed = now - current_start
+local weight = (window - elapsed) / window
+local allowed = 0
+if previous * weight + current + 1 <= limit then allowed = 1; current = current + 1 end
+local remaining = math.max(math.floor(limit - (previous * weight + current)), 0)
+local reset = window - elapsed
+redis.call('HSET', KEYS[1], 'start', current_start, 'previous', previous, 'current', current)
+redis.call('PEXPIRE', KEYS[1], window + reset)
+local retry = 0
+if allowed == 0 then retry = reset end
+return {allowed, remaining, reset, retry}
+";
+
+const TOKEN_BUCKET: &str = r"
+local burst = tonumber(ARGV[3])
+local per_ms = tonumber(ARGV[4])
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens, updated = tonumber(state[1]), tonumber(state[2])
+if tokens then
+    tokens = math.min(burst, tokens + math.max(now - updated, 0) * per_ms)
+else
+    tokens = burst
+end
+local allowed = 0
+if tokens >= 1 then allowed = 1; tokens = tokens - 1 end
+local reset = math.ceil((burst - tokens) / per_ms)
+local retry = 0
+if allowed == 0 then retry = math.max(math.ceil((1 - tokens) / per_ms), 0) end
+redis.call('HSET', KEYS[1], 'tokens', string.format('%.17g', tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.max(reset, 1))
+return {allowed, math.floor(tokens), reset, retry}
+";
+
+fn script(algorithm: Algorithm) -> &'static redis::Script {
+    static SCRIPTS: OnceLock<[redis::Script; 4]> = OnceLock::new();
+    let scripts = SCRIPTS.get_or_init(|| {
+        [FIXED_WINDOW, SLIDING_LOG, SLIDING_WINDOW, TOKEN_BUCKET].map(|body| redis::Script::new(&format!("{}{}", PRELUDE, body)))
+    });
+    &scripts[match algorithm {
+        Algorithm::FixedWindow => 0,
+        Algorithm::SlidingLog => 1,
+        Algorithm::SlidingWindow => 2,
+        Algorithm::TokenBucket => 3,
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(limit: &RateLimit, times: &[u64]) -> Vec<bool> {
+        let mut state = None;
+        times.iter().map(|&now| {
+            let (next, _, decision) = limit.check(state.take(), now);
+            state = Some(next);
+            decision.allowed
+        }).collect()
+    }
+
+    #[test]
+    fn fixed_window_allows_boundary_burst() {
+        let limit = RateLimit::from_options(None, Some(2), Some(1), None, None).unwrap().unwrap();
+        assert_eq!(run(&limit, &[0, 10, 20, 999, 1000, 1001, 1002]), [true, true, false, false, true, true, false]);
+    }
+
+    #[test]
+    fn sliding_log_counts_trailing_window() {
+        let limit = RateLimit::from_options(Some("sliding_log"), Some(2), Some(1), None, None).unwrap().unwrap();
+        assert_eq!(run(&limit, &[0, 900, 999, 1000, 1001, 1899, 1900]), [true, true, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn sliding_window_weights_previous_window() {
+        let limit = RateLimit::from_options(Some("sliding_window"), Some(10), Some(1), None, None).unwrap().unwrap();
+        let mut state = None;
+        for _ in 0..10 {
+            let (next, _, decision) = limit.check(state.take(), 500);
+            assert!(decision.allowed);
+            state = Some(next);
+        }
+        // Halfway into the next window, half of the previous 10 still count
+        let (next, _, decision) = limit.check(state.take(), 1500);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 4);
+        let mut state = Some(next);
+        let allowed: Vec<bool> = (0..5).map(|_| {
+            let (next, _, decision) = limit.check(state.take(), 1500);
+            state = Some(next);
+            decision.allowed
+        }).collect();
+        assert_eq!(allowed, [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn token_bucket_refills_at_rate_up_to_burst() {
+        let limit = RateLimit::from_options(Some("token_bucket"), None, None, Some(10.0), Some(3)).unwrap().unwrap();
+        assert_eq!(run(&limit, &[0, 0, 0, 0, 100, 100, 10_000, 10_000, 10_000, 10_000]),
+            [true, true, true, false, true, false, true, true, true, false]);
+
+        let (_, _, decision) = limit.check(Some(LimiterState::Bucket { tokens: 0.5, updated: 0 }), 0);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after_ms, 50);
+    }
+
+    #[test]
+    fn client_ip_skips_trusted_hops() {
+        let proxies = TrustedProxies::parse(&["10.0.0.0/8".to_string(), "::1".to_string()]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("6.6.6.6, 203.0.113.7, 10.1.2.3"));
+
+        assert_eq!(proxies.client_ip("10.0.0.1".parse().unwrap(), &headers), "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(proxies.client_ip("::ffff:10.0.0.1".parse().unwrap(), &headers), "203.0.113.7".parse::<IpAddr>().unwrap());
+        // An untrusted peer cannot spoof its address
+        assert_eq!(proxies.client_ip("198.51.100.1".parse().unwrap(), &headers), "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert!(!proxies.contains("11.0.0.1".parse().unwrap()));
+        assert!(TrustedProxies::parse(&["10.0.0.0/33".to_string()]).is_err());
+
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https, http"));
+        assert_eq!(proxies.scheme("10.0.0.1".parse().unwrap(), &headers, false), "https");
+        assert_eq!(proxies.scheme("198.51.100.1".parse().unwrap(), &headers, false), "http");
+        assert_eq!(proxies.scheme("198.51.100.1".parse().unwrap(), &headers, true), "https");
+    }
+
+    #[test]
+    fn key_uses_pattern_and_falls_back_to_ip() {
+        let headers = HeaderMap::new();
+        let request = RequestIdentity { method: "GET", path: "/users/1", pattern: "/users/:id", ip: "1.2.3.4".parse().unwrap(), headers: &headers, subject: None, api_key: None };
+        let limit = RateLimit::from_options(None, Some(1), Some(1), None, None).unwrap().unwrap();
+        assert_eq!(limit.clone().keyed(Some("header:X-Api-Key"), true).unwrap().key(&request), "GET /users/:id|ip=1.2.3.4|1000");
+        assert_eq!(limit.clone().keyed(Some("ip"), false).unwrap().key(&request), "GET /users/1|ip=1.2.3.4|1000");
+        assert_eq!(limit.keyed(Some("global"), false).unwrap().key(&request), "GET /users/1|*|1000");
+    }
+
+    #[test]
+    fn token_bucket_defaults_from_limit_and_window() {
+        let limit = RateLimit::from_options(Some("token-bucket"), Some(60), Some(60), None, None).unwrap().unwrap();
+        assert_eq!((limit.burst, limit.refill_per_sec), (60, 1.0));
+        assert!(RateLimit::from_options(Some("token_bucket"), None, None, Some(1.0), None).is_err());
+    }
+
+    #[test]
+    fn incomplete_limits_and_unknown_keys_are_rejected() {
+        assert!(RateLimit::from_options(None, None, None, None, None).unwrap().is_none());
+        assert!(RateLimit::from_options(None, Some(10), None, None, None).is_err());
+        assert!(RateLimit::from_options(Some("sliding_window"), None, Some(60), None, None).is_err());
+        assert!(RateLimit::from_options(Some("token_bucket"), None, None, None, Some(5)).is_err());
+        assert!(RateLimit::from_options(Some("token_bucket"), None, None, Some(0.0), Some(5)).is_err());
+
+        assert_eq!(LimitKey::from_str("Header: X-Tenant"), Ok(LimitKey::Header("x-tenant".to_string())));
+        for key in ["ipp", "user", "header:", "header:bad name"] {
+            assert!(LimitKey::from_str(key).is_err(), "{} was accepted", key);
+        }
+        let limit = RateLimit::from_options(None, Some(1), Some(1), None, None).unwrap().unwrap();
+        assert!(limit.keyed(Some("tenant"), false).is_err());
+    }
+
+    #[test]
+    fn unknown_algorithms_are_rejected() {
+        assert_eq!(Algorithm::from_str("Fixed-Window"), Ok(Algorithm::FixedWindow));
+        for name in ["tokenbucket", "sliding", "leaky_bucket"] {
+            assert!(Algorithm::from_str(name).is_err(), "{} was accepted", name);
+            assert!(RateLimit::from_options(Some(name), Some(10), Some(1), None, None).is_err());
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use redis::RedisResult;
+use redis::aio::MultiplexedConnection;
+
+/// What rate limiting does when Redis cannot answer.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FailureMode {
+    Open,   // Let the request through unchecked
+    Closed, // Reject with 503
+    #[default]
+    Local,  // Check against the in-process store instead
+}
+
+impl FailureMode {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "open" | "fail_open" => Ok(FailureMode::Open),
+            "closed" | "fail_closed" => Ok(FailureMode::Closed),
+            "local" => Ok(FailureMode::Local),
+            _ => Err(format!("Unknown Redis failure mode \"{}\" (expected open, closed or local)", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RedisSettings {
+    pub failure_mode: FailureMode,
+    pub timeout: Duration,          // Per call, connecting included
+    pub breaker_threshold: u32,     // Consecutive failures that open the circuit
+    pub breaker_cooldown: Duration, // How long it stays open before a probe is let through
+}
+
+impl Default for RedisSettings {
+    fn default() -> Self {
+        Self {
+            failure_mode: FailureMode::Local,
+            timeout: Duration::from_millis(250),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RedisUnavailable {
+    NotConfigured,
+    CircuitOpen,
+    Failed(String),
+}
+
+impl fmt::Display for RedisUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedisUnavailable::NotConfigured => write!(f, "Redis is not configured"),
+            RedisUnavailable::CircuitOpen => write!(f, "Redis circuit breaker is open"),
+            RedisUnavailable::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+enum Breaker {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant }, // One probe in flight; everyone else is still short-circuited
+}
+
+/// One multiplexed connection shared by every request, behind a circuit breaker.
+/// The connection is opened lazily and replaced after transport errors.
+pub struct RedisPool {
+    client: Mutex<Option<redis::Client>>,
+    connection: Mutex<Option<MultiplexedConnection>>,
+    connecting: tokio::sync::Mutex<()>, // Only one task dials at a time
+    breaker: Mutex<Breaker>,
+    settings: Mutex<RedisSettings>,
+    errors: AtomicU64,
+    short_circuited: AtomicU64,
+    trips: AtomicU64,
+}
+
+impl RedisPool {
+    pub fn new() -> Self {
+        Self {
+            client: Mutex::new(None),
+            connection: Mutex::new(None),
+            connecting: tokio::sync::Mutex::new(()),
+            breaker: Mutex::new(Breaker::Closed { failures: 0 }),
+            settings: Mutex::new(RedisSettings::default()),
+            errors: AtomicU64::new(0),
+            short_circuited: AtomicU64::new(0),
+            trips: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: redis::Client) {
+        *self.client.lock().unwrap() = Some(client);
+        *self.connection.lock().unwrap() = None;
+        *self.breaker.lock().unwrap() = Breaker::Closed { failures: 0 };
+    }
+
+    pub fn client(&self) -> Option<redis::Client> {
+        self.client.lock().unwrap().clone()
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.client.lock().unwrap().is_some()
+    }
+
+    pub fn configure(&self, settings: RedisSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    pub fn settings(&self) -> RedisSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    fn admit(&self) -> bool {
+        let cooldown = self.settings().breaker_cooldown;
+        let mut breaker = self.breaker.lock().unwrap();
+        let probe = match *breaker {
+            Breaker::Closed { .. } => return true,
+            Breaker::Open { until } => Instant::now() >= until,
+            // A probe that never reported back (its request was cancelled) must not wedge the breaker
+            Breaker::HalfOpen { since } => since.elapsed() >= cooldown,
+        };
+        if probe {
+            *breaker = Breaker::HalfOpen { since: Instant::now() };
+        }
+        probe
+    }
+
+    fn on_success(&self) {
+        *self.breaker.lock().unwrap() = Breaker::Closed { failures: 0 };
+    }
+
+    fn on_failure(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        let settings = self.settings();
+        let mut breaker = self.breaker.lock().unwrap();
+        let trip = match *breaker {
+            Breaker::Closed { failures } if failures + 1 < settings.breaker_threshold => {
+                *breaker = Breaker::Closed { failures: failures + 1 };
+                false
+            }
+            Breaker::Open { .. } => false,
+            _ => true,
+        };
+        if trip {
+            *breaker = Breaker::Open { until: Instant::now() + settings.breaker_cooldown };
+            self.trips.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn connection(&self) -> RedisResult<MultiplexedConnection> {
+        if let Some(con) = self.connection.lock().unwrap().clone() {
+            return Ok(con);
+        }
+        let _dialing = self.connecting.lock().await;
+        // Someone else may have connected while we waited
+        if let Some(con) = self.connection.lock().unwrap().clone() {
+            return Ok(con);
+        }
+        let client = self.client().ok_or((redis::ErrorKind::ClientError, "Redis is not configured"))?;
+        let con = client.get_multiplexed_async_connection().await?;
+        *self.connection.lock().unwrap() = Some(con.clone());
+        Ok(con)
+    }
+
+    /// Runs `f` on the shared connection, bounded by the configured timeout. Failures
+    /// feed the circuit breaker; while it is open calls fail immediately.
+    pub async fn call<T, F, Fut>(&self, f: F) -> Result<T, RedisUnavailable>
+    where
+        F: FnOnce(MultiplexedConnection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        self.call_with_timeout(self.settings().timeout, f).await
+    }
+
+    pub async fn call_with_timeout<T, F, Fut>(&self, timeout: Duration, f: F) -> Result<T, RedisUnavailable>
+    where
+        F: FnOnce(MultiplexedConnection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        if !self.is_configured() {
+            return Err(RedisUnavailable::NotConfigured);
+        }
+        if !self.admit() {
+            self.short_circuited.fetch_add(1, Ordering::Relaxed);
+            return Err(RedisUnavailable::CircuitOpen);
+        }
+
+        let result = tokio::time::timeout(timeout, async {
+            let con = self.connection().await?;
+            f(con).await
+        }).await;
+
+        match result {
+            Ok(Ok(value)) => {
+                self.on_success();
+                Ok(value)
+            }
+            Ok(Err(e)) => {
+                if e.is_connection_dropped() || e.is_io_error() || e.is_connection_refusal() || e.is_timeout() {
+                    *self.connection.lock().unwrap() = None;
+                }
+                self.on_failure();
+                Err(RedisUnavailable::Failed(e.to_string()))
+            }
+            Err(_) => {
+                self.on_failure();
+                Err(RedisUnavailable::Failed(format!("Redis call timed out after {}ms", timeout.as_millis())))
+            }
+        }
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let open = u8::from(!matches!(*self.breaker.lock().unwrap(), Breaker::Closed { .. }));
+        format!(
+            "# HELP qhttpx_redis_errors_total Redis calls that failed or timed out\n\
+             # TYPE qhttpx_redis_errors_total counter\n\
+             qhttpx_redis_errors_total {}\n\
+             \n\
+             # HELP qhttpx_redis_short_circuited_total Redis calls skipped because the circuit breaker was open\n\
+             # TYPE qhttpx_redis_short_circuited_total counter\n\
+             qhttpx_redis_short_circuited_total {}\n\
+             \n\
+             # HELP qhttpx_redis_circuit_trips_total Times the Redis circuit breaker opened\n\
+             # TYPE qhttpx_redis_circuit_trips_total counter\n\
+             qhttpx_redis_circuit_trips_total {}\n\
+             \n\
+             # HELP qhttpx_redis_circuit_open Whether the Redis circuit breaker is open (1) or closed (0)\n\
+             # TYPE qhttpx_redis_circuit_open gauge\n\
+             qhttpx_redis_circuit_open {}\n",
+            self.errors.load(Ordering::Relaxed),
+            self.short_circuited.load(Ordering::Relaxed),
+            self.trips.load(Ordering::Relaxed),
+            open,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_open(pool: &RedisPool) -> bool {
+        matches!(*pool.breaker.lock().unwrap(), Breaker::Open { .. })
+    }
+
+    #[test]
+    fn breaker_opens_probes_and_closes() {
+        let pool = RedisPool::new();
+        pool.configure(RedisSettings { breaker_threshold: 2, breaker_cooldown: Duration::from_millis(30), ..RedisSettings::default() });
+
+        pool.on_failure();
+        assert!(pool.admit() && !is_open(&pool));
+        pool.on_failure();
+        assert!(is_open(&pool));
+        assert!(!pool.admit());
+
+        // After the cooldown one probe goes through; the rest wait for its answer
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(pool.admit());
+        assert!(!pool.admit());
+        pool.on_failure();
+        assert!(is_open(&pool));
+
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(pool.admit());
+        pool.on_success();
+        assert!(pool.admit() && pool.admit());
+        assert_eq!(pool.trips.load(Ordering::Relaxed), 2);
+        assert!(pool.render_metrics().contains("qhttpx_redis_circuit_open 0\n"));
+    }
+
+    #[test]
+    fn stuck_probe_does_not_wedge_the_breaker() {
+        let pool = RedisPool::new();
+        pool.configure(RedisSettings { breaker_threshold: 1, breaker_cooldown: Duration::from_millis(30), ..RedisSettings::default() });
+        pool.on_failure();
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(pool.admit()); // Probe that is never reported
+        assert!(!pool.admit());
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(pool.admit());
+    }
+
+    #[tokio::test]
+    async fn calls_fail_fast_when_unconfigured_or_open() {
+        let pool = RedisPool::new();
+        let ping = |mut con: MultiplexedConnection| async move { redis::cmd("PING").query_async::<_, String>(&mut con).await };
+        assert!(matches!(pool.call(ping).await, Err(RedisUnavailable::NotConfigured)));
+
+        // Nothing listens on port 1: connecting is refused straight away
+        pool.set_client(redis::Client::open("redis://127.0.0.1:1/").unwrap());
+        pool.configure(RedisSettings { breaker_threshold: 2, breaker_cooldown: Duration::from_secs(60), ..RedisSettings::default() });
+        assert!(matches!(pool.call(ping).await, Err(RedisUnavailable::Failed(_))));
+        assert!(matches!(pool.call(ping).await, Err(RedisUnavailable::Failed(_))));
+        assert!(matches!(pool.call(ping).await, Err(RedisUnavailable::CircuitOpen)));
+
+        let metrics = pool.render_metrics();
+        assert!(metrics.contains("qhttpx_redis_errors_total 2\n"));
+        assert!(metrics.contains("qhttpx_redis_short_circuited_total 1\n"));
+        assert!(metrics.contains("qhttpx_redis_circuit_open 1\n"));
+
+        // A new client starts from a closed breaker
+        pool.set_client(redis::Client::open("redis://127.0.0.1:1/").unwrap());
+        assert!(matches!(pool.call(ping).await, Err(RedisUnavailable::Failed(_))));
+    }
+
+    #[test]
+    fn failure_mode_names() {
+        assert_eq!(FailureMode::from_str("fail-open"), Ok(FailureMode::Open));
+        assert_eq!(FailureMode::from_str("CLOSED"), Ok(FailureMode::Closed));
+        assert_eq!(FailureMode::from_str("local"), Ok(FailureMode::Local));
+        assert!(FailureMode::from_str("fail_opne").is_err());
+    }
+}
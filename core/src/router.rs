@@ -1,14 +1,37 @@
 use matchit::Router as MatchitRouter;
 use std::sync::{Arc, RwLock};
+use crate::cache::{CacheKeyPolicy, CacheLifetime};
+use crate::jwt::ClaimRequirement;
+use crate::rate_limit::RateLimit;
+use crate::cors::CorsPolicy;
+use crate::security_headers::SecurityHeaders;
+use crate::tls::ClientCertPolicy;
 
 #[derive(Clone, Debug, Default)]
 pub struct RoutePolicies {
-    pub rate_limit: Option<(u32, u64)>, // limit, window_sec
+    pub rate_limits: Vec<RateLimit>, // All must pass (e.g. per-second and per-day)
     pub cache_ttl: Option<u64>, // ttl_sec
+    pub cache_key: CacheKeyPolicy,
+    pub stale_while_revalidate: Option<u64>, // sec served stale while refreshing
+    pub stale_if_error: Option<u64>, // sec served stale when the handler fails
     pub jwt_auth: bool, // true if route requires Bearer token
+    pub api_key_auth: bool, // true if route requires a known API key
+    pub basic_auth: Option<String>, // Realm whose htpasswd users may access the route
+    pub authorize: Vec<ClaimRequirement>, // All must hold for the verified claims, else 403
+    pub client_cert: Option<ClientCertPolicy>, // Verified mTLS client certificate the route requires
+    pub security_headers: Option<Arc<SecurityHeaders>>, // Laid over the global policy for this route
+    pub cors: Option<Arc<CorsPolicy>>, // Replaces the global CORS policy for this route
+    pub csrf_exempt: bool, // Skip CSRF checks, e.g. for webhooks authenticated otherwise
     pub schema: Option<String>, // JSON Schema string for validation
     pub priority: Option<String>,
     pub slo_target: Option<u64>,
+    pub pattern: String, // The route as registered ("/users/:id"), set by Router::add
+}
+
+impl RoutePolicies {
+    pub fn cache_lifetime(&self) -> Option<CacheLifetime> {
+        self.cache_ttl.map(|ttl| CacheLifetime::new(ttl, self.stale_while_revalidate, self.stale_if_error))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -34,24 +57,57 @@ pub enum RouteAction {
     },
 }
 
+impl RouteAction {
+    pub fn policies(&self) -> &RoutePolicies {
+        match self {
+            RouteAction::JsHandler { policies, .. } => policies,
+            RouteAction::Static { policies, .. } => policies,
+            RouteAction::Json { policies, .. } => policies,
+            RouteAction::Upload { policies, .. } => policies,
+        }
+    }
+
+    fn policies_mut(&mut self) -> &mut RoutePolicies {
+        match self {
+            RouteAction::JsHandler { policies, .. } => policies,
+            RouteAction::Static { policies, .. } => policies,
+            RouteAction::Json { policies, .. } => policies,
+            RouteAction::Upload { policies, .. } => policies,
+        }
+    }
+}
+
 pub struct Router {
     inner: Arc<RwLock<MatchitRouter<RouteAction>>>,
+    guarded: Arc<RwLock<Vec<(String, RoutePolicies)>>>, // ("GET /admin", policies) of routes with auth, checked at start()
 }
 
 impl Router {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(RwLock::new(MatchitRouter::new())),
+            guarded: Arc::new(RwLock::new(Vec::new())),
         }
     }
 
-    pub fn add(&self, method: &str, path: &str, action: RouteAction) -> Result<(), String> {
+    pub fn add(&self, method: &str, path: &str, mut action: RouteAction) -> Result<(), String> {
+        action.policies_mut().pattern = path.to_string();
+        let policies = action.policies();
+        let guarded = (policies.jwt_auth || policies.basic_auth.is_some() || policies.client_cert.is_some()).then(|| policies.clone());
         let mut router = self.inner.write().map_err(|e| e.to_string())?;
         // matchit requires paths to start with /
         // We prefix the method: "/GET/users"
         let key = format!("/{}{}", method, path);
         // println!("Adding route: {}", key);
-        router.insert(key, action).map_err(|e| e.to_string())
+        router.insert(key, action).map_err(|e| e.to_string())?;
+        if let Some(policies) = guarded {
+            self.guarded.write().map_err(|e| e.to_string())?.push((format!("{} {}", method, path), policies));
+        }
+        Ok(())
+    }
+
+    pub fn guarded_routes(&self) -> Vec<(String, RoutePolicies)> {
+        self.guarded.read().map(|routes| routes.clone()).unwrap_or_default()
     }
 
     pub fn lookup(&self, method: &str, path: &str) -> Option<(RouteAction, Vec<(String, String)>)> {
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Replaced in the CSP by a fresh nonce per request, e.g. `script-src 'nonce-{nonce}'`.
+pub const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// Response security headers. `None` leaves a header to the layer below (the global
+/// policy, for a route override); an empty string turns it off.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<String>,
+    pub csp_report_only: Option<bool>,
+    pub permissions_policy: Option<String>,
+    pub cross_origin_opener_policy: Option<String>,
+    pub cross_origin_embedder_policy: Option<String>,
+    pub cross_origin_resource_policy: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub frame_options: Option<String>, // "DENY" | "SAMEORIGIN"
+    pub nosniff: Option<bool>,
+}
+
+impl SecurityHeaders {
+    /// What `set_security_headers(true)` has always sent, minus the obsolete X-XSS-Protection.
+    pub fn recommended() -> Self {
+        Self {
+            referrer_policy: Some("strict-origin-when-cross-origin".to_string()),
+            frame_options: Some("SAMEORIGIN".to_string()),
+            nosniff: Some(true),
+            ..Self::default()
+        }
+    }
+
+    /// Rejects values that would produce an invalid header or one browsers ignore.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(frame) = self.frame_options.as_deref().filter(|f| !f.is_empty()) {
+            if !frame.eq_ignore_ascii_case("DENY") && !frame.eq_ignore_ascii_case("SAMEORIGIN") {
+                return Err(format!("frame_options must be DENY or SAMEORIGIN, not \"{}\"", frame));
+            }
+        }
+        for (name, value) in self.headers("nonce") {
+            HeaderValue::from_str(&value).map_err(|_| format!("Invalid value for {}: {}", name, value))?;
+        }
+        Ok(())
+    }
+
+    /// `route` on top of `self`: every header the route sets wins.
+    pub fn overlay(&self, route: &SecurityHeaders) -> SecurityHeaders {
+        let pick = |base: &Option<String>, over: &Option<String>| over.clone().or_else(|| base.clone());
+        SecurityHeaders {
+            content_security_policy: pick(&self.content_security_policy, &route.content_security_policy),
+            csp_report_only: route.csp_report_only.or(self.csp_report_only),
+            permissions_policy: pick(&self.permissions_policy, &route.permissions_policy),
+            cross_origin_opener_policy: pick(&self.cross_origin_opener_policy, &route.cross_origin_opener_policy),
+            cross_origin_embedder_policy: pick(&self.cross_origin_embedder_policy, &route.cross_origin_embedder_policy),
+            cross_origin_resource_policy: pick(&self.cross_origin_resource_policy, &route.cross_origin_resource_policy),
+            referrer_policy: pick(&self.referrer_policy, &route.referrer_policy),
+            frame_options: pick(&self.frame_options, &route.frame_options),
+            nosniff: route.nosniff.or(self.nosniff),
+        }
+    }
+
+    fn uses_nonce(&self) -> bool {
+        self.content_security_policy.as_deref().is_some_and(|csp| csp.contains(NONCE_PLACEHOLDER))
+    }
+
+    fn headers(&self, nonce: &str) -> Vec<(&'static str, String)> {
+        let csp_header = if self.csp_report_only.unwrap_or(false) {
+            "content-security-policy-report-only"
+        } else {
+            "content-security-policy"
+        };
+        let csp = self.content_security_policy.as_ref().map(|csp| csp.replace(NONCE_PLACEHOLDER, nonce));
+        let nosniff = self.nosniff.unwrap_or(false).then(|| "nosniff".to_string());
+        [
+            (csp_header, csp),
+            ("permissions-policy", self.permissions_policy.clone()),
+            ("cross-origin-opener-policy", self.cross_origin_opener_policy.clone()),
+            ("cross-origin-embedder-policy", self.cross_origin_embedder_policy.clone()),
+            ("cross-origin-resource-policy", self.cross_origin_resource_policy.clone()),
+            ("referrer-policy", self.referrer_policy.clone()),
+            ("x-frame-options", self.frame_options.as_ref().map(|f| f.to_ascii_uppercase())),
+            ("x-content-type-options", nosniff),
+        ]
+            .into_iter()
+            .filter_map(|(name, value)| value.filter(|v| !v.is_empty()).map(|v| (name, v)))
+            .collect()
+    }
+}
+
+fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    // Without randomness a nonce would be guessable; an empty one simply matches nothing
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return String::new();
+    }
+    STANDARD.encode(bytes)
+}
+
+/// The policy one request ends up with, and its CSP nonce. Starts from the global policy;
+/// the matched route may narrow it before the handler runs.
+///
+/// Cached responses are replayed under a new nonce, so pages that embed it should not
+/// be cached.
+#[derive(Default)]
+pub struct RequestSecurity {
+    policy: Option<Arc<SecurityHeaders>>,
+    nonce: Option<String>,
+}
+
+impl RequestSecurity {
+    pub fn new(global: Option<Arc<SecurityHeaders>>) -> Self {
+        let mut security = Self { policy: global, nonce: None };
+        security.ensure_nonce();
+        security
+    }
+
+    pub fn route(&mut self, route: Option<&SecurityHeaders>) {
+        if let Some(route) = route {
+            let base = self.policy.as_deref().cloned().unwrap_or_default();
+            self.policy = Some(Arc::new(base.overlay(route)));
+            self.ensure_nonce();
+        }
+    }
+
+    fn ensure_nonce(&mut self) {
+        if self.nonce.is_none() && self.policy.as_ref().is_some_and(|p| p.uses_nonce()) {
+            self.nonce = Some(generate_nonce());
+        }
+    }
+
+    /// For handlers, to put on inline `<script nonce=...>` tags.
+    pub fn nonce(&self) -> Option<&str> {
+        self.nonce.as_deref()
+    }
+
+    /// Adds the policy's headers. Headers the response already carries (set by the handler) are kept.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let Some(policy) = &self.policy else {
+            return;
+        };
+        for (name, value) in policy.headers(self.nonce.as_deref().unwrap_or_default()) {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.entry(HeaderName::from_static(name)).or_insert(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_overrides_and_nonce() {
+        let global = SecurityHeaders {
+            content_security_policy: Some("default-src 'self'; script-src 'nonce-{nonce}'".to_string()),
+            permissions_policy: Some("camera=()".to_string()),
+            ..SecurityHeaders::recommended()
+        };
+        let route = SecurityHeaders {
+            frame_options: Some("deny".to_string()),
+            permissions_policy: Some(String::new()),
+            cross_origin_opener_policy: Some("same-origin".to_string()),
+            ..SecurityHeaders::default()
+        };
+        assert!(route.validate().is_ok());
+        assert!(SecurityHeaders { frame_options: Some("ALLOW-FROM x".to_string()), ..SecurityHeaders::default() }.validate().is_err());
+
+        let mut security = RequestSecurity::new(Some(Arc::new(global)));
+        security.route(Some(&route));
+        let nonce = security.nonce().unwrap().to_string();
+        assert_eq!(nonce.len(), 24);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("referrer-policy", HeaderValue::from_static("no-referrer"));
+        security.apply(&mut headers);
+        assert_eq!(headers["content-security-policy"], format!("default-src 'self'; script-src 'nonce-{}'", nonce).as_str());
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers["cross-origin-opener-policy"], "same-origin");
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["referrer-policy"], "no-referrer");
+        assert!(!headers.contains_key("permissions-policy"));
+        assert!(!headers.contains_key("x-xss-protection"));
+
+        assert!(RequestSecurity::new(Some(Arc::new(SecurityHeaders::recommended()))).nonce().is_none());
+        let mut none = HeaderMap::new();
+        RequestSecurity::new(None).apply(&mut none);
+        assert!(none.is_empty());
+    }
+}
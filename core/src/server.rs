@@ -1,8 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU64, AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
 use bytes::Bytes;
 use hyper::service::service_fn;
 use hyper::{Request, Response, StatusCode};
@@ -17,27 +17,33 @@ use napi::threadsafe_function::{ThreadsafeFunction, ErrorStrategy, ThreadsafeFun
 use tokio_tungstenite::{WebSocketStream, tungstenite::protocol::Message, tungstenite::protocol::Role, tungstenite::handshake::derive_accept_key};
 use futures_util::{SinkExt, StreamExt};
 
-use crate::router::{Router, RouteAction};
-use http_body_util::{Full, Empty, BodyExt, combinators::BoxBody, StreamBody, BodyStream};
-use hyper::body::Frame;
+use crate::router::{Router, RouteAction, RoutePolicies};
+use http_body_util::{Full, Empty, BodyExt, combinators::BoxBody, BodyStream};
 use hyper_util::server::conn::auto::Builder;
 use hyper_util::rt::TokioExecutor;
-use async_compression::tokio::bufread::{GzipEncoder, BrotliEncoder};
-use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::io::AsyncWriteExt;
 use dashmap::DashMap;
 use std::time::{Instant, Duration};
 // use std::path::PathBuf;
-use std::io::BufReader as StdBufReader;
-use std::fs::File as StdFile;
-use tokio_rustls::rustls::ServerConfig;
-use tokio_rustls::TlsAcceptor;
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{debug, info, warn};
 use jsonschema::Validator;
 use tokio::fs::File;
-use tokio_util::io::ReaderStream;
 use crate::governor::{TrafficGovernor, Priority};
+use crate::cache::{self, BoundedCache, CacheLifetime, CacheStats, CachedResponse, Flight, FlightLease, Freshness, SingleFlight};
+use crate::static_files::{self, StaticMount, ResolveError, Resolved};
+use crate::shared_cache::SharedCache;
+use crate::jwt::{JwtConfig, JwtVerifier};
+use crate::api_key::{ApiKey, ApiKeyStore};
+use crate::cors::{CorsPolicy, RequestCors};
+use crate::cookies;
+use crate::csrf::CsrfPolicy;
+use crate::https::{HstsPolicy, HttpsRedirect};
+use crate::security_headers::{RequestSecurity, SecurityHeaders};
+use crate::session::SessionStore;
+use crate::tls::{CertSource, ClientAuthConfig, ClientCert, TlsPolicy, TlsState};
+use crate::basic_auth::BasicAuth;
+use crate::redis_pool::{FailureMode, RedisPool, RedisSettings, RedisUnavailable};
+use crate::rate_limit::{Decision, LimiterState, RateLimit, RequestIdentity, TrustedProxies};
 
 #[derive(Debug)]
 pub struct ServerMetrics {
@@ -46,6 +52,8 @@ pub struct ServerMetrics {
     pub errors_total: AtomicU64,
     pub latency_sum_ms: AtomicU64,
     pub latency_count: AtomicU64,
+    pub rate_limited_local: AtomicU64,
+    pub rate_limited_redis: AtomicU64,
 }
 
 impl ServerMetrics {
@@ -56,6 +64,8 @@ impl ServerMetrics {
             errors_total: AtomicU64::new(0),
             latency_sum_ms: AtomicU64::new(0),
             latency_count: AtomicU64::new(0),
+            rate_limited_local: AtomicU64::new(0),
+            rate_limited_redis: AtomicU64::new(0),
         }
     }
 
@@ -82,18 +92,19 @@ impl ServerMetrics {
              \n\
              # HELP http_request_duration_ms_avg Average request duration in ms\n\
              # TYPE http_request_duration_ms_avg gauge\n\
-             http_request_duration_ms_avg {:.2}\n",
-            total, active, errors, avg_latency
+             http_request_duration_ms_avg {:.2}\n\
+             \n\
+             # HELP qhttpx_rate_limited_total Requests rejected with 429 by a route rate limit\n\
+             # TYPE qhttpx_rate_limited_total counter\n\
+             qhttpx_rate_limited_total{{store=\"local\"}} {}\n\
+             qhttpx_rate_limited_total{{store=\"redis\"}} {}\n",
+            total, active, errors, avg_latency,
+            self.rate_limited_local.load(Ordering::Relaxed),
+            self.rate_limited_redis.load(Ordering::Relaxed),
         )
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
-}
-
 pub struct RequestEvent {
     pub handler_id: u32,
     pub req_id: String,
@@ -103,6 +114,13 @@ pub struct RequestEvent {
     pub method: String,
     pub url: String,
     pub body: Vec<u8>,
+    pub user: Option<serde_json::Value>, // Verified JWT claims on jwt_auth routes
+    pub api_key: Option<serde_json::Value>, // Key metadata on api_key_auth routes
+    pub client_cert: Option<serde_json::Value>, // Verified mTLS client certificate, on any route
+    pub csp_nonce: Option<String>, // When the CSP uses {nonce}; the same value is in the header
+    pub csrf_token: Option<String>, // Current CSRF token, for pages that embed it in forms
+    pub cookies: HashMap<String, String>,
+    pub session: Option<serde_json::Value>, // Session data when sessions are configured
     pub response_sender: Mutex<Option<ResponseSender>>,
 }
 
@@ -111,24 +129,21 @@ pub struct WsEvent {
     pub event_type: String, // "open", "message", "close"
     pub payload: Option<String>,
     pub path: Option<String>,
-}
-
-#[derive(Clone)]
-pub struct CorsConfig {
-    pub origin: String,
-    pub methods: String,
-    pub headers: String,
-    pub credentials: bool,
+    pub user: Option<serde_json::Value>, // Verified JWT claims, on "open" for protected paths
 }
 
 // Update ResponseSender to use BoxBody for flexibility (String or Stream)
 pub type ResponseSender = oneshot::Sender<Response<BoxBody<Bytes, std::io::Error>>>;
 pub type WsSender = mpsc::UnboundedSender<Message>;
 pub type WsPeers = Arc<Mutex<HashMap<String, WsSender>>>;
+// Caches owned outside the server (e.g. the SQL query cache) that /metrics reports on
+type CacheStatsSource = Arc<dyn Fn() -> CacheStats + Send + Sync>;
+// Renders the full /metrics page, the same text get_metrics returns
+type MetricsPage = Arc<dyn Fn() -> String + Send + Sync>;
 pub type WsRooms = Arc<Mutex<HashMap<String, HashSet<String>>>>;
 
 // Helper to box full bodies
-fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, std::io::Error> {
+pub(crate) fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, std::io::Error> {
     Full::new(chunk.into())
         .map_err(|never| match never {})
         .boxed()
@@ -140,19 +155,32 @@ pub struct NativeServer {
     js_callback: Option<ThreadsafeFunction<RequestEvent, ErrorStrategy::Fatal>>,
     ws_callback: Option<ThreadsafeFunction<WsEvent, ErrorStrategy::Fatal>>,
     req_id_counter: Arc<AtomicU64>,
-    static_routes: Arc<Mutex<Vec<(String, String)>>>, // (prefix, directory)
+    static_routes: Arc<Mutex<Vec<StaticMount>>>,
     ws_peers: WsPeers,
     ws_rooms: WsRooms,
-    cors_config: Arc<Mutex<Option<CorsConfig>>>,
-    // Key: "IP RoutePath" -> (count, window_start)
-    rate_limit_store: Arc<DashMap<String, (u32, Instant)>>,
-    // Key: "Method Path" -> (Body, expiry)
-    cache_store: Arc<DashMap<String, (Bytes, Instant)>>,
-    tls_paths: Arc<Mutex<Option<(String, String)>>>, // (cert_path, key_path)
-    jwt_secret: Arc<Mutex<Option<String>>>,
-    redis_client: Arc<Mutex<Option<redis::Client>>>,
+    cors_policy: Arc<Mutex<Option<Arc<CorsPolicy>>>>,
+    // Key: "algorithm:Method Path IP" -> limiter state, expires once it no longer matters
+    rate_limit_store: Arc<BoundedCache<LimiterState>>,
+    trusted_proxies: Arc<Mutex<TrustedProxies>>,
+    // Key: built from the route's CacheKeyPolicy, expires after the route's TTL
+    cache_store: Arc<BoundedCache<Arc<CachedResponse>>>,
+    cache_inflight: Arc<SingleFlight>, // Keys currently being filled by a handler
+    shared_cache_prefix: Arc<Mutex<Option<String>>>, // Some => Redis L2 enabled at start()
+    shared_cache: Arc<Mutex<Option<Arc<SharedCache>>>>,
+    cache_sweep_interval: Arc<AtomicU64>, // ms
+    tls: Arc<TlsState>,
+    tls_watch_interval: Arc<AtomicU64>, // ms, 0 => no file watching
+    hsts: Arc<Mutex<Option<HstsPolicy>>>,
+    https_redirect: Arc<Mutex<Option<HttpsRedirect>>>,
+    jwt: Arc<JwtVerifier>,
+    api_keys: Arc<ApiKeyStore>,
+    basic_auth: Arc<BasicAuth>,
+    redis: Arc<RedisPool>,
     metrics: Arc<ServerMetrics>,
-    security_headers: Arc<AtomicBool>,
+    extra_caches: Arc<Mutex<Vec<CacheStatsSource>>>,
+    security_headers: Arc<Mutex<Option<Arc<SecurityHeaders>>>>, // Global policy, routes may override
+    csrf: Arc<Mutex<Option<Arc<CsrfPolicy>>>>, // All routes unless exempted
+    sessions: Arc<SessionStore>,
     schema_cache: Arc<DashMap<String, Arc<Validator>>>,
     governor: Arc<TrafficGovernor>,
     shutdown_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
@@ -169,14 +197,27 @@ impl Clone for NativeServer {
             static_routes: self.static_routes.clone(),
             ws_peers: self.ws_peers.clone(),
             ws_rooms: self.ws_rooms.clone(),
-            cors_config: self.cors_config.clone(),
+            cors_policy: self.cors_policy.clone(),
             rate_limit_store: self.rate_limit_store.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
             cache_store: self.cache_store.clone(),
-            tls_paths: self.tls_paths.clone(),
-            jwt_secret: self.jwt_secret.clone(),
-            redis_client: self.redis_client.clone(),
+            cache_inflight: self.cache_inflight.clone(),
+            shared_cache_prefix: self.shared_cache_prefix.clone(),
+            shared_cache: self.shared_cache.clone(),
+            cache_sweep_interval: self.cache_sweep_interval.clone(),
+            tls: self.tls.clone(),
+            tls_watch_interval: self.tls_watch_interval.clone(),
+            hsts: self.hsts.clone(),
+            https_redirect: self.https_redirect.clone(),
+            jwt: self.jwt.clone(),
+            api_keys: self.api_keys.clone(),
+            basic_auth: self.basic_auth.clone(),
+            redis: self.redis.clone(),
             metrics: self.metrics.clone(),
+            extra_caches: self.extra_caches.clone(),
             security_headers: self.security_headers.clone(),
+            csrf: self.csrf.clone(),
+            sessions: self.sessions.clone(),
             schema_cache: self.schema_cache.clone(),
             governor: self.governor.clone(),
             shutdown_tx: self.shutdown_tx.clone(),
@@ -187,6 +228,7 @@ impl Clone for NativeServer {
 impl NativeServer {
     pub fn new(port: u16) -> Self {
         let governor = Arc::new(TrafficGovernor::new(200, 10, 1000));
+        let redis = Arc::new(RedisPool::new());
         Self { 
             port, 
             router: Arc::new(Router::new()),
@@ -196,14 +238,27 @@ impl NativeServer {
             static_routes: Arc::new(Mutex::new(Vec::new())),
             ws_peers: Arc::new(Mutex::new(HashMap::new())),
             ws_rooms: Arc::new(Mutex::new(HashMap::new())),
-            cors_config: Arc::new(Mutex::new(None)),
-            rate_limit_store: Arc::new(DashMap::new()),
-            cache_store: Arc::new(DashMap::new()),
-            tls_paths: Arc::new(Mutex::new(None)),
-            jwt_secret: Arc::new(Mutex::new(None)),
-            redis_client: Arc::new(Mutex::new(None)),
+            cors_policy: Arc::new(Mutex::new(None)),
+            rate_limit_store: Arc::new(BoundedCache::new("rate_limit", 16 * 1024 * 1024)),
+            trusted_proxies: Arc::new(Mutex::new(TrustedProxies::default())),
+            cache_store: Arc::new(BoundedCache::new("response", 64 * 1024 * 1024)),
+            cache_inflight: Arc::new(SingleFlight::default()),
+            shared_cache_prefix: Arc::new(Mutex::new(None)),
+            shared_cache: Arc::new(Mutex::new(None)),
+            cache_sweep_interval: Arc::new(AtomicU64::new(30_000)),
+            tls: Arc::new(TlsState::new()),
+            tls_watch_interval: Arc::new(AtomicU64::new(0)),
+            hsts: Arc::new(Mutex::new(None)),
+            https_redirect: Arc::new(Mutex::new(None)),
+            jwt: Arc::new(JwtVerifier::new()),
+            api_keys: Arc::new(ApiKeyStore::new()),
+            basic_auth: Arc::new(BasicAuth::new()),
+            sessions: Arc::new(SessionStore::new(redis.clone())),
+            redis,
             metrics: Arc::new(ServerMetrics::new()),
-            security_headers: Arc::new(AtomicBool::new(false)),
+            extra_caches: Arc::new(Mutex::new(Vec::new())),
+            security_headers: Arc::new(Mutex::new(None)),
+            csrf: Arc::new(Mutex::new(None)),
             schema_cache: Arc::new(DashMap::new()),
             governor,
             shutdown_tx: Arc::new(Mutex::new(None)),
@@ -211,37 +266,183 @@ impl NativeServer {
     }
 
     pub fn set_security_headers(&self, enabled: bool) {
-        self.security_headers.store(enabled, Ordering::Relaxed);
+        self.set_security_policy(enabled.then(SecurityHeaders::recommended));
+    }
+
+    /// Headers added to every response: handler, static, cached and error responses alike.
+    pub fn set_security_policy(&self, policy: Option<SecurityHeaders>) {
+        *self.security_headers.lock().unwrap() = policy.map(Arc::new);
+    }
+
+    /// CSRF checks for unsafe methods on every route not marked `csrf_exempt`. None turns them off.
+    pub fn set_csrf(&self, policy: Option<CsrfPolicy>) {
+        *self.csrf.lock().unwrap() = policy.map(Arc::new);
     }
 
     pub fn set_redis(&self, client: redis::Client) {
-        *self.redis_client.lock().unwrap() = Some(client);
+        self.redis.set_client(client);
     }
 
-    pub fn set_jwt_secret(&self, secret: String) {
-        let mut s = self.jwt_secret.lock().unwrap();
-        *s = Some(secret);
+    pub fn has_redis(&self) -> bool {
+        self.redis.is_configured()
     }
 
-    pub fn set_tls(&self, cert_path: String, key_path: String) {
-        let mut paths = self.tls_paths.lock().unwrap();
-        *paths = Some((cert_path, key_path));
+    pub fn configure_redis(&self, settings: RedisSettings) {
+        self.redis.configure(settings);
     }
 
-    pub fn set_cors(&self, origin: String, methods: String, headers: String, credentials: bool) {
-        let mut config = self.cors_config.lock().unwrap();
-        *config = Some(CorsConfig {
-            origin,
-            methods,
-            headers,
-            credentials,
-        });
+    pub fn set_jwt_secret(&self, secret: String) -> Result<(), String> {
+        self.jwt.set_secret(secret).map(|_| ())
+    }
+
+    /// Replaces the JWT verification keys and algorithm allow-list. Returns the number of keys loaded.
+    pub fn configure_jwt(&self, config: JwtConfig) -> Result<usize, String> {
+        self.jwt.configure(config)
+    }
+
+    /// Re-reads JWT key files and optionally swaps in a new JWKS document, without a restart.
+    pub fn refresh_jwt_keys(&self, jwks: Option<String>) -> Result<usize, String> {
+        self.jwt.refresh(jwks)
+    }
+
+    /// Keys accepted on `api_key_auth` routes; can be reloaded while serving.
+    pub fn api_keys(&self) -> &ApiKeyStore {
+        &self.api_keys
+    }
+
+    /// Sessions and the cookie keys, loaded for every JS handler once configured.
+    pub fn sessions(&self) -> &SessionStore {
+        &self.sessions
+    }
+
+    /// htpasswd realms for `basic_auth` routes and the metrics endpoint.
+    pub fn basic_auth(&self) -> &BasicAuth {
+        &self.basic_auth
+    }
+
+    pub fn set_trusted_proxies(&self, proxies: TrustedProxies) {
+        *self.trusted_proxies.lock().unwrap() = proxies;
+    }
+
+    /// The default certificate, served when SNI matches no hostname certificate.
+    pub fn set_tls(&self, cert_path: String, key_path: String, ocsp_path: Option<String>) {
+        self.tls.add_certificate(CertSource { hostnames: Vec::new(), cert_path, key_path, ocsp_path });
+    }
+
+    /// A certificate selected by SNI for `source.hostnames`.
+    pub fn add_tls_certificate(&self, source: CertSource) {
+        self.tls.add_certificate(source);
+    }
+
+    /// Enables mutual TLS on the HTTPS listener; takes effect at start() or the next reload.
+    pub fn set_client_auth(&self, config: Option<ClientAuthConfig>) {
+        self.tls.set_client_auth(config);
+    }
+
+    /// Re-reads certificates, OCSP responses, client CAs and CRLs. New connections use
+    /// them at once; on error the running config is kept.
+    pub fn reload_tls(&self) -> Result<usize, String> {
+        if !self.tls.is_configured() {
+            return Err("TLS is not configured (call set_tls first)".to_string());
+        }
+        self.tls.reload()
+    }
+
+    /// Versions, cipher suites, ALPN and session resumption; applies at start() or the next reload.
+    pub fn set_tls_policy(&self, policy: TlsPolicy) -> Result<(), String> {
+        self.tls.set_policy(policy)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
     }
 
+    /// `Strict-Transport-Security` for every response sent over TLS; None turns it off.
+    pub fn set_hsts(&self, policy: Option<HstsPolicy>) {
+        *self.hsts.lock().unwrap() = policy;
+    }
+
+    /// A second, plain-HTTP listener that redirects every request to HTTPS. Bound at start().
+    pub fn set_https_redirect(&self, redirect: Option<HttpsRedirect>) {
+        *self.https_redirect.lock().unwrap() = redirect;
+    }
+
+    /// Reload TLS files automatically when they change, checking every `interval`.
+    pub fn watch_tls(&self, interval: Option<Duration>) {
+        let ms = interval.map(|i| i.as_millis().max(100) as u64).unwrap_or(0);
+        self.tls_watch_interval.store(ms, Ordering::Relaxed);
+    }
+
+    /// The CORS policy for every route without its own. None turns CORS off.
+    pub fn set_cors(&self, policy: Option<CorsPolicy>) {
+        *self.cors_policy.lock().unwrap() = policy.map(Arc::new);
+    }
+
+    pub fn configure_cache(&self, response_max_bytes: Option<usize>, rate_limit_max_bytes: Option<usize>, sweep_interval_ms: Option<u64>) {
+        if let Some(max) = response_max_bytes {
+            self.cache_store.set_max_bytes(max);
+        }
+        if let Some(max) = rate_limit_max_bytes {
+            self.rate_limit_store.set_max_bytes(max);
+        }
+        if let Some(interval) = sweep_interval_ms {
+            self.cache_sweep_interval.store(interval.max(100), Ordering::Relaxed);
+        }
+    }
+
+    /// Adds Redis as a second response cache tier shared by every instance, using the
+    /// client from `set_redis`. Takes effect at `start()`.
+    pub fn enable_shared_cache(&self, prefix: String) {
+        *self.shared_cache_prefix.lock().unwrap() = Some(prefix);
+    }
+
+    pub fn cache_sweep_interval(&self) -> Duration {
+        Duration::from_millis(self.cache_sweep_interval.load(Ordering::Relaxed))
+    }
+
+    /// Evicts cached responses whose path matches `pattern` ("/blog/*", or an exact path),
+    /// locally and, with the shared tier, in Redis and on every other instance.
+    pub async fn cache_purge(&self, pattern: &str) -> Result<usize, String> {
+        let shared = self.shared_cache.lock().unwrap().clone();
+        // Redis goes first: otherwise a concurrent miss could copy a purged entry from
+        // Redis straight back into L1. The fence stops responses already being generated.
+        self.cache_store.fence();
+        let shared_removed = match shared {
+            Some(shared) => shared.purge(pattern).await,
+            None => Ok(0),
+        };
+        let removed = self.cache_store.purge(pattern);
+        Ok(removed + shared_removed.map_err(|e| format!("Redis cache purge failed: {}", e))?)
+    }
+
+    /// Evicts cached responses the handler tagged with `tag` via the Cache-Tag header.
+    pub async fn cache_purge_tag(&self, tag: &str) -> Result<usize, String> {
+        let shared = self.shared_cache.lock().unwrap().clone();
+        // Same order as cache_purge
+        self.cache_store.fence();
+        let shared_removed = match shared {
+            Some(shared) => shared.purge_tag(tag).await,
+            None => Ok(0),
+        };
+        let removed = self.cache_store.purge_tag(tag);
+        Ok(removed + shared_removed.map_err(|e| format!("Redis cache purge failed: {}", e))?)
+    }
+
+    /// Lets the engine report caches it owns (e.g. the SQL query cache) with the server's.
+    pub fn add_metrics_cache(&self, stats: impl Fn() -> CacheStats + Send + Sync + 'static) {
+        self.extra_caches.lock().unwrap().push(Arc::new(stats));
+    }
+
+    /// The Prometheus page, as served on /metrics.
     pub fn get_metrics(&self) -> String {
         let (limit, inflight, shed) = self.governor.get_metrics();
         let base = self.metrics.render();
-        
+        let mut caches = vec![self.cache_store.stats(), self.rate_limit_store.stats(), self.sessions.stats()];
+        caches.extend(self.extra_caches.lock().unwrap().iter().map(|stats| stats()));
+        let redis = if self.redis.is_configured() { self.redis.render_metrics() } else { String::new() };
+        let tls = if self.tls.is_configured() { self.tls.metrics().render_metrics() } else { String::new() };
+        let sessions = if self.sessions.is_enabled() { self.sessions.render_metrics() } else { String::new() };
+
         format!("{}
              # HELP qhttpx_concurrency_limit Current adaptive concurrency limit
              # TYPE qhttpx_concurrency_limit gauge
@@ -254,12 +455,16 @@ impl NativeServer {
              # HELP qhttpx_shed_requests_total Total number of requests rejected by governor
              # TYPE qhttpx_shed_requests_total counter
              qhttpx_shed_requests_total {}
-             ", base, limit, inflight, shed)
+
+{}
+{}
+{}
+{}", base, limit, inflight, shed, cache::render_metrics(&caches), redis, tls, sessions)
     }
 
     pub fn ws_subscribe(&self, socket_id: String, room: String) {
         let mut rooms = self.ws_rooms.lock().unwrap();
-        rooms.entry(room).or_insert_with(HashSet::new).insert(socket_id);
+        rooms.entry(room).or_default().insert(socket_id);
     }
 
     pub fn ws_unsubscribe(&self, socket_id: String, room: String) {
@@ -302,9 +507,9 @@ impl NativeServer {
         }
     }
 
-    pub fn add_static_route(&self, prefix: String, dir: String) {
+    pub fn add_static_route(&self, mount: StaticMount) {
         let mut routes = self.static_routes.lock().unwrap();
-        routes.push((prefix, dir));
+        routes.push(mount);
     }
 
     pub fn set_callback(&mut self, callback: ThreadsafeFunction<RequestEvent, ErrorStrategy::Fatal>) {
@@ -326,31 +531,14 @@ impl NativeServer {
         self.router.add(method, path, action)
     }
 
-    pub fn send_response(&self, handle: &Mutex<Option<ResponseSender>>, status: u16, body: String) -> Result<(), String> {
+    /// `headers` are set after the engine's own, so a handler can override them.
+    pub fn send_response(&self, handle: &Mutex<Option<ResponseSender>>, status: u16, body: String, headers: Option<Vec<String>>) -> Result<(), String> {
         let mut guard = handle.lock().map_err(|e| e.to_string())?;
         if let Some(tx) = guard.take() {
             let mut builder = Response::builder()
                 .status(StatusCode::from_u16(status).unwrap_or(StatusCode::OK));
 
-            // Inject Security Headers
-            if self.security_headers.load(Ordering::Relaxed) {
-                builder = builder
-                    .header("X-Content-Type-Options", "nosniff")
-                    .header("X-Frame-Options", "SAMEORIGIN")
-                    .header("X-XSS-Protection", "1; mode=block")
-                    .header("Referrer-Policy", "strict-origin-when-cross-origin");
-            }
-
-            // Inject CORS
-            {
-                let config = self.cors_config.lock().unwrap();
-                if let Some(cors) = &*config {
-                    builder = builder.header("Access-Control-Allow-Origin", &cors.origin);
-                    if cors.credentials {
-                        builder = builder.header("Access-Control-Allow-Credentials", "true");
-                    }
-                }
-            }
+            builder = apply_handler_headers(builder, headers)?;
 
             let response = builder
                 .body(full(body))
@@ -363,32 +551,14 @@ impl NativeServer {
         }
     }
 
-    pub fn send_html(&self, handle: &Mutex<Option<ResponseSender>>, status: u16, body: String) -> Result<(), String> {
+    pub fn send_html(&self, handle: &Mutex<Option<ResponseSender>>, status: u16, body: String, headers: Option<Vec<String>>) -> Result<(), String> {
         let mut guard = handle.lock().map_err(|e| e.to_string())?;
         if let Some(tx) = guard.take() {
             let mut builder = Response::builder()
                 .status(StatusCode::from_u16(status).unwrap_or(StatusCode::OK))
                 .header("Content-Type", "text/html");
 
-            // Inject Security Headers
-            if self.security_headers.load(Ordering::Relaxed) {
-                builder = builder
-                    .header("X-Content-Type-Options", "nosniff")
-                    .header("X-Frame-Options", "SAMEORIGIN")
-                    .header("X-XSS-Protection", "1; mode=block")
-                    .header("Referrer-Policy", "strict-origin-when-cross-origin");
-            }
-
-            // Inject CORS
-            {
-                let config = self.cors_config.lock().unwrap();
-                if let Some(cors) = &*config {
-                    builder = builder.header("Access-Control-Allow-Origin", &cors.origin);
-                    if cors.credentials {
-                        builder = builder.header("Access-Control-Allow-Credentials", "true");
-                    }
-                }
-            }
+            builder = apply_handler_headers(builder, headers)?;
 
             let response = builder
                 .body(full(body))
@@ -409,29 +579,60 @@ impl NativeServer {
     }
 
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Protected routes fail closed, so refuse to start without keys to verify them
+        for (route, policies) in self.router.guarded_routes() {
+            if policies.jwt_auth && !self.jwt.has_keys() {
+                return Err(format!("Route {} requires JWT auth but no verification keys are configured (call set_jwt_secret or configure_jwt)", route).into());
+            }
+            if let Some(realm) = policies.basic_auth.as_deref().filter(|realm| !self.basic_auth.has_realm(realm)) {
+                return Err(format!("Route {} uses Basic auth realm \"{}\", which was never added", route, realm).into());
+            }
+            if policies.client_cert.is_some() && !(self.tls.is_configured() && self.tls.has_client_auth()) {
+                return Err(format!("Route {} requires a client certificate but mutual TLS is not configured (call set_tls and set_client_auth)", route).into());
+            }
+        }
+
         // Setup TLS
-        let tls_acceptor = if let Some((cert_path, key_path)) = self.tls_paths.lock().unwrap().clone() {
-            let cert_file = StdFile::open(&cert_path).map_err(|e| format!("Failed to open cert file {}: {}", cert_path, e))?;
-            let key_file = StdFile::open(&key_path).map_err(|e| format!("Failed to open key file {}: {}", key_path, e))?;
-            
-            let certs = rustls_pemfile::certs(&mut StdBufReader::new(cert_file))
-                .collect::<Result<Vec<_>, _>>()?;
-            let key = rustls_pemfile::private_key(&mut StdBufReader::new(key_file))?
-                .ok_or("No private key found")?;
-            
-            let mut config = ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(certs, key)?;
-            
-            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-            
-            Some(TlsAcceptor::from(Arc::new(config)))
-        } else {
-            None
+        let tls_enabled = self.tls.is_configured();
+        if tls_enabled {
+            self.tls.reload()?;
+            let watch_ms = self.tls_watch_interval.load(Ordering::Relaxed);
+            if watch_ms > 0 {
+                self.tls.spawn_watcher(Duration::from_millis(watch_ms));
+            }
+        }
+
+        let shared_cache_prefix = self.shared_cache_prefix.lock().unwrap().clone();
+        let shared_cache = match shared_cache_prefix {
+            Some(prefix) => {
+                if !self.redis.is_configured() {
+                    return Err("Shared response cache is enabled but no Redis connection was configured (call connect_redis first)".into());
+                }
+                let shared = Arc::new(SharedCache::new(self.redis.clone(), prefix));
+                *self.shared_cache.lock().unwrap() = Some(shared.clone());
+                Some(shared)
+            }
+            None => None,
         };
 
         let addr = SocketAddr::from(([127, 0, 0, 1], self.port));
         let listener = TcpListener::bind(addr).await?;
+
+        let https_redirect = self.https_redirect.lock().unwrap().clone();
+        let redirect_listener = match &https_redirect {
+            Some(redirect) => Some(TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], redirect.port))).await?),
+            None => None,
+        };
+
+        // Expired cache and rate-limit entries are otherwise only dropped when touched again
+        self.cache_store.spawn_sweeper(self.cache_sweep_interval());
+        self.rate_limit_store.spawn_sweeper(self.cache_sweep_interval());
+        if self.sessions.is_enabled() {
+            self.sessions.memory().spawn_sweeper(self.cache_sweep_interval());
+        }
+        if let Some(shared) = &shared_cache {
+            shared.spawn_subscriber(&self.cache_store);
+        }
         
         // Setup shutdown channel
         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -439,6 +640,11 @@ impl NativeServer {
             let mut lock = self.shutdown_tx.lock().unwrap();
             *lock = Some(tx);
         }
+        // Dropped when the main loop ends, which stops the redirect listener with it
+        let (listening_tx, listening_rx) = tokio::sync::watch::channel(());
+        if let (Some(redirect), Some(listener)) = (https_redirect, redirect_listener) {
+            spawn_https_redirect(listener, Arc::new(redirect), listening_rx);
+        }
 
         // Clone state for the loop
         let router = self.router.clone();
@@ -448,19 +654,32 @@ impl NativeServer {
         let ws_callback = self.ws_callback.clone();
         let ws_peers = self.ws_peers.clone();
         let ws_rooms = self.ws_rooms.clone();
-        let cors_config = self.cors_config.clone();
+        let cors_policy = self.cors_policy.clone();
         let rate_limit_store = self.rate_limit_store.clone();
+        let trusted_proxies = self.trusted_proxies.clone();
         let cache_store = self.cache_store.clone();
-        let jwt_secret = self.jwt_secret.clone();
-        let redis_client = self.redis_client.clone();
+        let cache_inflight = self.cache_inflight.clone();
+        let jwt = self.jwt.clone();
+        let api_keys = self.api_keys.clone();
+        let basic_auth = self.basic_auth.clone();
+        let redis = self.redis.clone();
         let metrics = self.metrics.clone();
+        let metrics_page: MetricsPage = {
+            let server = self.clone();
+            Arc::new(move || server.get_metrics())
+        };
         let security_headers = self.security_headers.clone();
+        let csrf = self.csrf.clone();
+        let sessions = self.sessions.clone();
         let schema_cache = self.schema_cache.clone();
         let governor = self.governor.clone();
 
-        let _protocol = if tls_acceptor.is_some() { "https" } else { "http" };
+        let tls = self.tls.clone();
+        let hsts = self.hsts.clone();
+        let _protocol = if tls_enabled { "https" } else { "http" };
         
         tokio::spawn(async move {
+            let _listening = listening_tx;
             tokio::pin!(rx);
             loop {
                 let accept_result = tokio::select! {
@@ -478,7 +697,9 @@ impl NativeServer {
                 let (stream, remote_addr) = match accept_result {
                     Ok(conn) => conn,
                     Err(e) => {
-                        eprintln!("Accept error: {}", e);
+                        warn!(target: "Server", error = %e, "accept failed");
+                        // Errors like EMFILE persist until something frees up; do not spin on them
+                        tokio::time::sleep(Duration::from_millis(100)).await;
                         continue;
                     }
                 };
@@ -490,17 +711,32 @@ impl NativeServer {
                 let static_routes_clone = static_routes.clone();
                 let ws_peers_clone = ws_peers.clone();
                 let ws_rooms_clone = ws_rooms.clone();
-                let cors_config_clone = cors_config.clone();
+                let cors_policy_clone = cors_policy.clone();
                 let rate_limit_clone = rate_limit_store.clone();
+                let trusted_proxies_clone = trusted_proxies.clone();
                 let cache_clone = cache_store.clone();
-                let jwt_secret_clone = jwt_secret.clone();
-                let redis_client_clone = redis_client.clone();
+                let cache_inflight_clone = cache_inflight.clone();
+                let shared_cache_clone = shared_cache.clone();
+                let jwt_clone = jwt.clone();
+                let api_keys_clone = api_keys.clone();
+                let basic_auth_clone = basic_auth.clone();
+                let redis_clone = redis.clone();
                 let metrics_clone = metrics.clone();
+                let metrics_page_clone = metrics_page.clone();
                 let security_headers_clone = security_headers.clone();
+                let csrf_clone = csrf.clone();
+                let sessions_clone = sessions.clone();
                 let schema_cache_clone = schema_cache.clone();
                 let governor_clone = governor.clone();
                 
-                let tls_acceptor = tls_acceptor.clone();
+                // Picked per connection so a reload applies to the next handshake
+                let tls_acceptor = if tls_enabled { tls.acceptor() } else { None };
+                let tls_state = tls.clone();
+                let hsts_clone = if tls_acceptor.is_some() { Some(hsts.clone()) } else { None };
+                let is_tls = tls_acceptor.is_some();
+                // Filled in after the handshake, before the first request is read
+                let client_cert: Arc<OnceLock<Arc<ClientCert>>> = Arc::new(OnceLock::new());
+                let connection_cert = client_cert.clone();
     
                 tokio::task::spawn(async move {
                     let service = service_fn(move |req| {
@@ -515,20 +751,38 @@ impl NativeServer {
                         let static_routes_clone = static_routes_clone.clone();
                         let ws_peers_clone = ws_peers_clone.clone();
                         let ws_rooms_clone = ws_rooms_clone.clone();
-                        let cors_config_clone = cors_config_clone.clone();
+                        let cors_policy_clone = cors_policy_clone.clone();
                         let rate_limit_clone = rate_limit_clone.clone();
+                        let trusted_proxies_clone = trusted_proxies_clone.clone();
                         let cache_clone = cache_clone.clone();
-                        let jwt_secret_clone = jwt_secret_clone.clone();
-                        let redis_client_clone = redis_client_clone.clone();
+                        let cache_inflight_clone = cache_inflight_clone.clone();
+                        let shared_cache_clone = shared_cache_clone.clone();
+                        let jwt_clone = jwt_clone.clone();
+                        let api_keys_clone = api_keys_clone.clone();
+                        let basic_auth_clone = basic_auth_clone.clone();
+                        let redis_clone = redis_clone.clone();
                         let metrics_clone = metrics_clone.clone();
+                        let metrics_page_clone = metrics_page_clone.clone();
                         let security_headers_clone = security_headers_clone.clone();
+                        let csrf_clone = csrf_clone.clone();
+                        let sessions_clone = sessions_clone.clone();
                         let schema_cache_clone = schema_cache_clone.clone();
                         let governor_clone = governor_clone.clone();
+                        let client_cert = client_cert.get().cloned();
+                        // Only over TLS: browsers ignore it on plain HTTP anyway (RFC 6797 §7.2)
+                        let hsts_value = hsts_clone.as_ref()
+                            .and_then(|hsts| hsts.lock().unwrap().as_ref().map(HstsPolicy::header_value))
+                            .and_then(|value| hyper::header::HeaderValue::from_str(&value).ok());
     
                         async move {
-                            let res = handle_request(
+                            let mut extra_headers = hyper::HeaderMap::new();
+                            let mut security = RequestSecurity::new(security_headers_clone.lock().unwrap().clone());
+                            let mut cors = RequestCors::new(cors_policy_clone.lock().unwrap().clone(), req.headers());
+                            let mut res = handle_request(
                                 req, 
                                 remote_addr,
+                                client_cert,
+                                is_tls,
                                 router_clone, 
                                 callback_clone,
                                 ws_callback_clone,
@@ -536,16 +790,44 @@ impl NativeServer {
                                 static_routes_clone,
                                 ws_peers_clone,
                                 ws_rooms_clone,
-                                cors_config_clone,
                                 rate_limit_clone,
+                                trusted_proxies_clone,
                                 cache_clone,
-                                jwt_secret_clone,
-                                redis_client_clone,
+                                cache_inflight_clone,
+                                shared_cache_clone,
+                                jwt_clone,
+                                api_keys_clone,
+                                basic_auth_clone,
+                                redis_clone,
                                 metrics_clone,
-                                security_headers_clone,
+                                metrics_page_clone,
+                                csrf_clone,
+                                sessions_clone,
                                 schema_cache_clone,
                                 governor_clone,
+                                &mut security,
+                                &mut cors,
+                                &mut extra_headers,
                             ).await;
+
+                            if let Ok(response) = &mut res {
+                                // Cookies are appended so the handler's own Set-Cookie headers survive
+                                let cookies: Vec<_> = extra_headers.get_all(hyper::header::SET_COOKIE).iter().cloned().collect();
+                                extra_headers.remove(hyper::header::SET_COOKIE);
+                                response.headers_mut().extend(extra_headers);
+                                for cookie in cookies {
+                                    response.headers_mut().append(hyper::header::SET_COOKIE, cookie);
+                                }
+                                // The one place security headers are added, whatever produced the response
+                                // Likewise CORS, so handler, static and error responses all get the same headers
+                                if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+                                    security.apply(response.headers_mut());
+                                    cors.apply(response.headers_mut());
+                                }
+                                if let Some(value) = hsts_value {
+                                    response.headers_mut().insert(hyper::header::STRICT_TRANSPORT_SECURITY, value);
+                                }
+                            }
     
                             if let Ok(response) = &res {
                                 info!(
@@ -565,6 +847,13 @@ impl NativeServer {
                     if let Some(acceptor) = tls_acceptor {
                         match acceptor.accept(stream).await {
                             Ok(tls_stream) => {
+                                tls_state.metrics().record_handshake(tls_stream.get_ref().1);
+                                let peer_cert = tls_stream.get_ref().1.peer_certificates()
+                                    .and_then(|chain| chain.first())
+                                    .and_then(|leaf| ClientCert::from_der(leaf));
+                                if let Some(cert) = peer_cert {
+                                    let _ = connection_cert.set(Arc::new(cert));
+                                }
                                 let io = TokioIo::new(tls_stream);
                                 let builder = Builder::new(TokioExecutor::new());
                                 if let Err(err) = builder.serve_connection_with_upgrades(io, service).await {
@@ -574,7 +863,11 @@ impl NativeServer {
                                     }
                                 }
                             }
-                            Err(e) => eprintln!("TLS Handshake Error: {}", e),
+                            Err(e) => {
+                                tls_state.metrics().record_failure(&e);
+                                // Scanners and dropped clients fail handshakes constantly; the metrics count them
+                                debug!(target: "Server", error = %e, peer = %remote_addr, "TLS handshake failed");
+                            }
                         }
                     } else {
                         let io = TokioIo::new(stream);
@@ -596,6 +889,337 @@ impl NativeServer {
     }
 }
 
+/// Answers every request on the plain-HTTP listener with a redirect to HTTPS.
+fn spawn_https_redirect(listener: TcpListener, redirect: Arc<HttpsRedirect>, mut listening: tokio::sync::watch::Receiver<()>) {
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!(target: "Server", error = %e, "redirect listener accept failed");
+                        // Errors like EMFILE persist until something frees up; do not spin on them
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+                _ = listening.changed() => break,
+            };
+            let redirect = redirect.clone();
+            tokio::task::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let response = match redirect.location(req.headers(), req.uri()) {
+                        Some(location) => Response::builder()
+                            .status(redirect.status)
+                            .header(hyper::header::LOCATION, location)
+                            .body(full("")),
+                        None => Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(full("Bad Request")),
+                    };
+                    async move { Ok::<_, Infallible>(response.unwrap()) }
+                });
+                let io = TokioIo::new(stream);
+                if let Err(err) = Builder::new(TokioExecutor::new()).serve_connection(io, service).await {
+                    let err_debug = format!("{:?}", err);
+                    if !err_debug.contains("ConnectionReset") && !err_debug.contains("10054") {
+                        debug!(target: "Server", error = ?err, "redirect connection failed");
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// `headers` is flat name/value pairs, like the request headers handed to handlers. The
+/// first value of a name replaces the engine's; repeating the name adds more (Set-Cookie).
+fn apply_handler_headers(mut builder: hyper::http::response::Builder, headers: Option<Vec<String>>) -> Result<hyper::http::response::Builder, String> {
+    if let (Some(map), Some(headers)) = (builder.headers_mut(), headers) {
+        if headers.len() % 2 != 0 {
+            return Err("Response headers must be name/value pairs".to_string());
+        }
+        let mut replaced = HashSet::new();
+        for pair in headers.chunks(2) {
+            let name = hyper::header::HeaderName::from_bytes(pair[0].as_bytes()).map_err(|e| e.to_string())?;
+            let value = hyper::header::HeaderValue::from_str(&pair[1]).map_err(|e| e.to_string())?;
+            if replaced.insert(name.clone()) {
+                map.insert(name, value);
+            } else {
+                map.append(name, value);
+            }
+        }
+    }
+    Ok(builder)
+}
+
+/// Checks `limits` in order, stopping at the first rejection. Returns the decision to
+/// report: the rejection, or else the most constrained of `status` and the new ones.
+/// Fails only when Redis cannot answer and the failure mode is `Closed`.
+async fn enforce_rate_limits(
+    limits: &[&RateLimit],
+    identity: &RequestIdentity<'_>,
+    mut status: Option<Decision>,
+    redis: &RedisPool,
+    rate_limit_store: &BoundedCache<LimiterState>,
+    metrics: &ServerMetrics,
+) -> Result<Option<Decision>, RedisUnavailable> {
+    let failure_mode = redis.settings().failure_mode;
+
+    for limit in limits {
+        let key = limit.key(identity);
+
+        // Distributed Rate Limit (Redis) - same algorithms, evaluated atomically in Lua
+        let remote = if redis.is_configured() {
+            let remote_key = key.clone();
+            Some(redis.call(|mut con| async move { limit.check_redis(&mut con, &remote_key).await }).await)
+        } else {
+            None
+        };
+
+        let (decision, rejections) = match remote {
+            Some(Ok(decision)) => (decision, &metrics.rate_limited_redis),
+            Some(Err(e)) if failure_mode == FailureMode::Closed => return Err(e),
+            Some(Err(_)) if failure_mode == FailureMode::Open => continue,
+            // Local Rate Limit (in-process store), also the fallback while Redis is down
+            _ => (limit.check_local(rate_limit_store, &key), &metrics.rate_limited_local),
+        };
+
+        if !decision.allowed {
+            rejections.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(decision));
+        }
+        if status.as_ref().is_none_or(|s| decision.remaining < s.remaining) {
+            status = Some(decision);
+        }
+    }
+    Ok(status)
+}
+
+enum AuthFailure {
+    NotConfigured,
+    MissingToken,
+    InvalidToken,
+    MissingApiKey,
+    InvalidApiKey,
+    BasicChallenge(String), // WWW-Authenticate value of the realm
+    Forbidden(String),
+    CertificateRejected(String), // Missing or unacceptable mTLS client certificate
+    CsrfRejected(String), // Unsafe request without a matching token or from another site
+}
+
+/// Who the request was authenticated as, by either mechanism.
+#[derive(Default)]
+struct Principal {
+    claims: Option<serde_json::Value>,
+    api_key: Option<Arc<ApiKey>>,
+}
+
+impl AuthFailure {
+    fn into_response(self, builder: hyper::http::response::Builder) -> Response<BoxBody<Bytes, std::io::Error>> {
+        let builder = match &self {
+            AuthFailure::NotConfigured => builder.status(StatusCode::INTERNAL_SERVER_ERROR),
+            AuthFailure::MissingToken => builder
+                .status(StatusCode::UNAUTHORIZED)
+                .header(hyper::header::WWW_AUTHENTICATE, "Bearer"),
+            AuthFailure::InvalidToken => builder
+                .status(StatusCode::UNAUTHORIZED)
+                .header(hyper::header::WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#),
+            AuthFailure::MissingApiKey | AuthFailure::InvalidApiKey => builder.status(StatusCode::UNAUTHORIZED),
+            AuthFailure::BasicChallenge(challenge) => builder
+                .status(StatusCode::UNAUTHORIZED)
+                .header(hyper::header::WWW_AUTHENTICATE, challenge.as_str()),
+            AuthFailure::Forbidden(_) => builder
+                .status(StatusCode::FORBIDDEN)
+                .header("Content-Type", "application/problem+json")
+                .header(hyper::header::WWW_AUTHENTICATE, r#"Bearer error="insufficient_scope""#),
+            AuthFailure::CertificateRejected(_) | AuthFailure::CsrfRejected(_) => builder
+                .status(StatusCode::FORBIDDEN)
+                .header("Content-Type", "application/problem+json"),
+        };
+        let body = match self {
+            AuthFailure::NotConfigured => "Authentication Not Configured".to_string(),
+            AuthFailure::MissingToken => "Missing Bearer Token".to_string(),
+            AuthFailure::InvalidToken => "Invalid Token".to_string(),
+            AuthFailure::MissingApiKey => "Missing API Key".to_string(),
+            AuthFailure::InvalidApiKey => "Invalid API Key".to_string(),
+            AuthFailure::BasicChallenge(_) => "Unauthorized".to_string(),
+            // RFC 9457 problem body: the token is fine, its grants are not
+            AuthFailure::Forbidden(detail) | AuthFailure::CertificateRejected(detail) | AuthFailure::CsrfRejected(detail) => serde_json::json!({
+                "type": "about:blank",
+                "title": "Forbidden",
+                "status": 403,
+                "detail": detail,
+            }).to_string(),
+        };
+        builder.body(full(body)).unwrap()
+    }
+}
+
+/// Checks the client certificate, JWT, API key and Basic credentials the route asks for,
+/// then its scope/role requirements against the token, key or certificate presented.
+#[allow(clippy::too_many_arguments)]
+async fn authenticate(
+    jwt: &JwtVerifier,
+    api_keys: &ApiKeyStore,
+    basic_auth: &BasicAuth,
+    client_cert: Option<&ClientCert>,
+    policies: &RoutePolicies,
+    headers: &hyper::HeaderMap,
+    query: Option<&str>,
+    upgrade: bool,
+) -> Result<Principal, AuthFailure> {
+    let mut principal = Principal::default();
+    if let Some(policy) = &policies.client_cert {
+        policy.check(client_cert).map_err(AuthFailure::CertificateRejected)?;
+    }
+    if policies.jwt_auth {
+        if !jwt.has_keys() {
+            // Keys were removed after start(); never let the request through unverified
+            return Err(AuthFailure::NotConfigured);
+        }
+        let token = jwt.extract_token(headers, query, upgrade).ok_or(AuthFailure::MissingToken)?;
+        principal.claims = Some(jwt.verify(&token).map_err(|_| AuthFailure::InvalidToken)?);
+    }
+    if policies.api_key_auth {
+        let key = api_keys.extract(headers).ok_or(AuthFailure::MissingApiKey)?;
+        principal.api_key = Some(api_keys.lookup(key).ok_or(AuthFailure::InvalidApiKey)?);
+    }
+    if let Some(name) = &policies.basic_auth {
+        check_basic(basic_auth, name, headers).await?;
+    }
+
+    // A valid token or key is not enough for every route
+    let cert_grants = client_cert.filter(|_| principal.claims.is_none() && principal.api_key.is_none()).map(ClientCert::to_json);
+    let grants = principal.claims.as_ref()
+        .or(principal.api_key.as_ref().map(|key| &key.metadata))
+        .or(cert_grants.as_ref());
+    let unmet = policies.authorize.iter()
+        .find(|requirement| !grants.is_some_and(|grants| requirement.satisfied_by(grants)));
+    if let Some(requirement) = unmet {
+        return Err(AuthFailure::Forbidden(format!("Requires {}", requirement.describe())));
+    }
+    Ok(principal)
+}
+
+/// Basic credentials against realm `name`. A realm that does not exist rejects everyone.
+async fn check_basic(basic_auth: &BasicAuth, name: &str, headers: &hyper::HeaderMap) -> Result<String, AuthFailure> {
+    let Some(realm) = basic_auth.realm(name) else {
+        return Err(AuthFailure::NotConfigured);
+    };
+    realm.check(headers).await.ok_or_else(|| AuthFailure::BasicChallenge(realm.challenge()))
+}
+
+/// Fail-closed answer while Redis cannot be asked.
+fn rate_limiter_unavailable(builder: hyper::http::response::Builder) -> Response<BoxBody<Bytes, std::io::Error>> {
+    builder
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(hyper::header::RETRY_AFTER, "1")
+        .body(full("Rate Limiter Unavailable"))
+        .unwrap()
+}
+
+fn session_store_unavailable(builder: hyper::http::response::Builder) -> Response<BoxBody<Bytes, std::io::Error>> {
+    builder
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(hyper::header::RETRY_AFTER, "1")
+        .body(full("Session Store Unavailable"))
+        .unwrap()
+}
+
+fn header_pairs(headers: &hyper::HeaderMap) -> Vec<(String, String)> {
+    headers.iter()
+        .map(|(k, v)| (k.as_str().to_string(), v.to_str().unwrap_or("").to_string()))
+        .collect()
+}
+
+/// Replays a cached response with its original status and headers.
+fn serve_cached(cached: &CachedResponse, x_cache: &'static str) -> Response<BoxBody<Bytes, std::io::Error>> {
+    let mut response = Response::new(full(cached.body.clone()));
+    *response.status_mut() = cached.status;
+    *response.headers_mut() = cached.headers.clone();
+    response.headers_mut().insert(hyper::header::AGE, cached.age().as_secs().into());
+    response.headers_mut().insert(X_CACHE, hyper::header::HeaderValue::from_static(x_cache));
+    response
+}
+
+const X_CACHE: &str = "x-cache";
+
+/// Where `store_response` puts a response: the in-process tier and, if enabled, Redis.
+struct CacheTarget<'a> {
+    store: &'a BoundedCache<Arc<CachedResponse>>,
+    shared: Option<Arc<SharedCache>>,
+    key: String,
+    lifetime: CacheLifetime,
+    generation: u64, // Cache generation when the request looked the key up
+}
+
+/// L1 first; on a miss, an entry from the shared tier is copied into L1 for its remaining
+/// lifetime, unless a purge started since `generation`.
+async fn lookup_cached(cache_store: &BoundedCache<Arc<CachedResponse>>, shared: Option<&SharedCache>, key: &str, generation: u64) -> Option<Arc<CachedResponse>> {
+    if let Some(cached) = cache_store.get(key) {
+        return Some(cached);
+    }
+    let cached = Arc::new(shared?.get(key).await?);
+    let remaining = cached.lifetime.retention().saturating_sub(cached.age());
+    if remaining.is_zero() {
+        return None;
+    }
+    cache_store.insert_since(generation, key.to_string(), cached.clone(), remaining);
+    Some(cached)
+}
+
+/// Passes a freshly generated response through the route cache: a 5xx is swapped for a
+/// `stale-if-error` copy when one is still usable, and cacheable responses are stored.
+async fn store_response(
+    response: Response<BoxBody<Bytes, std::io::Error>>,
+    target: Option<CacheTarget<'_>>,
+    path: &str,
+    stale: Option<Arc<CachedResponse>>,
+) -> Response<BoxBody<Bytes, std::io::Error>> {
+    let Some(target) = target else {
+        return response;
+    };
+    let lifetime = target.lifetime;
+
+    if response.status().is_server_error() {
+        if let Some(stale) = stale.filter(|c| c.freshness() != Freshness::Expired) {
+            return serve_cached(&stale, "STALE");
+        }
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(X_CACHE, hyper::header::HeaderValue::from_static("MISS"));
+    if !cache::is_cacheable_status(parts.status) || !cache::is_storable(&parts.headers) {
+        // Surrogate keys are for us, not for the client
+        parts.headers.remove(cache::CACHE_TAG);
+        return Response::from_parts(parts, body);
+    }
+
+    // Collect body to cache
+    let bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(full(format!("Error reading body for cache: {}", e)))
+            .unwrap(),
+    };
+
+    if !parts.headers.contains_key(hyper::header::CACHE_CONTROL) {
+        if let Ok(value) = hyper::header::HeaderValue::from_str(&lifetime.cache_control()) {
+            parts.headers.insert(hyper::header::CACHE_CONTROL, value);
+        }
+    }
+
+    let cached = Arc::new(CachedResponse::new(path.to_string(), parts.status, &parts.headers, bytes.clone(), lifetime));
+    // Generated from data a purge has since invalidated: serve it, but store it nowhere
+    let stored = target.store.insert_since(target.generation, target.key.clone(), cached.clone(), lifetime.retention());
+    if let Some(shared) = target.shared.filter(|_| stored) {
+        tokio::spawn(async move { shared.set(&target.key, &cached).await });
+    }
+    parts.headers.remove(cache::CACHE_TAG);
+    Response::from_parts(parts, full(bytes))
+}
+
 struct RequestGuard {
     metrics: Arc<ServerMetrics>,
     start: Instant,
@@ -621,56 +1245,60 @@ impl Drop for RequestGuard {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_request(
     req: Request<Incoming>,
     remote_addr: SocketAddr,
+    client_cert: Option<Arc<ClientCert>>, // Verified during the TLS handshake
+    tls: bool, // The connection is TLS
     router: Arc<Router>,
     callback: Option<ThreadsafeFunction<RequestEvent, ErrorStrategy::Fatal>>,
     ws_callback: Option<ThreadsafeFunction<WsEvent, ErrorStrategy::Fatal>>,
     req_id: String,
-    static_routes: Arc<Mutex<Vec<(String, String)>>>,
+    static_routes: Arc<Mutex<Vec<StaticMount>>>,
     ws_peers: WsPeers,
     ws_rooms: WsRooms,
-    cors_config: Arc<Mutex<Option<CorsConfig>>>,
-    rate_limit_store: Arc<DashMap<String, (u32, Instant)>>,
-    cache_store: Arc<DashMap<String, (Bytes, Instant)>>,
-    jwt_secret: Arc<Mutex<Option<String>>>,
-    redis_client: Arc<Mutex<Option<redis::Client>>>,
+    rate_limit_store: Arc<BoundedCache<LimiterState>>,
+    trusted_proxies: Arc<Mutex<TrustedProxies>>,
+    cache_store: Arc<BoundedCache<Arc<CachedResponse>>>,
+    cache_inflight: Arc<SingleFlight>,
+    shared_cache: Option<Arc<SharedCache>>,
+    jwt: Arc<JwtVerifier>,
+    api_keys: Arc<ApiKeyStore>,
+    basic_auth: Arc<BasicAuth>,
+    redis: Arc<RedisPool>,
     metrics: Arc<ServerMetrics>,
-    security_headers: Arc<AtomicBool>,
+    metrics_page: MetricsPage,
+    csrf: Arc<Mutex<Option<Arc<CsrfPolicy>>>>,
+    sessions: Arc<SessionStore>,
     schema_cache: Arc<DashMap<String, Arc<Validator>>>,
     governor: Arc<TrafficGovernor>,
+    security: &mut RequestSecurity, // Security headers and CSP nonce, applied by the caller
+    cors: &mut RequestCors, // CORS headers, applied by the caller
+    extra_headers: &mut hyper::HeaderMap, // Added to whatever response is returned
 ) -> Result<Response<BoxBody<Bytes, std::io::Error>>, Infallible> {
     let _guard = RequestGuard::new(metrics.clone());
     let method = req.method().clone();
     let uri = req.uri().clone();
     let path = uri.path().to_string();
-    
-    // Helper to inject security headers
-    let make_builder = || {
-        let mut builder = Response::builder();
-        if security_headers.load(Ordering::Relaxed) {
-            builder = builder
-                .header("X-Content-Type-Options", "nosniff")
-                .header("X-Frame-Options", "SAMEORIGIN")
-                .header("X-XSS-Protection", "1; mode=block")
-                .header("Referrer-Policy", "strict-origin-when-cross-origin");
-        }
-        builder
-    };
 
     // Metrics Endpoint - BYPASS GOVERNOR
     if path == "/metrics" && method == hyper::Method::GET {
-        return Ok(make_builder()
+        if let Some(realm) = basic_auth.metrics_realm() {
+            if let Err(failure) = check_basic(&basic_auth, &realm, req.headers()).await {
+                return Ok(failure.into_response(Response::builder()));
+            }
+        }
+        return Ok(Response::builder()
             .status(StatusCode::OK)
             .header("Content-Type", "text/plain")
-            .body(full(metrics.render()))
+            .body(full(metrics_page()))
             .unwrap());
     }
 
     // Health Check - BYPASS GOVERNOR (or High Priority)
     if path == "/health" {
-         return Ok(make_builder()
+         return Ok(Response::builder()
             .status(StatusCode::OK)
             .body(full("OK"))
             .unwrap());
@@ -688,14 +1316,11 @@ async fn handle_request(
     
     // Determine Priority via Router Lookup or Static Check
     let route_match = router.lookup(method.as_str(), &path);
+    security.route(route_match.as_ref().and_then(|(action, _)| action.policies().security_headers.as_deref()));
+    cors.route(route_match.as_ref().and_then(|(action, _)| action.policies().cors.as_ref()));
 
     let (priority, slo_target) = if let Some((action, _)) = &route_match {
-        let policies = match action {
-            RouteAction::JsHandler { policies, .. } => policies,
-            RouteAction::Static { policies, .. } => policies,
-            RouteAction::Json { policies, .. } => policies,
-            RouteAction::Upload { policies, .. } => policies,
-        };
+        let policies = action.policies();
         
         let p = if let Some(p) = &policies.priority {
             Priority::from_str(p)
@@ -706,12 +1331,7 @@ async fn handle_request(
         };
         (p, policies.slo_target)
     } else {
-        let is_static = static_routes.lock().unwrap().iter().any(|(prefix, _)| path.starts_with(prefix));
-        if is_static {
-            (Priority::Interactive, None)
-        } else {
-            (Priority::Interactive, None)
-        }
+        (Priority::Interactive, None)
     };
 
     // GOVERNOR ADMISSION CONTROL
@@ -744,33 +1364,29 @@ async fn handle_request(
         slo_target,
     };
 
-    // Check Accept-Encoding
-    let accept_encoding = req.headers()
-        .get(hyper::header::ACCEPT_ENCODING)
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("")
-        .to_string();
-
-    // 0. Handle CORS Preflight (OPTIONS)
-    if req.method() == hyper::Method::OPTIONS {
-        let config = cors_config.lock().unwrap();
-        if let Some(cors) = &*config {
-            let mut builder = make_builder()
-                .status(StatusCode::NO_CONTENT)
-                .header("Access-Control-Allow-Origin", &cors.origin)
-                .header("Access-Control-Allow-Methods", &cors.methods)
-                .header("Access-Control-Allow-Headers", &cors.headers);
-            
-            if cors.credentials {
-                builder = builder.header("Access-Control-Allow-Credentials", "true");
+    // 0. Handle CORS Preflight (OPTIONS), under the policy of the route it asks about
+    if RequestCors::is_preflight(&method, req.headers()) {
+        let target = req.headers().get(hyper::header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .and_then(|m| router.lookup(m, &path));
+        cors.route(target.as_ref().and_then(|(action, _)| action.policies().cors.as_ref()));
+        match cors.preflight(req.headers()) {
+            Some(Ok(headers)) => {
+                let mut response = Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(full(""))
+                    .unwrap();
+                *response.headers_mut() = headers;
+                return Ok(response);
             }
-            
-            // Allow caching of preflight response
-            builder = builder.header("Access-Control-Max-Age", "86400");
-
-            return Ok(builder
-                .body(full(""))
-                .unwrap());
+            Some(Err(reason)) => {
+                debug!(target: "Server", path = %path, reason = %reason, "cors_preflight_rejected");
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(full("CORS preflight rejected"))
+                    .unwrap());
+            }
+            None => {}
         }
     }
     
@@ -781,6 +1397,19 @@ async fn handle_request(
              .unwrap_or(false);
 
          if is_websocket {
+             // A GET route on the same path carries the auth policies for the socket
+             let protected = |action: &&RouteAction| {
+                 let policies = action.policies();
+                 policies.jwt_auth || policies.api_key_auth || policies.basic_auth.is_some() || policies.client_cert.is_some()
+             };
+             let ws_user = match route_match.as_ref().map(|(action, _)| action).filter(protected) {
+                 Some(action) => match authenticate(&jwt, &api_keys, &basic_auth, client_cert.as_deref(), action.policies(), req.headers(), uri.query(), true).await {
+                     Ok(principal) => principal.claims,
+                     Err(failure) => return Ok(failure.into_response(Response::builder())),
+                 },
+                 None => None,
+             };
+
              let key = req.headers().get("Sec-WebSocket-Key")
                 .map(|v| v.to_str().unwrap_or("").to_string())
                 .unwrap_or_default();
@@ -816,6 +1445,7 @@ async fn handle_request(
                                             event_type: "open".to_string(),
                                             payload: None,
                                             path: Some(path_clone),
+                                            user: ws_user,
                                         }, ThreadsafeFunctionCallMode::NonBlocking);
                                     }
 
@@ -825,7 +1455,7 @@ async fn handle_request(
                                             msg = rx.recv() => {
                                                 match msg {
                                                     Some(m) => {
-                                                        if let Err(_) = ws.send(m).await {
+                                                        if ws.send(m).await.is_err() {
                                                             break;
                                                         }
                                                     }
@@ -842,6 +1472,7 @@ async fn handle_request(
                                                                      event_type: "message".to_string(),
                                                                      payload: Some(m.to_string()),
                                                                      path: None,
+                                                                     user: None,
                                                                  }, ThreadsafeFunctionCallMode::NonBlocking);
                                                              }
                                                         } else if m.is_close() {
@@ -879,10 +1510,11 @@ async fn handle_request(
                                     // Notify Close
                                     if let Some(cb) = &ws_callback {
                                         cb.call(WsEvent {
-                                            socket_id: socket_id,
+                                            socket_id,
                                             event_type: "close".to_string(),
                                             payload: None,
                                             path: None,
+                                            user: None,
                                         }, ThreadsafeFunctionCallMode::NonBlocking);
                                     }
                                 // } Removed Ok match brace
@@ -908,82 +1540,35 @@ async fn handle_request(
     // 2. Check Static Files (Level 0)
     let matched_static_route = {
         let static_routes = static_routes.lock().unwrap();
-        static_routes.iter().find_map(|(prefix, dir)| {
-            if path.starts_with(prefix) {
-                Some((prefix.clone(), dir.clone()))
-            } else {
-                None
-            }
+        static_routes.iter().find_map(|mount| {
+            mount.strip_prefix(&path).map(|rest| (mount.clone(), rest.to_string()))
         })
     };
 
-    if let Some((prefix, dir)) = matched_static_route {
-        let rest_path = &path[prefix.len()..];
-        let file_path = std::path::Path::new(&dir).join(rest_path.trim_start_matches('/'));
-        
-        // Security: Prevent directory traversal
-        if !file_path.starts_with(&dir) {
-            return Ok(make_builder()
-                .status(StatusCode::FORBIDDEN)
-                .body(full("Forbidden"))
-                .unwrap());
-        }
-
-        // Async check for existence
-        if tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
-            let is_file = tokio::fs::metadata(&file_path).await.map(|m| m.is_file()).unwrap_or(false);
-            
-            if is_file {
-                let file = match File::open(&file_path).await {
-                    Ok(f) => f,
-                    Err(_) => return Ok(make_builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(full("File Read Error"))
-                        .unwrap()),
-                };
-                
-                let mime_type = mime_guess::from_path(&file_path).first_or_octet_stream();
-                
-                // Check for Compression Support
-                let use_gzip = accept_encoding.contains("gzip");
-                let use_brotli = accept_encoding.contains("br");
-
-                // Zero-copy stream
-                let stream = ReaderStream::new(file);
-                
-                let mut builder = make_builder()
-                    .status(StatusCode::OK)
-                    .header("Content-Type", mime_type.as_ref());
-
-                if let Some(config) = cors_config.lock().unwrap().as_ref() {
-                    builder = builder.header("Access-Control-Allow-Origin", &config.origin);
-                }
-
-                if use_brotli {
-                    let file = File::open(&file_path).await.unwrap();
-                    let reader = BufReader::new(file);
-                    let compressed = BrotliEncoder::with_quality(reader, async_compression::Level::Best);
-                    let stream = ReaderStream::new(compressed);
-                    let body = StreamBody::new(stream.map(|f| f.map(Frame::data).map_err(|e| e)));
-                    return Ok(builder
-                        .header("Content-Encoding", "br")
-                        .body(BodyExt::boxed(body))
-                        .unwrap());
-                } else if use_gzip {
-                    let file = File::open(&file_path).await.unwrap();
-                    let reader = BufReader::new(file);
-                    let compressed = GzipEncoder::with_quality(reader, async_compression::Level::Best);
-                    let stream = ReaderStream::new(compressed);
-                    let body = StreamBody::new(stream.map(|f| f.map(Frame::data).map_err(|e| e)));
-                    return Ok(builder
-                        .header("Content-Encoding", "gzip")
-                        .body(BodyExt::boxed(body))
-                        .unwrap());
-                }
-
-                let body = StreamBody::new(stream.map(|result| result.map(Frame::data)));
-                return Ok(builder.body(BodyExt::boxed(body)).unwrap());
+    let is_head = method == hyper::Method::HEAD;
+    if let Some((mount, rest_path)) = matched_static_route.filter(|_| method == hyper::Method::GET || is_head) {
+        // Security: percent-decode, reject traversal and apply the mount's symlink policy
+        match mount.resolve(&rest_path).await {
+            Ok(resolved) => {
+                let builder = Response::builder();
+                return Ok(match resolved {
+                    Resolved::Asset(asset) => static_files::serve_asset(builder, &asset, req.headers(), is_head),
+                    Resolved::File(file_path) => match static_files::serve_file(builder, &file_path, req.headers(), is_head).await {
+                        Ok(response) => response,
+                        Err(_) => Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(full("File Read Error"))
+                            .unwrap(),
+                    },
+                });
             }
+            Err(ResolveError::Forbidden) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(full("Forbidden"))
+                    .unwrap());
+            }
+            Err(ResolveError::NotFound) => {}
         }
     }
 
@@ -992,159 +1577,189 @@ async fn handle_request(
     if let Some((route_action, params_vec)) = route_match {
         
         // 3.1 Extract Policies
-        let policies = match &route_action {
-            RouteAction::JsHandler { policies, .. } => policies.clone(),
-            RouteAction::Static { policies, .. } => policies.clone(),
-            RouteAction::Json { policies, .. } => policies.clone(),
-            RouteAction::Upload { policies, .. } => policies.clone(),
+        let policies = route_action.policies().clone();
+
+        // 3.2 Rate Limit Check (limits keyed by JWT subject or API key run after auth)
+        let client_ip = trusted_proxies.lock().unwrap().client_ip(remote_addr.ip(), req.headers());
+        let (limits_after_auth, limits_before_auth): (Vec<&RateLimit>, Vec<&RateLimit>) =
+            policies.rate_limits.iter().partition(|limit| limit.needs_subject());
+        let identity = RequestIdentity {
+            method: method.as_str(),
+            path: &path,
+            pattern: &policies.pattern,
+            ip: client_ip,
+            headers: req.headers(),
+            subject: None,
+            api_key: None,
         };
+        let mut rate_limit_status = match enforce_rate_limits(&limits_before_auth, &identity, None, &redis, &rate_limit_store, &metrics).await {
+            Ok(status) => status,
+            Err(_) => return Ok(rate_limiter_unavailable(Response::builder())),
+        };
+        if let Some(decision) = rate_limit_status.as_ref().filter(|d| !d.allowed) {
+            decision.write_headers(extra_headers);
+            return Ok(Response::builder()
+               .status(StatusCode::TOO_MANY_REQUESTS)
+               .body(full("Rate Limit Exceeded"))
+               .unwrap());
+        }
 
-        // 3.2 Rate Limit Check
-        if let Some((limit, window_sec)) = policies.rate_limit {
-             let key = format!("{} {} {}", method, path, remote_addr.ip());
-             
-             // Distributed Rate Limit (Redis)
-              let mut redis_opt = redis_client.lock().unwrap().clone();
-              if let Some(client) = redis_opt.as_mut() {
-                     let con = client.get_multiplexed_async_connection().await.ok();
-                 if let Some(mut con) = con {
-                    // Lua script for atomic INCR + EXPIRE
-                    let script = redis::Script::new(r"
-                        let current = redis.call('INCR', KEYS[1])
-                        if tonumber(current) == 1 then
-                            redis.call('EXPIRE', KEYS[1], ARGV[1])
-                        end
-                        return current
-                    ");
-                    let count: u32 = script.key(&key).arg(window_sec).invoke_async(&mut con).await.unwrap_or(0);
-                    
-                    if count > limit {
-                         return Ok(make_builder()
-                            .status(StatusCode::TOO_MANY_REQUESTS)
-                            .body(full("Rate Limit Exceeded (Distributed)"))
-                            .unwrap());
-                    }
-                    // If Redis works, we skip local check
-                    // But if we want to be safe, we can continue? No, Redis is authoritative.
-                    // To break out of local check block, we need to structure this better.
-                    // For now, let's use a flag or else.
-                 } else {
-                     // Redis connection failed, fallback to local?
-                     // Fallback below
-                 }
-             } else {
-                 // Local Rate Limit (DashMap)
-                 let now = Instant::now();
-                 
-                 let mut entry = rate_limit_store.entry(key).or_insert((0, now));
-                 let (count, start) = entry.value_mut();
-
-                 if start.elapsed() > Duration::from_secs(window_sec as u64) {
-                     // Reset
-                     *count = 1;
-                     *start = now;
-                 } else {
-                     *count += 1;
-                     if *count > limit {
-                          return Ok(make_builder()
-                             .status(StatusCode::TOO_MANY_REQUESTS)
-                             .body(full("Rate Limit Exceeded"))
-                             .unwrap());
-                     }
-                 }
-             }
+        // 3.3 JWT / API key / Basic auth and scope/role policies, natively before any JS code runs
+        let principal = match authenticate(&jwt, &api_keys, &basic_auth, client_cert.as_deref(), &policies, req.headers(), uri.query(), false).await {
+            Ok(principal) => principal,
+            Err(failure) => return Ok(failure.into_response(Response::builder())),
+        };
+        let auth_claims = principal.claims;
+        let api_key_metadata = principal.api_key.as_ref().map(|key| key.metadata.clone());
+        let client_cert_json = client_cert.as_ref().map(|cert| cert.to_json());
+
+        let auth_subject = auth_claims.as_ref().and_then(|claims| claims.get("sub")).and_then(|sub| sub.as_str());
+
+        // 3.3.1 CSRF: hand out a token on safe requests; unsafe ones are checked before dispatch
+        let csrf_policy = if policies.csrf_exempt { None } else { csrf.lock().unwrap().clone() };
+        let csrf_scheme = trusted_proxies.lock().unwrap().scheme(remote_addr.ip(), req.headers(), tls);
+        let csrf_binding = match &csrf_policy {
+            Some(_) => CsrfPolicy::binding(
+                sessions.cookie_value(req.headers()).as_deref(),
+                auth_subject,
+                principal.api_key.as_ref().map(|key| key.id.as_str()),
+            ),
+            None => String::new(),
+        };
+        let mut csrf_token = csrf_policy.as_ref().and_then(|csrf| csrf.cookie_token(req.headers(), &csrf_binding));
+        if let Some(csrf) = csrf_policy.as_ref().filter(|_| csrf_token.is_none() && CsrfPolicy::is_safe(&method)) {
+            csrf_token = csrf.issue(&csrf_binding);
+            if let Some(cookie) = csrf_token.as_deref().and_then(|token| csrf.set_cookie(token)) {
+                extra_headers.append(hyper::header::SET_COOKIE, cookie);
+            }
+        }
+        let csrf_check = csrf_policy.filter(|_| !CsrfPolicy::is_safe(&method));
+
+        if !limits_after_auth.is_empty() {
+            let api_key = principal.api_key.as_ref().map(|key| key.id.as_str());
+            let identity = RequestIdentity { subject: auth_subject, api_key, ..identity };
+            rate_limit_status = match enforce_rate_limits(&limits_after_auth, &identity, rate_limit_status, &redis, &rate_limit_store, &metrics).await {
+                Ok(status) => status,
+                Err(_) => return Ok(rate_limiter_unavailable(Response::builder())),
+            };
+            if let Some(decision) = rate_limit_status.as_ref().filter(|d| !d.allowed) {
+                decision.write_headers(extra_headers);
+                return Ok(Response::builder()
+                   .status(StatusCode::TOO_MANY_REQUESTS)
+                   .body(full("Rate Limit Exceeded"))
+                   .unwrap());
+            }
+        }
+        if let Some(decision) = &rate_limit_status {
+            decision.write_headers(extra_headers);
         }
 
-        // 3.3 JWT Auth Check (Placeholder for real implementation)
-        // If the route is protected, we check for Bearer token
-        // This is where Native JWT verification happens before any JS code runs!
-        if policies.jwt_auth {
-            if let Some(secret) = jwt_secret.lock().unwrap().clone() {
-                let auth_header = req.headers().get("Authorization")
-                    .and_then(|h| h.to_str().ok())
-                    .unwrap_or("");
-                
-                if !auth_header.starts_with("Bearer ") {
-                     return Ok(make_builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(full("Missing Bearer Token"))
-                        .unwrap());
+        // 3.4 Cache Check (GET only)
+        let cache_key = match policies.cache_ttl {
+            Some(_) if method == hyper::Method::GET => Some(policies.cache_key.key(
+                method.as_str(),
+                &path,
+                uri.query(),
+                req.headers(),
+                auth_subject,
+                principal.api_key.as_ref().map(|key| key.id.as_str()),
+            )),
+            _ => None,
+        };
+        let cache_lifetime = policies.cache_lifetime();
+        let is_js = matches!(route_action, RouteAction::JsHandler { .. });
+        let mut stale: Option<Arc<CachedResponse>> = None;
+        let mut _flight_lease: Option<FlightLease> = None;
+        let cache_generation = cache_store.generation();
+        if let (Some(key), Some(lifetime)) = (&cache_key, cache_lifetime) {
+            if let Some(cached) = lookup_cached(&cache_store, shared_cache.as_deref(), key, cache_generation).await {
+                match cached.freshness() {
+                    Freshness::Fresh => return Ok(serve_cached(&cached, "HIT")),
+                    Freshness::Revalidate if is_js => {
+                        // One background refresh per key; everyone else keeps getting the stale copy
+                        if let (RouteAction::JsHandler { id, .. }, Some(cb), Some(lease)) = (&route_action, &callback, cache_inflight.try_lead(key)) {
+                            let (tx, rx) = oneshot::channel();
+                            let event = RequestEvent {
+                                handler_id: *id,
+                                req_id: req_id.clone(),
+                                params: params_vec.clone(),
+                                query: uri.query().unwrap_or("").to_string(),
+                                headers: header_pairs(req.headers()),
+                                method: method.to_string(),
+                                url: uri.to_string(),
+                                body: Vec::new(),
+                                user: auth_claims.clone(),
+                                api_key: api_key_metadata.clone(),
+                                client_cert: client_cert_json.clone(),
+                                csp_nonce: security.nonce().map(str::to_string),
+                                csrf_token: csrf_token.clone(),
+                                cookies: cookies::parse(req.headers()),
+                                session: None, // A background refresh has no one to hand a cookie to
+                                response_sender: Mutex::new(Some(tx)),
+                            };
+                            cb.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+
+                            let (cache_store, shared_cache, key, path) = (cache_store.clone(), shared_cache.clone(), key.clone(), path.clone());
+                            tokio::spawn(async move {
+                                let _lease = lease;
+                                if let Ok(response) = rx.await {
+                                    let target = CacheTarget { store: &cache_store, shared: shared_cache, key, lifetime, generation: cache_generation };
+                                    store_response(response, Some(target), &path, None).await;
+                                }
+                            });
+                        }
+                        return Ok(serve_cached(&cached, "STALE"));
+                    }
+                    _ => {}
                 }
+                stale = Some(cached);
+            }
 
-                let token = &auth_header[7..];
-                let decoding_key = DecodingKey::from_secret(secret.as_bytes());
-                let validation = Validation::default();
-                
-                if decode::<Claims>(token, &decoding_key, &validation).is_err() {
-                    return Ok(make_builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(full("Invalid Token"))
-                        .unwrap());
+            // Concurrent misses wait for a single handler call instead of stampeding it
+            if is_js {
+                match cache_inflight.join(key) {
+                    Flight::Leader(lease) => _flight_lease = Some(lease),
+                    Flight::Follower(flight) => {
+                        flight.wait().await;
+                        if let Some(cached) = cache_store.get(key) {
+                            if cached.freshness() == Freshness::Fresh {
+                                return Ok(serve_cached(&cached, "HIT"));
+                            }
+                        }
+                        // The leader failed; a usable stale copy beats calling the handler again
+                        if let Some(cached) = stale.as_ref().filter(|c| c.freshness() != Freshness::Expired) {
+                            return Ok(serve_cached(cached, "STALE"));
+                        }
+                    }
                 }
-            } else {
-                 // Warn: Protected route but no secret set
-                 eprintln!("Warning: Route requires JWT but no secret set in NativeServer");
             }
         }
 
-        // 3.3 Cache Check (GET only)
-        if method == hyper::Method::GET {
-             if let Some(_ttl_sec) = policies.cache_ttl {
-                 if let Some(cached) = cache_store.get(&path) {
-                     let (body, expiry) = cached.value();
-                     if expiry.elapsed() < Duration::from_secs(0) {
-                         // Valid cache (Wait, elapsed < 0 means future? No, expiry should be absolute time or Instant created at + TTL)
-                         // Store expiry as Instant (deadline).
-                         // if now < expiry
-                         if Instant::now() < *expiry {
-                             return Ok(make_builder()
-                                .status(StatusCode::OK)
-                                .body(full(body.clone()))
-                                .unwrap());
-                         }
-                     }
-                 }
-             }
-        }
+        let cache_target = match (cache_key, cache_lifetime) {
+            (Some(key), Some(lifetime)) => Some(CacheTarget { store: &cache_store, shared: shared_cache.clone(), key, lifetime, generation: cache_generation }),
+            _ => None,
+        };
 
         match route_action {
             RouteAction::Static { content, content_type, .. } => {
-                let mut builder = make_builder()
+                let builder = Response::builder()
                     .status(StatusCode::OK)
                     .header("Content-Type", content_type);
 
-                if let Some(config) = cors_config.lock().unwrap().as_ref() {
-                    builder = builder.header("Access-Control-Allow-Origin", &config.origin);
-                }
-
-                if method == hyper::Method::GET {
-                    if let Some(ttl_sec) = policies.cache_ttl {
-                        cache_store.insert(path.to_string(), (Bytes::from(content.clone()), Instant::now() + Duration::from_secs(ttl_sec)));
-                    }
-                }
-
-                return Ok(builder
+                let response = builder
                     .body(full(content))
-                    .unwrap());
+                    .unwrap();
+                Ok(store_response(response, cache_target, &path, stale).await)
             },
             RouteAction::Json { content, .. } => {
-                let mut builder = make_builder()
+                let builder = Response::builder()
                     .status(StatusCode::OK)
                     .header("Content-Type", "application/json");
 
-                if let Some(config) = cors_config.lock().unwrap().as_ref() {
-                    builder = builder.header("Access-Control-Allow-Origin", &config.origin);
-                }
-
-                if method == hyper::Method::GET {
-                    if let Some(ttl_sec) = policies.cache_ttl {
-                        cache_store.insert(path.to_string(), (Bytes::from(content.clone()), Instant::now() + Duration::from_secs(ttl_sec)));
-                    }
-                }
-
-                return Ok(builder
+                let response = builder
                     .body(full(content))
-                    .unwrap());
+                    .unwrap();
+                Ok(store_response(response, cache_target, &path, stale).await)
             },
             RouteAction::JsHandler { id, policies } => {
                 // Generate Req ID early (Already done at start of function)
@@ -1153,26 +1768,37 @@ async fn handle_request(
                 let handle = Mutex::new(Some(tx));
 
                 // Extract headers before consuming body
-                let headers_vec: Vec<(String, String)> = req.headers().iter()
-                    .map(|(k, v)| (k.as_str().to_string(), v.to_str().unwrap_or("").to_string()))
-                    .collect();
+                let headers_vec = header_pairs(req.headers());
+                let csrf_headers = csrf_check.as_ref().map(|_| req.headers().clone());
+                let request_cookies = cookies::parse(req.headers());
+                let session = match sessions.load(req.headers()).await {
+                    Ok(session) => session,
+                    Err(_) => return Ok(session_store_unavailable(Response::builder())),
+                };
 
                 // Read Body
                 let body_bytes = match req.collect().await {
                     Ok(c) => c.to_bytes(),
-                    Err(e) => return Ok(make_builder()
+                    Err(e) => return Ok(Response::builder()
                         .status(StatusCode::BAD_REQUEST)
                         .body(full(format!("Bad Request Body: {}", e)))
                         .unwrap()),
                 };
 
+                // The token may be a form field, so this waits for the body; the handler never runs
+                if let (Some(csrf), Some(headers)) = (&csrf_check, &csrf_headers) {
+                    if let Err(e) = csrf.verify(headers, Some(&body_bytes), csrf_scheme, &csrf_binding) {
+                        return Ok(AuthFailure::CsrfRejected(e.to_string()).into_response(Response::builder()));
+                    }
+                }
+
                 // Native Schema Validation
                 if let Some(schema_str) = &policies.schema {
                     if !body_bytes.is_empty() {
                          // Parse body as JSON
                          let json_body: serde_json::Value = match serde_json::from_slice(&body_bytes) {
                              Ok(v) => v,
-                             Err(e) => return Ok(make_builder()
+                             Err(e) => return Ok(Response::builder()
                                  .status(StatusCode::BAD_REQUEST)
                                  .header("Content-Type", "application/json")
                                  .body(full(format!(r#"{{"error": "Invalid JSON: {}"}}"#, e)))
@@ -1216,7 +1842,7 @@ async fn handle_request(
                                         Err(e) => {
                                             eprintln!("Schema Compilation Error: {}", e);
                                             // Fail safe or allow? Let's fail safe.
-                                            return Ok(make_builder()
+                                            return Ok(Response::builder()
                                                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                                                 .body(full(format!("Invalid Schema Definition: {}", e)))
                                                 .unwrap());
@@ -1225,7 +1851,7 @@ async fn handle_request(
                                 },
                                 Err(e) => {
                                     eprintln!("Schema JSON Parse Error: {}", e);
-                                    return Ok(make_builder()
+                                    return Ok(Response::builder()
                                         .status(StatusCode::INTERNAL_SERVER_ERROR)
                                         .body(full(format!("Invalid Schema JSON: {}", e)))
                                         .unwrap());
@@ -1234,7 +1860,7 @@ async fn handle_request(
                          };
 
                          if let Some(errors) = validation_result {
-                             return Ok(make_builder()
+                             return Ok(Response::builder()
                                  .status(StatusCode::BAD_REQUEST)
                                  .header("Content-Type", "application/json")
                                  .body(full(format!(r#"{{"validation_errors": {:?}}}"#, errors)))
@@ -1244,6 +1870,8 @@ async fn handle_request(
                 }
 
                 if let Some(cb) = &callback {
+                    // Open for staging only while the handler is in flight
+                    let _session_ticket = session.as_ref().map(|_| sessions.begin(&req_id));
                     cb.call(RequestEvent {
                         handler_id: id,
                         req_id: req_id.clone(),
@@ -1253,47 +1881,48 @@ async fn handle_request(
                         method: method.to_string(),
                         url: uri.to_string(),
                         body: body_bytes.to_vec(),
+                        user: auth_claims,
+                        api_key: api_key_metadata,
+                        client_cert: client_cert_json,
+                        csp_nonce: security.nonce().map(str::to_string),
+                        csrf_token,
+                        cookies: request_cookies,
+                        session: session.as_ref().map(|session| session.data.clone()),
                         response_sender: handle,
                     }, ThreadsafeFunctionCallMode::NonBlocking);
 
                     // Wait for response
-                    match rx.await {
-                        Ok(response) => {
-                            // Cache Population
-                            if method == hyper::Method::GET {
-                                if let Some(ttl_sec) = policies.cache_ttl {
-                                    let (parts, body) = response.into_parts();
-                                    // Collect body to cache
-                                    let bytes = match body.collect().await {
-                                        Ok(collected) => collected.to_bytes(),
-                                        Err(e) => return Ok(make_builder()
-                                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                            .body(full(format!("Error reading body for cache: {}", e)))
-                                            .unwrap()),
-                                    };
-                                    
-                                    cache_store.insert(path.to_string(), (bytes.clone(), Instant::now() + Duration::from_secs(ttl_sec)));
-                                    return Ok(Response::from_parts(parts, full(bytes)));
-                                }
-                            }
-                            return Ok(response);
-                        },
+                    let response = match rx.await {
+                        Ok(response) => response,
                         Err(_) => {
                             // Sender dropped
-                            return Ok(make_builder()
+                            Response::builder()
                                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                                 .body(full("Internal Server Error: No response from handler"))
-                                .unwrap());
+                                .unwrap()
                         },
+                    };
+                    // Whatever the handler staged is written now that it has answered
+                    if let Some(session) = session {
+                        if let Some(cookie) = sessions.finish(session, &req_id).await {
+                            extra_headers.append(hyper::header::SET_COOKIE, cookie);
+                        }
                     }
+                    // Cache Population (unless the handler opted out via Cache-Control)
+                    Ok(store_response(response, cache_target, &path, stale).await)
                 } else {
-                    return Ok(make_builder()
+                    Ok(Response::builder()
                         .status(StatusCode::INTERNAL_SERVER_ERROR)
                         .body(full("Internal Server Error: No JS callback"))
-                        .unwrap());
+                        .unwrap())
                 }
             },
             RouteAction::Upload { dir, handler_id: _, .. } => {
+                if let Some(csrf) = &csrf_check {
+                    if let Err(e) = csrf.verify(req.headers(), None, csrf_scheme, &csrf_binding) {
+                        return Ok(AuthFailure::CsrfRejected(e.to_string()).into_response(Response::builder()));
+                    }
+                }
                 let boundary = req.headers()
                     .get("content-type")
                     .and_then(|ct| ct.to_str().ok())
@@ -1304,7 +1933,7 @@ async fn handle_request(
                         .filter_map(|res| async move {
                             match res {
                                 Ok(frame) => frame.into_data().ok().map(Ok),
-                                Err(e) => Some(Err(std::io::Error::new(std::io::ErrorKind::Other, e))),
+                                Err(e) => Some(Err(std::io::Error::other(e))),
                             }
                         });
                     let mut multipart = multer::Multipart::new(body_stream, boundary);
@@ -1331,33 +1960,47 @@ async fn handle_request(
                         "files": uploaded_files
                     }).to_string();
 
-                    let mut builder = make_builder()
+                    let builder = Response::builder()
                         .status(StatusCode::OK)
                         .header("Content-Type", "application/json");
-
-                    if let Some(config) = cors_config.lock().unwrap().as_ref() {
-                        builder = builder.header("Access-Control-Allow-Origin", &config.origin);
-                    }
-
-                    return Ok(builder.body(full(response_json)).unwrap());
+                    Ok(builder.body(full(response_json)).unwrap())
                 } else {
-                     return Ok(make_builder()
+                     Ok(Response::builder()
                         .status(StatusCode::BAD_REQUEST)
                         .body(full("Missing Boundary"))
-                        .unwrap());
+                        .unwrap())
                 }
             }
         }
     } else {
         // 404
         metrics.errors_total.fetch_add(1, Ordering::Relaxed);
-        return Ok(make_builder()
+        Ok(Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(full("Not Found"))
-            .unwrap());
+            .unwrap())
     }
     
     // Unreachable due to returns above, but needed for type safety if we drop through
     // However, the function structure has returns in all branches.
     // The issue was likely due to the previous edit messing up the braces.
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_headers_replace_then_append() {
+        let pairs = ["Content-Type", "text/plain", "Set-Cookie", "a=1", "set-cookie", "b=2", "Cache-Tag", "post-1"];
+        let builder = Response::builder().header("Content-Type", "text/html");
+        let builder = apply_handler_headers(builder, Some(pairs.iter().map(|s| s.to_string()).collect())).unwrap();
+        let headers = builder.headers_ref().unwrap();
+        assert_eq!(headers.get_all("content-type").iter().collect::<Vec<_>>(), ["text/plain"]);
+        assert_eq!(headers.get_all("set-cookie").iter().collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert_eq!(headers["cache-tag"], "post-1");
+
+        assert!(apply_handler_headers(Response::builder(), Some(vec!["Cache-Tag".to_string()])).is_err());
+        assert!(apply_handler_headers(Response::builder(), Some(vec!["Bad Name".to_string(), "x".to_string()])).is_err());
+    }
+}
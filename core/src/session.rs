@@ -0,0 +1,386 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use dashmap::DashMap;
+use hyper::header::{HeaderMap, HeaderValue};
+use redis::AsyncCommands;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use tracing::warn;
+use crate::cache::{BoundedCache, CacheStats};
+use crate::cookies::{self, CookieAttributes, CookieKeys};
+use crate::redis_pool::{RedisPool, RedisUnavailable};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionBackend {
+    Memory,
+    Redis { prefix: String }, // Keys are `{prefix}{id}`, shared by every instance
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    pub ttl: Duration,
+    pub sliding: bool, // Every request that reads the session pushes its expiry out again
+    pub attributes: CookieAttributes,
+    pub backend: SessionBackend,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: "sid".to_string(),
+            ttl: Duration::from_secs(24 * 3600),
+            sliding: true,
+            attributes: CookieAttributes::default(),
+            backend: SessionBackend::Memory,
+        }
+    }
+}
+
+/// What the handler left behind for its request.
+#[derive(Debug, PartialEq)]
+pub enum SessionUpdate {
+    Save { data: Value, regenerate: bool },
+    Destroy,
+}
+
+/// A session as loaded for one request, before the handler ran.
+pub struct LoadedSession {
+    config: Arc<SessionConfig>,
+    id: Option<String>, // None until there is something to store
+    pub data: Value, // Always an object
+}
+
+/// Server-side sessions: the cookie carries only a random id (signed when cookie keys are
+/// set), the data lives in memory or Redis. Handlers stage their changes per request and
+/// they are written once the response is ready.
+pub struct SessionStore {
+    config: RwLock<Option<Arc<SessionConfig>>>,
+    keys: RwLock<Option<Arc<CookieKeys>>>,
+    memory: Arc<BoundedCache<String>>,
+    redis: Arc<RedisPool>,
+    pending: DashMap<String, Option<SessionUpdate>>, // req_id -> update, for requests still in flight
+    write_failures: AtomicU64,
+}
+
+/// Held while a handler may stage its session. Stages for a request that is not in flight
+/// (never dispatched, already finished, or cancelled) are dropped rather than kept forever.
+pub struct SessionTicket {
+    store: Arc<SessionStore>,
+    req_id: String,
+}
+
+impl Drop for SessionTicket {
+    fn drop(&mut self) {
+        self.store.pending.remove(&self.req_id);
+    }
+}
+
+impl SessionStore {
+    pub fn new(redis: Arc<RedisPool>) -> Self {
+        Self {
+            config: RwLock::new(None),
+            keys: RwLock::new(None),
+            memory: Arc::new(BoundedCache::new("session", 32 * 1024 * 1024)),
+            redis,
+            pending: DashMap::new(),
+            write_failures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn configure(&self, config: Option<SessionConfig>) {
+        *self.config.write().unwrap() = config.map(Arc::new);
+    }
+
+    pub fn set_keys(&self, keys: Option<CookieKeys>) {
+        *self.keys.write().unwrap() = keys.map(Arc::new);
+    }
+
+    pub fn keys(&self) -> Option<Arc<CookieKeys>> {
+        self.keys.read().unwrap().clone()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.read().unwrap().is_some()
+    }
+
+    pub fn memory(&self) -> &Arc<BoundedCache<String>> {
+        &self.memory
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.memory.stats()
+    }
+
+    pub fn render_metrics(&self) -> String {
+        format!(
+            "# HELP qhttpx_session_write_failures_total Session saves, deletes and expiry updates that failed\n\
+             # TYPE qhttpx_session_write_failures_total counter\n\
+             qhttpx_session_write_failures_total {}\n",
+            self.write_failures.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Opens request `req_id` for staging until the ticket is dropped.
+    pub fn begin(self: &Arc<Self>, req_id: &str) -> SessionTicket {
+        self.pending.insert(req_id.to_string(), None);
+        SessionTicket { store: self.clone(), req_id: req_id.to_string() }
+    }
+
+    /// Called by the handler before it responds; the last call for a request wins. Returns
+    /// false when the request is no longer in flight and the update was dropped.
+    pub fn stage(&self, req_id: &str, update: SessionUpdate) -> bool {
+        match self.pending.get_mut(req_id) {
+            Some(mut pending) => {
+                *pending = Some(update);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The session cookie as sent, when sessions are on. CSRF tokens are bound to it.
+    pub fn cookie_value(&self, headers: &HeaderMap) -> Option<String> {
+        let config = self.config.read().unwrap().clone()?;
+        cookies::parse(headers).remove(&config.cookie_name)
+    }
+
+    /// Reads the request's session. Ok(None) when sessions are off; unknown, expired or
+    /// forged ids give an empty session. Errs only when Redis cannot answer, since an empty
+    /// session saved over the real one would lose it.
+    pub async fn load(&self, headers: &HeaderMap) -> Result<Option<LoadedSession>, RedisUnavailable> {
+        let Some(config) = self.config.read().unwrap().clone() else {
+            return Ok(None);
+        };
+        let id = cookies::parse(headers).remove(&config.cookie_name)
+            .and_then(|value| match self.keys() {
+                Some(keys) => keys.unsign(&config.cookie_name, &value),
+                None => Some(value),
+            });
+        let stored = match &id {
+            Some(id) => self.read(&config, id).await?,
+            None => None,
+        };
+        let data = stored.as_deref()
+            .and_then(|json| serde_json::from_str::<Value>(json).ok())
+            .filter(Value::is_object);
+        Ok(Some(LoadedSession {
+            id: id.filter(|_| data.is_some()),
+            data: data.unwrap_or_else(|| Value::Object(Default::default())),
+            config,
+        }))
+    }
+
+    /// Writes what the handler staged and returns the `Set-Cookie` to send, if any. A
+    /// session nobody changed is only touched, and only when expiry is sliding.
+    pub async fn finish(&self, session: LoadedSession, req_id: &str) -> Option<HeaderValue> {
+        let update = self.pending.get_mut(req_id).and_then(|mut pending| pending.take());
+        let config = session.config.clone();
+        let result = match update {
+            Some(SessionUpdate::Destroy) => match &session.id {
+                Some(id) => self.delete(&config, id).await.map(|_| Some(None)),
+                None => Ok(None),
+            },
+            Some(SessionUpdate::Save { data, regenerate }) if regenerate || data != session.data => {
+                if let (true, Some(old)) = (regenerate, &session.id) {
+                    let _ = self.delete(&config, old).await;
+                }
+                let id = match session.id.as_ref().filter(|_| !regenerate) {
+                    Some(id) => Some(id.clone()),
+                    None => generate_id(),
+                };
+                match id {
+                    Some(id) if data.as_object().is_some_and(|o| !o.is_empty()) || session.id.is_some() => {
+                        self.write(&config, &id, &data).await.map(|_| Some(Some(id)))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            _ => match &session.id {
+                Some(id) if config.sliding => self.touch(&config, id).await.map(|_| Some(Some(id.clone()))),
+                _ => Ok(None),
+            },
+        };
+        match result {
+            // Some(Some(id)): (re)issue the cookie; Some(None): clear it
+            Ok(Some(id)) => self.cookie(&config, id.as_deref()),
+            Ok(None) => None,
+            Err(e) => {
+                // The response still goes out, but the handler's changes are lost
+                self.write_failures.fetch_add(1, Ordering::Relaxed);
+                warn!(target: "Session", error = %e, "session write failed");
+                None
+            }
+        }
+    }
+
+    fn cookie(&self, config: &SessionConfig, id: Option<&str>) -> Option<HeaderValue> {
+        let result = match id {
+            Some(id) => {
+                let value = match self.keys() {
+                    Some(keys) => keys.sign(&config.cookie_name, id),
+                    None => id.to_string(),
+                };
+                config.attributes.set_cookie(&config.cookie_name, &value, Some(config.ttl))
+            }
+            None => config.attributes.set_cookie(&config.cookie_name, "", Some(Duration::ZERO)),
+        };
+        result.ok()
+    }
+
+    async fn read(&self, config: &SessionConfig, id: &str) -> Result<Option<String>, RedisUnavailable> {
+        match &config.backend {
+            SessionBackend::Memory => Ok(self.memory.get(id)),
+            SessionBackend::Redis { prefix } => {
+                let key = format!("{}{}", prefix, id);
+                self.redis.call(|mut con| async move { con.get::<_, Option<String>>(key).await }).await
+            }
+        }
+    }
+
+    async fn write(&self, config: &SessionConfig, id: &str, data: &Value) -> Result<(), RedisUnavailable> {
+        let json = data.to_string();
+        match &config.backend {
+            SessionBackend::Memory => {
+                self.memory.insert(id.to_string(), json, config.ttl);
+                Ok(())
+            }
+            SessionBackend::Redis { prefix } => {
+                let (key, ttl) = (format!("{}{}", prefix, id), config.ttl.as_secs().max(1));
+                self.redis.call(|mut con| async move { con.set_ex::<_, _, ()>(key, json, ttl).await }).await
+            }
+        }
+    }
+
+    async fn touch(&self, config: &SessionConfig, id: &str) -> Result<(), RedisUnavailable> {
+        match &config.backend {
+            SessionBackend::Memory => {
+                if let Some(json) = self.memory.get(id) {
+                    self.memory.insert(id.to_string(), json, config.ttl);
+                }
+                Ok(())
+            }
+            SessionBackend::Redis { prefix } => {
+                let (key, ttl) = (format!("{}{}", prefix, id), config.ttl.as_secs().max(1) as i64);
+                self.redis.call(|mut con| async move { con.expire::<_, ()>(key, ttl).await }).await
+            }
+        }
+    }
+
+    async fn delete(&self, config: &SessionConfig, id: &str) -> Result<(), RedisUnavailable> {
+        match &config.backend {
+            SessionBackend::Memory => {
+                self.memory.remove(id);
+                Ok(())
+            }
+            SessionBackend::Redis { prefix } => {
+                let key = format!("{}{}", prefix, id);
+                self.redis.call(|mut con| async move { con.del::<_, ()>(key).await }).await
+            }
+        }
+    }
+}
+
+fn generate_id() -> Option<String> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).ok()?;
+    Some(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::COOKIE;
+    use serde_json::json;
+
+    fn store() -> Arc<SessionStore> {
+        let store = Arc::new(SessionStore::new(Arc::new(RedisPool::new())));
+        store.configure(Some(SessionConfig { ttl: Duration::from_secs(600), ..SessionConfig::default() }));
+        store.set_keys(Some(CookieKeys::new(&[vec![9u8; 32]]).unwrap()));
+        store
+    }
+
+    fn with_cookie(set_cookie: &HeaderValue) -> HeaderMap {
+        let pair = set_cookie.to_str().unwrap().split(';').next().unwrap().to_string();
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, pair.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn saves_reloads_slides_and_destroys() {
+        let store = store();
+        assert!(SessionStore::new(Arc::new(RedisPool::new())).load(&HeaderMap::new()).await.unwrap().is_none());
+
+        // Nothing stored, nothing staged: no cookie
+        let empty = store.load(&HeaderMap::new()).await.unwrap().unwrap();
+        assert_eq!(empty.data, json!({}));
+        assert!(store.finish(empty, "1").await.is_none());
+
+        let session = store.load(&HeaderMap::new()).await.unwrap().unwrap();
+        let _ticket = store.begin("2");
+        store.stage("2", SessionUpdate::Save { data: json!({"user": 7}), regenerate: false });
+        let cookie = store.finish(session, "2").await.unwrap();
+        assert!(cookie.to_str().unwrap().contains("Max-Age=600; Secure; HttpOnly; SameSite=Lax"));
+
+        // Read back; untouched sessions still get their expiry pushed out
+        let session = store.load(&with_cookie(&cookie)).await.unwrap().unwrap();
+        assert_eq!(session.data, json!({"user": 7}));
+        let slid = store.finish(session, "3").await.unwrap();
+        assert_eq!(slid, cookie);
+
+        // Regenerating keeps the data under a new id and drops the old one
+        let session = store.load(&with_cookie(&cookie)).await.unwrap().unwrap();
+        let _ticket = store.begin("4");
+        store.stage("4", SessionUpdate::Save { data: json!({"user": 7, "admin": true}), regenerate: true });
+        let renewed = store.finish(session, "4").await.unwrap();
+        assert_ne!(renewed, cookie);
+        assert_eq!(store.load(&with_cookie(&cookie)).await.unwrap().unwrap().data, json!({}));
+
+        // A tampered id is ignored
+        let forged = HeaderValue::from_static("sid=abc.def");
+        assert_eq!(store.load(&with_cookie(&forged)).await.unwrap().unwrap().data, json!({}));
+
+        let session = store.load(&with_cookie(&renewed)).await.unwrap().unwrap();
+        assert_eq!(session.data["admin"], true);
+        let _ticket = store.begin("5");
+        store.stage("5", SessionUpdate::Destroy);
+        let cleared = store.finish(session, "5").await.unwrap();
+        assert!(cleared.to_str().unwrap().starts_with("sid=; Path=/; Max-Age=0"));
+        assert_eq!(store.load(&with_cookie(&renewed)).await.unwrap().unwrap().data, json!({}));
+    }
+
+    #[tokio::test]
+    async fn stages_outside_a_request_are_dropped() {
+        let store = store();
+        let update = || SessionUpdate::Save { data: json!({"user": 7}), regenerate: false };
+        // Never dispatched, or a handler id that is made up
+        assert!(!store.stage("1", update()));
+
+        let ticket = store.begin("2");
+        let session = store.load(&HeaderMap::new()).await.unwrap().unwrap();
+        assert!(store.finish(session, "2").await.is_none());
+        // A late call, after the response went out, is kept only until the request ends
+        assert!(store.stage("2", update()));
+        drop(ticket);
+        assert!(!store.stage("2", update()));
+        assert!(store.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_counted() {
+        // Redis store without a connection: every write fails
+        let store = Arc::new(SessionStore::new(Arc::new(RedisPool::new())));
+        store.configure(Some(SessionConfig { backend: SessionBackend::Redis { prefix: "s:".to_string() }, ..SessionConfig::default() }));
+        assert!(store.render_metrics().contains("qhttpx_session_write_failures_total 0\n"));
+
+        let session = store.load(&HeaderMap::new()).await.unwrap().unwrap();
+        let _ticket = store.begin("1");
+        store.stage("1", SessionUpdate::Save { data: json!({"user": 7}), regenerate: false });
+        assert!(store.finish(session, "1").await.is_none());
+        assert!(store.render_metrics().contains("qhttpx_session_write_failures_total 1\n"));
+    }
+}
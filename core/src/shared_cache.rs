@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use hyper::StatusCode;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use redis::{AsyncCommands, RedisError, RedisResult};
+use redis::aio::MultiplexedConnection;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use crate::cache::{self, BoundedCache, CacheLifetime, CachedResponse};
+use crate::redis_pool::RedisPool;
+
+// Keys per SCAN/DEL round trip when purging
+const BATCH: usize = 500;
+// Purges scan the whole keyspace, so they get far longer than the per-request timeout
+const PURGE_TIMEOUT: Duration = Duration::from_secs(30);
+// PEXPIRE that only ever pushes the expiry out. A tag index lists entries with different
+// lifetimes, so a short-lived entry must not cut it below a longer-lived one.
+const EXTEND_TTL: &str = "if redis.call('PTTL', KEYS[1]) < tonumber(ARGV[1]) then return redis.call('PEXPIRE', KEYS[1], ARGV[1]) end return 0";
+
+/// Second tier of the route cache, shared by every instance through Redis.
+///
+/// Entries are hashes under `{prefix}entry:{key}`, `{prefix}tag:{tag}` sets index them for
+/// tag purges, and every purge is broadcast on `{prefix}invalidate` so the other instances
+/// drop their in-process copies as well.
+pub struct SharedCache {
+    redis: Arc<RedisPool>,
+    prefix: String,
+    instance: String, // Lets a subscriber skip its own broadcasts
+}
+
+#[derive(Serialize, Deserialize)]
+struct Invalidation {
+    origin: String,
+    kind: String, // "pattern" | "tag"
+    value: String,
+}
+
+impl SharedCache {
+    pub fn new(redis: Arc<RedisPool>, prefix: String) -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        Self {
+            redis,
+            prefix,
+            instance: format!("{}-{}", std::process::id(), nanos),
+        }
+    }
+
+    fn entry_key(&self, key: &str) -> String {
+        format!("{}entry:{}", self.prefix, key)
+    }
+
+    fn tag_key(&self, tag: &str) -> String {
+        format!("{}tag:{}", self.prefix, tag)
+    }
+
+    fn channel(&self) -> String {
+        format!("{}invalidate", self.prefix)
+    }
+
+    /// Looks up `key`. Redis errors count as a miss; the in-process tier keeps working.
+    pub async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let entry_key = self.entry_key(key);
+        let result = self.redis.call(|mut con| async move {
+            con.hgetall::<_, HashMap<String, Vec<u8>>>(entry_key).await
+        }).await;
+        match result {
+            Ok(fields) => decode(fields),
+            Err(e) => {
+                debug!(target: "Cache", error = %e, "shared cache read failed");
+                None
+            }
+        }
+    }
+
+    pub async fn set(&self, key: &str, entry: &CachedResponse) {
+        let Some(pipe) = self.store_pipeline(key, entry) else {
+            return;
+        };
+        let result = self.redis.call(|mut con| async move {
+            pipe.query_async::<_, ()>(&mut con).await
+        }).await;
+        if let Err(e) = result {
+            debug!(target: "Cache", error = %e, "shared cache write failed");
+        }
+    }
+
+    /// The commands that store `entry`, or None if it has already expired.
+    fn store_pipeline(&self, key: &str, entry: &CachedResponse) -> Option<redis::Pipeline> {
+        let age = entry.age();
+        let remaining = entry.lifetime.retention().saturating_sub(age);
+        if remaining.is_zero() {
+            return None;
+        }
+        let ttl_ms = remaining.as_millis() as u64;
+        let entry_key = self.entry_key(key);
+
+        let headers: Vec<(&str, &str)> = entry.headers.iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+            .collect();
+        let stored_at = unix_millis().saturating_sub(age.as_millis() as u64);
+
+        let mut pipe = redis::pipe();
+        pipe.cmd("HSET").arg(&entry_key)
+            .arg("path").arg(&entry.path)
+            .arg("status").arg(entry.status.as_u16())
+            .arg("headers").arg(serde_json::to_string(&headers).unwrap_or_default())
+            .arg("body").arg(entry.body.as_ref())
+            .arg("tags").arg(serde_json::to_string(&entry.tags).unwrap_or_default())
+            .arg("stored_at").arg(stored_at)
+            .arg("ttl").arg(entry.lifetime.ttl.as_secs())
+            .arg("swr").arg(entry.lifetime.stale_while_revalidate.as_secs())
+            .arg("sie").arg(entry.lifetime.stale_if_error.as_secs())
+            .ignore();
+        pipe.cmd("PEXPIRE").arg(&entry_key).arg(ttl_ms).ignore();
+        for tag in &entry.tags {
+            // Only ever extended, so the index lives as long as the longest-lived entry it lists
+            pipe.cmd("SADD").arg(self.tag_key(tag)).arg(&entry_key).ignore();
+            pipe.cmd("EVAL").arg(EXTEND_TTL).arg(1).arg(self.tag_key(tag)).arg(ttl_ms).ignore();
+        }
+        Some(pipe)
+    }
+
+    /// Removes entries whose path matches `pattern` and tells the other instances to do the same.
+    pub async fn purge(&self, pattern: &str) -> Result<usize, String> {
+        let result = self.redis.call_with_timeout(PURGE_TIMEOUT, |mut con| async move {
+            let entry_prefix = self.entry_key("");
+
+            let mut doomed = Vec::new();
+            {
+                let mut keys = con.scan_match::<_, String>(format!("{}*", entry_prefix)).await?;
+                while let Some(key) = keys.next_item().await {
+                    let matches = key.strip_prefix(&entry_prefix)
+                        .map(|cache_key| cache::glob_match(pattern, key_path(cache_key)))
+                        .unwrap_or(false);
+                    if matches {
+                        doomed.push(key);
+                    }
+                }
+            }
+
+            let mut removed = 0;
+            for batch in doomed.chunks(BATCH) {
+                removed += con.del::<_, usize>(batch).await?;
+            }
+            self.broadcast(&mut con, "pattern", pattern).await?;
+            Ok(removed)
+        }).await;
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Removes entries tagged with `tag` and tells the other instances to do the same.
+    pub async fn purge_tag(&self, tag: &str) -> Result<usize, String> {
+        let result = self.redis.call_with_timeout(PURGE_TIMEOUT, |mut con| async move {
+            let tag_key = self.tag_key(tag);
+            let members: Vec<String> = con.smembers(&tag_key).await?;
+
+            let mut removed = 0;
+            for batch in members.chunks(BATCH) {
+                removed += con.del::<_, usize>(batch).await?;
+            }
+            con.del::<_, ()>(&tag_key).await?;
+            self.broadcast(&mut con, "tag", tag).await?;
+            Ok(removed)
+        }).await;
+        result.map_err(|e| e.to_string())
+    }
+
+    async fn broadcast(&self, con: &mut MultiplexedConnection, kind: &str, value: &str) -> RedisResult<()> {
+        let message = Invalidation { origin: self.instance.clone(), kind: kind.to_string(), value: value.to_string() };
+        con.publish(self.channel(), serde_json::to_string(&message).unwrap_or_default()).await
+    }
+
+    /// Applies purges broadcast by other instances to `local`, reconnecting if the
+    /// subscription drops. `local` is emptied on every (re)subscribe. The task ends once
+    /// `local` is dropped.
+    pub fn spawn_subscriber(self: &Arc<Self>, local: &Arc<BoundedCache<Arc<CachedResponse>>>) {
+        let shared = self.clone();
+        let local = Arc::downgrade(local);
+        tokio::spawn(async move {
+            while local.strong_count() > 0 {
+                if let Err(e) = shared.listen(&local).await {
+                    warn!(target: "Cache", error = %e, "shared cache subscription lost, retrying");
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+
+    async fn listen(&self, local: &std::sync::Weak<BoundedCache<Arc<CachedResponse>>>) -> Result<(), RedisError> {
+        let client = self.redis.client().ok_or((redis::ErrorKind::ClientError, "Redis is not configured"))?;
+        let mut pubsub = client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(self.channel()).await?;
+        // Purges broadcast while we were not subscribed are lost; anything held locally
+        // may be one of them, so start over
+        if let Some(local) = local.upgrade() {
+            local.purge("*");
+        }
+        let mut messages = pubsub.on_message();
+
+        while let Some(message) = messages.next().await {
+            let Some(local) = local.upgrade() else {
+                return Ok(());
+            };
+            let Ok(payload) = message.get_payload::<String>() else {
+                continue;
+            };
+            let Ok(invalidation) = serde_json::from_str::<Invalidation>(&payload) else {
+                continue;
+            };
+            if invalidation.origin == self.instance {
+                continue;
+            }
+            match invalidation.kind.as_str() {
+                "pattern" => local.purge(&invalidation.value),
+                "tag" => local.purge_tag(&invalidation.value),
+                _ => 0,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// The path part of a cache key ("GET /blog/1?page=2|sub=42" -> "/blog/1").
+fn key_path(key: &str) -> &str {
+    let rest = key.split_once(' ').map(|(_, rest)| rest).unwrap_or(key);
+    rest.split(['?', '|']).next().unwrap_or(rest)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn decode(mut fields: HashMap<String, Vec<u8>>) -> Option<CachedResponse> {
+    let mut text = |name: &str| fields.remove(name).and_then(|v| String::from_utf8(v).ok());
+    let path = text("path")?;
+    let status = StatusCode::from_u16(text("status")?.parse().ok()?).ok()?;
+    let header_pairs: Vec<(String, String)> = serde_json::from_str(&text("headers")?).ok()?;
+    let tags: Vec<String> = serde_json::from_str(&text("tags")?).ok()?;
+    let stored_at: u64 = text("stored_at")?.parse().ok()?;
+    let lifetime = CacheLifetime::new(
+        text("ttl")?.parse().ok()?,
+        text("swr").and_then(|v| v.parse().ok()),
+        text("sie").and_then(|v| v.parse().ok()),
+    );
+    let body = Bytes::from(fields.remove("body")?);
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in header_pairs {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+            headers.append(name, value);
+        }
+    }
+
+    // Wall-clock age carries over between instances; Instant does not
+    let age = Duration::from_millis(unix_millis().saturating_sub(stored_at));
+    let stored_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+
+    Some(CachedResponse { path, status, headers, body, tags, stored_at, lifetime })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CACHE_TAG;
+
+    fn entry(tags: &'static str, ttl: u64) -> CachedResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        headers.insert(CACHE_TAG, HeaderValue::from_static(tags));
+        CachedResponse::new("/blog/1".to_string(), StatusCode::OK, &headers, Bytes::from_static(b"hello"), CacheLifetime::new(ttl, Some(5), None))
+    }
+
+    fn packed(pipe: &redis::Pipeline) -> String {
+        String::from_utf8_lossy(&pipe.get_packed_pipeline()).into_owned()
+    }
+
+    #[test]
+    fn tag_index_expiry_is_only_extended() {
+        let shared = SharedCache::new(Arc::new(RedisPool::new()), "qx:".to_string());
+        let commands = packed(&shared.store_pipeline("GET /blog/1", &entry("post-1", 60)).unwrap());
+        assert!(commands.contains("qx:entry:GET /blog/1"));
+        assert!(commands.contains("qx:tag:post-1"));
+        assert!(commands.contains(EXTEND_TTL));
+        // PEXPIRE only on the entry itself, never straight on the tag set
+        assert_eq!(commands.matches("PEXPIRE\r\n").count(), 1);
+
+        let expired = CachedResponse { stored_at: Instant::now() - Duration::from_secs(120), ..entry("post-1", 60) };
+        assert!(shared.store_pipeline("GET /blog/1", &expired).is_none());
+    }
+
+    #[test]
+    fn keys_map_back_to_paths() {
+        assert_eq!(key_path("GET /blog/1?page=2|sub=42"), "/blog/1");
+        assert_eq!(key_path("GET /blog/1|accept=text/html"), "/blog/1");
+        assert_eq!(key_path("GET /"), "/");
+    }
+
+    #[test]
+    fn decodes_stored_fields() {
+        let stored_at = unix_millis() - 2000;
+        let fields: HashMap<String, Vec<u8>> = [
+            ("path", "/blog/1".as_bytes().to_vec()),
+            ("status", b"200".to_vec()),
+            ("headers", br#"[["content-type","text/plain"]]"#.to_vec()),
+            ("body", b"hello".to_vec()),
+            ("tags", br#"["post-1"]"#.to_vec()),
+            ("stored_at", stored_at.to_string().into_bytes()),
+            ("ttl", b"60".to_vec()),
+            ("swr", b"5".to_vec()),
+        ].into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+
+        let cached = decode(fields.clone()).unwrap();
+        assert_eq!(cached.path, "/blog/1");
+        assert_eq!(cached.headers["content-type"], "text/plain");
+        assert_eq!(cached.body, Bytes::from_static(b"hello"));
+        assert_eq!(cached.tags, ["post-1"]);
+        assert_eq!(cached.lifetime, CacheLifetime::new(60, Some(5), None));
+        // Age carries over from the wall clock
+        assert!(cached.age() >= Duration::from_millis(1900));
+
+        let mut broken = fields;
+        broken.remove("status");
+        assert!(decode(broken).is_none());
+    }
+
+    /// Runs against a real server when REDIS_URL is set (e.g. redis://127.0.0.1:6379/15).
+    #[tokio::test]
+    async fn stores_and_purges_against_redis() {
+        let Ok(url) = std::env::var("REDIS_URL") else {
+            return;
+        };
+        let redis = Arc::new(RedisPool::new());
+        redis.set_client(redis::Client::open(url).unwrap());
+        let shared = SharedCache::new(redis.clone(), format!("qhttpx-test-{}:", unix_millis()));
+
+        shared.set("GET /blog/1", &entry("post-1", 600)).await;
+        shared.set("GET /blog/2", &entry("post-1", 1)).await;
+        let tag_key = shared.tag_key("post-1");
+        let pttl = redis.call(|mut con| async move { con.pttl::<_, i64>(tag_key).await }).await.unwrap();
+        assert!(pttl > 60_000, "short-lived entry shortened the tag index to {}ms", pttl);
+
+        assert_eq!(shared.get("GET /blog/1").await.unwrap().body, Bytes::from_static(b"hello"));
+        assert_eq!(shared.purge_tag("post-1").await.unwrap(), 2);
+        assert!(shared.get("GET /blog/1").await.is_none());
+
+        shared.set("GET /blog/3", &entry("post-3", 600)).await;
+        assert_eq!(shared.purge("/blog/*").await.unwrap(), 1);
+        assert!(shared.get("GET /blog/3").await.is_none());
+
+        // Purges missed while unsubscribed cannot be replayed: subscribing empties the local tier
+        let local = Arc::new(BoundedCache::new("test", 1 << 20));
+        local.insert("GET /blog/4".to_string(), Arc::new(entry("post-4", 600)), Duration::from_secs(600));
+        Arc::new(shared).spawn_subscriber(&local);
+        for _ in 0..100 {
+            if local.len() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(local.len(), 0);
+    }
+}
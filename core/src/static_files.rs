@@ -0,0 +1,714 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http_body_util::{BodyExt, StreamBody, combinators::BoxBody};
+use hyper::body::Frame;
+use hyper::header::{self, HeaderMap};
+use hyper::http::response::Builder;
+use hyper::{Response, StatusCode};
+use async_compression::tokio::bufread::{GzipEncoder, BrotliEncoder};
+use percent_encoding::percent_decode_str;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::io::ReaderStream;
+use crate::server::full;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+    Deny,       // Any symlink below the root is rejected
+    WithinRoot, // Symlinks are followed as long as the target stays under the root
+    Follow,     // Symlinks are followed anywhere
+}
+
+impl SymlinkPolicy {
+    pub fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "deny" => Ok(SymlinkPolicy::Deny),
+            "within_root" => Ok(SymlinkPolicy::WithinRoot),
+            "follow" => Ok(SymlinkPolicy::Follow),
+            _ => Err(format!("Symlink policy must be deny, within_root or follow, not \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ResolveError {
+    Forbidden,
+    NotFound,
+}
+
+/// A file held in memory with everything needed to serve it precomputed.
+#[derive(Debug)]
+pub struct Asset {
+    pub body: Bytes,
+    pub gzip: Option<Bytes>,
+    pub brotli: Option<Bytes>,
+    pub etag: String,
+    pub content_type: String,
+    pub last_modified: SystemTime,
+}
+
+impl Asset {
+    pub fn new(path: &str, body: Bytes, last_modified: SystemTime) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+
+        // Only keep compressed variants that actually save bytes
+        let gzip = compress_gzip(&body).filter(|c| c.len() < body.len());
+        let brotli = compress_brotli(&body).filter(|c| c.len() < body.len());
+
+        Self {
+            etag: format!("\"{:x}-{:x}\"", body.len(), hasher.finish()),
+            content_type: mime_guess::from_path(path).first_or_octet_stream().to_string(),
+            last_modified,
+            body,
+            gzip,
+            brotli,
+        }
+    }
+}
+
+fn compress_gzip(data: &[u8]) -> Option<Bytes> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok().map(Bytes::from)
+}
+
+fn compress_brotli(data: &[u8]) -> Option<Bytes> {
+    let mut out = Vec::new();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+        writer.write_all(data).ok()?;
+    }
+    Some(Bytes::from(out))
+}
+
+/// An in-memory static mount, keyed by the normalised relative path ("css/app.css").
+#[derive(Debug, Default)]
+pub struct AssetBundle {
+    assets: HashMap<String, Arc<Asset>>,
+}
+
+impl AssetBundle {
+    /// Every file gets the same Last-Modified, the time the bundle was built.
+    pub fn from_files(files: HashMap<String, Vec<u8>>) -> Result<Self, String> {
+        let mut bundle = Self::default();
+        let built = SystemTime::now();
+        for (path, data) in files {
+            bundle.insert(&path, data, built)?;
+        }
+        Ok(bundle)
+    }
+
+    /// Builds a bundle from the regular files of a tar archive; other entry types are skipped.
+    /// Last-Modified comes from each entry's mtime, so every replica serving the same
+    /// archive agrees on it.
+    pub fn from_tar(archive: &[u8]) -> Result<Self, String> {
+        let mut bundle = Self::default();
+        let built = SystemTime::now();
+        let mut archive = tar::Archive::new(archive);
+        for entry in archive.entries().map_err(|e| e.to_string())? {
+            let mut entry = entry.map_err(|e| e.to_string())?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path().map_err(|e| e.to_string())?.to_string_lossy().into_owned();
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+            let modified = match entry.header().mtime() {
+                Ok(secs) if secs > 0 => UNIX_EPOCH + std::time::Duration::from_secs(secs),
+                _ => built,
+            };
+            bundle.insert(&path, data, modified)?;
+        }
+        Ok(bundle)
+    }
+
+    fn insert(&mut self, path: &str, data: Vec<u8>, last_modified: SystemTime) -> Result<(), String> {
+        let key = bundle_key(&sanitize(path).map_err(|_| format!("Invalid bundle path: {}", path))?);
+        if key.is_empty() {
+            return Err(format!("Invalid bundle path: {}", path));
+        }
+        let asset = Asset::new(&key, Bytes::from(data), last_modified);
+        self.assets.insert(key, Arc::new(asset));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// `directory` is set for request paths ending in '/', which serve that directory's index.html.
+    fn get(&self, relative: &Path, directory: bool) -> Option<Arc<Asset>> {
+        let key = bundle_key(relative);
+        if key.is_empty() {
+            return self.assets.get("index.html").cloned();
+        }
+        if directory {
+            return self.assets.get(&format!("{}/index.html", key)).cloned();
+        }
+        self.assets.get(&key).cloned()
+    }
+}
+
+fn bundle_key(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Debug, Clone)]
+pub enum StaticSource {
+    Dir {
+        root: PathBuf, // Canonical root directory
+        symlinks: SymlinkPolicy,
+    },
+    Bundle(Arc<AssetBundle>),
+}
+
+#[derive(Debug)]
+pub enum Resolved {
+    File(PathBuf),
+    Asset(Arc<Asset>),
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticMount {
+    pub prefix: String,
+    pub source: StaticSource,
+}
+
+impl StaticMount {
+    pub fn new(prefix: String, dir: &str, symlinks: SymlinkPolicy) -> std::io::Result<Self> {
+        let root = std::fs::canonicalize(dir)?;
+        Ok(Self { prefix, source: StaticSource::Dir { root, symlinks } })
+    }
+
+    pub fn bundle(prefix: String, bundle: AssetBundle) -> Self {
+        Self { prefix, source: StaticSource::Bundle(Arc::new(bundle)) }
+    }
+
+    /// Returns the part of `path` below this mount, or None if the mount doesn't apply.
+    /// "/public" matches "/public" and "/public/app.js" but not "/publicity".
+    pub fn strip_prefix<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(&self.prefix)?;
+        if rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/') {
+            Some(rest)
+        } else {
+            None
+        }
+    }
+
+    /// Maps a request path (relative to the prefix) to a regular file under the root,
+    /// or to an asset of the bundle.
+    pub async fn resolve(&self, rest: &str) -> Result<Resolved, ResolveError> {
+        let relative = sanitize(rest)?;
+        match &self.source {
+            StaticSource::Bundle(bundle) => bundle.get(&relative, rest.ends_with('/')).map(Resolved::Asset).ok_or(ResolveError::NotFound),
+            StaticSource::Dir { root, symlinks } => resolve_in_dir(root, *symlinks, &relative).await.map(Resolved::File),
+        }
+    }
+}
+
+async fn resolve_in_dir(root: &Path, symlinks: SymlinkPolicy, relative: &Path) -> Result<PathBuf, ResolveError> {
+    // Deny: walk every component so a link anywhere on the way is caught,
+    // not just one at the leaf.
+    if symlinks == SymlinkPolicy::Deny {
+        let mut current = root.to_path_buf();
+        for component in relative.components() {
+            current.push(component);
+            match tokio::fs::symlink_metadata(&current).await {
+                Ok(meta) if meta.file_type().is_symlink() => return Err(ResolveError::Forbidden),
+                Ok(_) => {}
+                Err(_) => return Err(ResolveError::NotFound),
+            }
+        }
+    }
+
+    let canonical = tokio::fs::canonicalize(root.join(relative)).await
+        .map_err(|_| ResolveError::NotFound)?;
+
+    if symlinks != SymlinkPolicy::Follow && !canonical.starts_with(root) {
+        return Err(ResolveError::Forbidden);
+    }
+
+    let meta = tokio::fs::metadata(&canonical).await.map_err(|_| ResolveError::NotFound)?;
+    if !meta.is_file() {
+        return Err(ResolveError::NotFound);
+    }
+
+    Ok(canonical)
+}
+
+/// Percent-decodes a request path and turns it into a relative path made only of
+/// normal components. `..`, backslashes, NUL bytes and drive/root prefixes are rejected
+/// rather than normalised so that an encoded traversal can never be silently rewritten.
+pub fn sanitize(rest: &str) -> Result<PathBuf, ResolveError> {
+    let decoded = percent_decode_str(rest)
+        .decode_utf8()
+        .map_err(|_| ResolveError::Forbidden)?;
+
+    let mut out = PathBuf::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(ResolveError::Forbidden),
+            s if s.contains('\\') || s.contains('\0') => return Err(ResolveError::Forbidden),
+            s => {
+                let mut components = Path::new(s).components();
+                match (components.next(), components.next()) {
+                    (Some(Component::Normal(_)), None) => out.push(s),
+                    _ => return Err(ResolveError::Forbidden),
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Selection {
+    NotModified,
+    Full,
+    Partial(u64, u64), // Inclusive byte range
+    Unsatisfiable,
+}
+
+fn etag_matches(list: &str, etag: &str, weak: bool) -> bool {
+    let strip = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    list.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        if candidate == "*" {
+            return true;
+        }
+        if weak {
+            strip(candidate) == strip(etag)
+        } else {
+            !candidate.starts_with("W/") && !etag.starts_with("W/") && candidate == etag
+        }
+    })
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Evaluates the conditional (RFC 9110 §13) and Range headers of a GET/HEAD request
+/// against a representation of `len` bytes.
+pub fn evaluate(headers: &HeaderMap, etag: &str, last_modified: SystemTime, len: u64) -> Selection {
+    let header_str = |name: header::HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(inm) = header_str(header::IF_NONE_MATCH) {
+        if etag_matches(inm, etag, true) {
+            return Selection::NotModified;
+        }
+    } else if let Some(ims) = header_str(header::IF_MODIFIED_SINCE).and_then(|v| httpdate::parse_http_date(v).ok()) {
+        if unix_secs(last_modified) <= unix_secs(ims) {
+            return Selection::NotModified;
+        }
+    }
+
+    let range = match header_str(header::RANGE) {
+        Some(r) => r,
+        None => return Selection::Full,
+    };
+
+    // A stale If-Range means the client's partial copy is outdated: send everything
+    if let Some(if_range) = header_str(header::IF_RANGE) {
+        let fresh = match httpdate::parse_http_date(if_range) {
+            Ok(date) => unix_secs(date) == unix_secs(last_modified),
+            Err(_) => etag_matches(if_range, etag, false),
+        };
+        if !fresh {
+            return Selection::Full;
+        }
+    }
+
+    let spec = match range.strip_prefix("bytes=") {
+        // Multiple ranges are allowed to be ignored (RFC 9110 §14.2)
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Selection::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(parts) => parts,
+        None => return Selection::Full,
+    };
+
+    let bounds = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        // bytes=-500: the last 500 bytes
+        (None, Some(suffix)) if start.is_empty() => {
+            if suffix == 0 || len == 0 {
+                return Selection::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (Some(first), None) if end.is_empty() => (first, len.saturating_sub(1)),
+        (Some(first), Some(last)) if first <= last => (first, last.min(len.saturating_sub(1))),
+        _ => return Selection::Full,
+    };
+
+    if bounds.0 >= len {
+        return Selection::Unsatisfiable;
+    }
+    Selection::Partial(bounds.0, bounds.1)
+}
+
+fn empty() -> BoxBody<Bytes, std::io::Error> {
+    full(Bytes::new())
+}
+
+fn representation_headers(builder: Builder, content_type: &str, etag: &str, last_modified: SystemTime) -> Builder {
+    builder
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ETAG, etag)
+        .header(header::LAST_MODIFIED, httpdate::fmt_http_date(last_modified))
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::VARY, "Accept-Encoding")
+}
+
+/// The asset's ETag for one content-coding. Each coding is a different representation,
+/// so each gets its own strong validator.
+fn coding_etag(etag: &str, encoding: Option<&str>) -> String {
+    match encoding {
+        Some(encoding) => {
+            let suffix = if encoding == "gzip" { "gz" } else { encoding };
+            format!("{}-{}\"", etag.trim_end_matches('"'), suffix)
+        }
+        None => etag.to_string(),
+    }
+}
+
+/// Serves an in-memory asset, picking a precompressed variant when the client accepts it.
+/// Ranges always address the identity body.
+pub fn serve_asset(builder: Builder, asset: &Asset, headers: &HeaderMap, head_only: bool) -> Response<BoxBody<Bytes, std::io::Error>> {
+    let len = asset.body.len() as u64;
+    let accept_encoding = headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let (body, encoding) = match (&asset.brotli, &asset.gzip) {
+        _ if headers.contains_key(header::RANGE) => (asset.body.clone(), None),
+        (Some(br), _) if accept_encoding.contains("br") => (br.clone(), Some("br")),
+        (_, Some(gz)) if accept_encoding.contains("gzip") => (gz.clone(), Some("gzip")),
+        _ => (asset.body.clone(), None),
+    };
+    let etag = coding_etag(&asset.etag, encoding);
+    let builder = representation_headers(builder, &asset.content_type, &etag, asset.last_modified);
+
+    match evaluate(headers, &etag, asset.last_modified, len) {
+        Selection::NotModified => builder.status(StatusCode::NOT_MODIFIED).body(empty()).unwrap(),
+        Selection::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(empty())
+            .unwrap(),
+        Selection::Partial(start, end) => {
+            let slice = asset.body.slice(start as usize..=end as usize);
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(header::CONTENT_LENGTH, slice.len())
+                .body(if head_only { empty() } else { full(slice) })
+                .unwrap()
+        }
+        Selection::Full => {
+            let mut builder = builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, body.len());
+            if let Some(encoding) = encoding {
+                builder = builder.header(header::CONTENT_ENCODING, encoding);
+            }
+            builder.body(if head_only { empty() } else { full(body) }).unwrap()
+        }
+    }
+}
+
+/// Streams a file from disk. Full responses are compressed on the fly; ranges are sent as-is.
+pub async fn serve_file(builder: Builder, path: &Path, headers: &HeaderMap, head_only: bool) -> std::io::Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let meta = tokio::fs::metadata(path).await?;
+    let len = meta.len();
+    let last_modified = meta.modified().unwrap_or(UNIX_EPOCH);
+    // Weak: the validator is derived from metadata, not content
+    let etag = format!("W/\"{:x}-{:x}\"", len, unix_secs(last_modified));
+    let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+    let builder = representation_headers(builder, mime_type.as_ref(), &etag, last_modified);
+
+    match evaluate(headers, &etag, last_modified, len) {
+        Selection::NotModified => Ok(builder.status(StatusCode::NOT_MODIFIED).body(empty()).unwrap()),
+        Selection::Unsatisfiable => Ok(builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(empty())
+            .unwrap()),
+        Selection::Partial(start, end) => {
+            let builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(header::CONTENT_LENGTH, end - start + 1);
+            if head_only {
+                return Ok(builder.body(empty()).unwrap());
+            }
+            let mut file = File::open(path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            let stream = ReaderStream::new(file.take(end - start + 1));
+            let body = StreamBody::new(stream.map(|result| result.map(Frame::data)));
+            Ok(builder.body(BodyExt::boxed(body)).unwrap())
+        }
+        Selection::Full => {
+            let accept_encoding = headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()).unwrap_or("");
+            let builder = builder.status(StatusCode::OK);
+            if head_only {
+                return Ok(builder.header(header::CONTENT_LENGTH, len).body(empty()).unwrap());
+            }
+
+            let file = File::open(path).await?;
+            if accept_encoding.contains("br") {
+                let compressed = BrotliEncoder::with_quality(BufReader::new(file), async_compression::Level::Best);
+                let body = StreamBody::new(ReaderStream::new(compressed).map(|f| f.map(Frame::data)));
+                return Ok(builder.header(header::CONTENT_ENCODING, "br").body(BodyExt::boxed(body)).unwrap());
+            } else if accept_encoding.contains("gzip") {
+                let compressed = GzipEncoder::with_quality(BufReader::new(file), async_compression::Level::Best);
+                let body = StreamBody::new(ReaderStream::new(compressed).map(|f| f.map(Frame::data)));
+                return Ok(builder.header(header::CONTENT_ENCODING, "gzip").body(BodyExt::boxed(body)).unwrap());
+            }
+
+            // Zero-copy stream
+            let body = StreamBody::new(ReaderStream::new(file).map(|result| result.map(Frame::data)));
+            Ok(builder.header(header::CONTENT_LENGTH, len).body(BodyExt::boxed(body)).unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    struct TempTree {
+        base: PathBuf,
+    }
+
+    impl TempTree {
+        // Layout: <base>/root/{index.html, css/app.css} and <base>/secret.txt outside the root
+        fn new() -> Self {
+            let base = std::env::temp_dir().join(format!(
+                "qhttpx-static-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir_all(base.join("root/css")).unwrap();
+            std::fs::write(base.join("root/index.html"), "<h1>hi</h1>").unwrap();
+            std::fs::write(base.join("root/css/app.css"), "body{}").unwrap();
+            std::fs::write(base.join("secret.txt"), "secret").unwrap();
+            Self { base }
+        }
+
+        fn mount(&self, symlinks: SymlinkPolicy) -> StaticMount {
+            StaticMount::new("/static".to_string(), self.base.join("root").to_str().unwrap(), symlinks).unwrap()
+        }
+    }
+
+    impl Drop for TempTree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    #[test]
+    fn sanitize_rejects_traversal_vectors() {
+        let vectors = [
+            "/../secret.txt",
+            "/css/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/%2E%2E%2Fsecret.txt",
+            "/..%2fsecret.txt",
+            "/..%5csecret.txt",
+            "/css%5c..%5c..%5csecret.txt",
+            "/index.html%00.png",
+            "/%ff%fe",
+        ];
+        for vector in vectors {
+            assert_eq!(sanitize(vector), Err(ResolveError::Forbidden), "{}", vector);
+        }
+    }
+
+    #[test]
+    fn sanitize_normalises_harmless_segments() {
+        assert_eq!(sanitize("/./css//app.css").unwrap(), PathBuf::from("css").join("app.css"));
+        assert_eq!(sanitize("/my%20file.txt").unwrap(), PathBuf::from("my file.txt"));
+        // Double encoding decodes once only, leaving a literal file name
+        assert_eq!(sanitize("/%252e%252e").unwrap(), PathBuf::from("%2e%2e"));
+    }
+
+    #[test]
+    fn strip_prefix_respects_segment_boundary() {
+        let tree = TempTree::new();
+        let mount = tree.mount(SymlinkPolicy::WithinRoot);
+        assert_eq!(mount.strip_prefix("/static/index.html"), Some("/index.html"));
+        assert_eq!(mount.strip_prefix("/static"), Some(""));
+        assert_eq!(mount.strip_prefix("/staticfoo/index.html"), None);
+        assert_eq!(mount.strip_prefix("/other"), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_files_inside_root() {
+        let tree = TempTree::new();
+        let mount = tree.mount(SymlinkPolicy::WithinRoot);
+        match mount.resolve("/css/app.css").await.unwrap() {
+            Resolved::File(path) => assert!(path.starts_with(tree.base.join("root").canonicalize().unwrap())),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(mount.resolve("/missing.txt").await.err(), Some(ResolveError::NotFound));
+        // Directories are not served
+        assert_eq!(mount.resolve("/css").await.err(), Some(ResolveError::NotFound));
+        assert_eq!(mount.resolve("/../secret.txt").await.err(), Some(ResolveError::Forbidden));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlink_policies() {
+        let tree = TempTree::new();
+        let root = tree.base.join("root");
+        std::os::unix::fs::symlink(tree.base.join("secret.txt"), root.join("escape.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("index.html"), root.join("alias.html")).unwrap();
+        std::os::unix::fs::symlink(tree.base.clone(), root.join("up")).unwrap();
+
+        let deny = tree.mount(SymlinkPolicy::Deny);
+        assert_eq!(deny.resolve("/alias.html").await.err(), Some(ResolveError::Forbidden));
+        assert_eq!(deny.resolve("/escape.txt").await.err(), Some(ResolveError::Forbidden));
+        assert_eq!(deny.resolve("/up/secret.txt").await.err(), Some(ResolveError::Forbidden));
+        assert!(deny.resolve("/index.html").await.is_ok());
+
+        let within = tree.mount(SymlinkPolicy::WithinRoot);
+        assert!(within.resolve("/alias.html").await.is_ok());
+        assert_eq!(within.resolve("/escape.txt").await.err(), Some(ResolveError::Forbidden));
+        assert_eq!(within.resolve("/up/secret.txt").await.err(), Some(ResolveError::Forbidden));
+
+        let follow = tree.mount(SymlinkPolicy::Follow);
+        assert!(follow.resolve("/alias.html").await.is_ok());
+        assert!(follow.resolve("/escape.txt").await.is_ok());
+    }
+
+    #[test]
+    fn symlink_policy_names() {
+        assert_eq!(SymlinkPolicy::from_str("Deny"), Ok(SymlinkPolicy::Deny));
+        assert_eq!(SymlinkPolicy::from_str("within-root"), Ok(SymlinkPolicy::WithinRoot));
+        assert_eq!(SymlinkPolicy::from_str("FOLLOW"), Ok(SymlinkPolicy::Follow));
+        assert!(SymlinkPolicy::from_str("allow").is_err());
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(name.clone(), value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn evaluate_conditionals_and_ranges() {
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let etag = "\"abc\"";
+        let eval = |pairs: &[(header::HeaderName, &str)]| evaluate(&headers(pairs), etag, modified, 1000);
+
+        assert_eq!(eval(&[]), Selection::Full);
+        assert_eq!(eval(&[(header::IF_NONE_MATCH, "\"abc\"")]), Selection::NotModified);
+        assert_eq!(eval(&[(header::IF_NONE_MATCH, "W/\"abc\", \"x\"")]), Selection::NotModified);
+        assert_eq!(eval(&[(header::IF_NONE_MATCH, "\"other\"")]), Selection::Full);
+        assert_eq!(eval(&[(header::IF_MODIFIED_SINCE, &httpdate::fmt_http_date(modified))]), Selection::NotModified);
+
+        assert_eq!(eval(&[(header::RANGE, "bytes=0-99")]), Selection::Partial(0, 99));
+        assert_eq!(eval(&[(header::RANGE, "bytes=900-")]), Selection::Partial(900, 999));
+        assert_eq!(eval(&[(header::RANGE, "bytes=-100")]), Selection::Partial(900, 999));
+        assert_eq!(eval(&[(header::RANGE, "bytes=990-5000")]), Selection::Partial(990, 999));
+        assert_eq!(eval(&[(header::RANGE, "bytes=1000-")]), Selection::Unsatisfiable);
+        assert_eq!(eval(&[(header::RANGE, "bytes=0-1,5-6")]), Selection::Full);
+        assert_eq!(eval(&[(header::RANGE, "items=0-1")]), Selection::Full);
+
+        assert_eq!(eval(&[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, "\"abc\"")]), Selection::Partial(0, 9));
+        assert_eq!(eval(&[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, "\"old\"")]), Selection::Full);
+    }
+
+    #[tokio::test]
+    async fn bundle_resolves_and_serves_variants() {
+        let mut files = HashMap::new();
+        files.insert("/index.html".to_string(), b"<html>hello</html>".repeat(50));
+        files.insert("js/app.js".to_string(), b"console.log(1);".to_vec());
+        let mount = StaticMount::bundle("/assets".to_string(), AssetBundle::from_files(files).unwrap());
+
+        let asset = match mount.resolve("/").await.unwrap() {
+            Resolved::Asset(asset) => asset,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(asset.content_type, "text/html");
+        assert!(asset.gzip.is_some() && asset.brotli.is_some());
+        assert!(mount.resolve("/js/app.js").await.is_ok());
+        assert_eq!(mount.resolve("/js/../../etc/passwd").await.err(), Some(ResolveError::Forbidden));
+        assert_eq!(mount.resolve("/missing.js").await.err(), Some(ResolveError::NotFound));
+        assert_eq!(mount.resolve("/js/").await.err(), Some(ResolveError::NotFound));
+
+        let res = serve_asset(Response::builder(), &asset, &headers(&[(header::ACCEPT_ENCODING, "gzip, br")]), false);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "br");
+        let br_etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        let res = serve_asset(Response::builder(), &asset, &headers(&[(header::ACCEPT_ENCODING, "gzip")]), false);
+        let gz_etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(br_etag, format!("{}-br\"", asset.etag.trim_end_matches('"')));
+        assert_eq!(gz_etag, format!("{}-gz\"", asset.etag.trim_end_matches('"')));
+        assert!(br_etag != asset.etag && gz_etag != asset.etag);
+
+        let res = serve_asset(Response::builder(), &asset, &headers(&[(header::IF_NONE_MATCH, asset.etag.as_str())]), false);
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()[header::ETAG], asset.etag.as_str());
+        // A cached gzip copy does not validate the brotli representation
+        let res = serve_asset(Response::builder(), &asset, &headers(&[(header::ACCEPT_ENCODING, "br"), (header::IF_NONE_MATCH, gz_etag.as_str())]), false);
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = serve_asset(Response::builder(), &asset, &headers(&[(header::ACCEPT_ENCODING, "br"), (header::IF_NONE_MATCH, br_etag.as_str())]), false);
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = serve_asset(Response::builder(), &asset, &headers(&[(header::RANGE, "bytes=0-5")]), false);
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], format!("bytes 0-5/{}", asset.body.len()));
+    }
+
+    #[tokio::test]
+    async fn tar_bundle_keeps_mtimes_and_serves_directory_indexes() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data, mtime) in [("index.html", &b"root"[..], 1_700_000_000), ("docs/index.html", b"docs", 1_600_000_000)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        let mount = StaticMount::bundle("/".to_string(), AssetBundle::from_tar(&archive).unwrap());
+
+        let asset = |resolved| match resolved {
+            Ok(Resolved::Asset(asset)) => asset,
+            other => panic!("unexpected {:?}", other),
+        };
+        let docs = asset(mount.resolve("/docs/").await);
+        assert_eq!(docs.body, Bytes::from_static(b"docs"));
+        assert_eq!(docs.last_modified, UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000));
+        assert_eq!(asset(mount.resolve("/").await).last_modified, UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000));
+        // Without the slash relative links would break; only the file itself is served
+        assert_eq!(mount.resolve("/docs").await.err(), Some(ResolveError::NotFound));
+        assert!(mount.resolve("/docs/index.html").await.is_ok());
+    }
+
+    #[test]
+    fn bundle_rejects_unsafe_paths() {
+        let mut files = HashMap::new();
+        files.insert("../escape.txt".to_string(), b"x".to_vec());
+        assert!(AssetBundle::from_files(files).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 import { NativeEngine } from '../core';
-import { RequestContext, EnvContext, DatabaseContext, RequestMetrics, RequestSnapshot } from '../types';
+import { RequestContext, EnvContext, DatabaseContext, RequestMetrics, RequestSnapshot, ClientCertificate, CookieConfig } from '../types';
 
 export class Context implements RequestContext {
     private _status: number = 200;
@@ -14,6 +14,16 @@ export class Context implements RequestContext {
     env!: EnvContext;
     db!: DatabaseContext;
     perf!: RequestMetrics;
+    user?: Record<string, any>;
+    apiKey?: Record<string, any>;
+    clientCert?: ClientCertificate;
+    cspNonce?: string;
+    csrfToken?: string;
+    cookies: Record<string, string> = {};
+    session?: Record<string, any> | null;
+    private regenerate = false;
+    // Flat name/value pairs, like rawHeaders; sent with the response
+    private responseHeaders: string[] = [];
 
     constructor(
         private engine: NativeEngine,
@@ -147,23 +157,83 @@ export class Context implements RequestContext {
         return this.req.json<T>();
     }
 
+    /** Moves the session to a new id when the response is sent; call after login */
+    regenerateSession(): void {
+        this.regenerate = true;
+    }
+
+    private commitSession(): void {
+        // Undefined means sessions are off; null destroys the session
+        if (this.session !== undefined) {
+            this.engine.saveSession(this.id, this.session, this.regenerate);
+        }
+    }
+
+    /** Sets a response header, replacing earlier values of it (e.g. Cache-Tag, Cache-Control) */
+    header(name: string, value: string): this {
+        const lower = name.toLowerCase();
+        const kept: string[] = [];
+        for (let i = 0; i < this.responseHeaders.length; i += 2) {
+            if (this.responseHeaders[i].toLowerCase() !== lower) {
+                kept.push(this.responseHeaders[i], this.responseHeaders[i + 1]);
+            }
+        }
+        kept.push(name, value);
+        this.responseHeaders = kept;
+        return this;
+    }
+
+    /** Adds another value for a response header, e.g. a second Set-Cookie */
+    appendHeader(name: string, value: string): this {
+        this.responseHeaders.push(name, value);
+        return this;
+    }
+
+    /** Adds a Set-Cookie; signed and encrypted values use the keys from setCookieKeys */
+    setCookie(name: string, value: string, options: CookieConfig = {}): this {
+        const { signed, encrypted, ...attributes } = options;
+        if (encrypted) {
+            value = this.engine.encryptCookie(name, value);
+        } else if (signed) {
+            value = this.engine.signCookie(name, value);
+        }
+        return this.appendHeader('Set-Cookie', this.engine.serializeCookie(name, value, attributes));
+    }
+
+    /** Deletes a cookie; pass the path and domain it was set with */
+    clearCookie(name: string, options: { path?: string; domain?: string } = {}): this {
+        return this.appendHeader('Set-Cookie', this.engine.serializeCookie(name, '', { ...options, maxAge: 0 }));
+    }
+
+    signedCookie(name: string): string | undefined {
+        const raw = this.cookies[name];
+        return raw === undefined ? undefined : this.engine.unsignCookie(name, raw) ?? undefined;
+    }
+
+    encryptedCookie(name: string): string | undefined {
+        const raw = this.cookies[name];
+        return raw === undefined ? undefined : this.engine.decryptCookie(name, raw) ?? undefined;
+    }
+
     send(data: any): void {
+        this.commitSession();
         if (typeof data === 'string') {
-             this.engine.sendResponse(this.responseHandle, this._status, data);
+             this.engine.sendResponse(this.responseHandle, this._status, data, this.responseHeaders);
         } else {
             if (this.serializer) {
                 const body = this.serializer(data);
-                this.engine.sendResponse(this.responseHandle, this._status, body);
+                this.engine.sendResponse(this.responseHandle, this._status, body, this.responseHeaders);
             } else {
                 // Fallback to manual stringify if engine.sendJson is problematic or just to be safe
                 // this.engine.sendJson(this.responseHandle, this._status, data);
-                this.engine.sendResponse(this.responseHandle, this._status, JSON.stringify(data));
+                this.engine.sendResponse(this.responseHandle, this._status, JSON.stringify(data), this.responseHeaders);
             }
         }
     }
 
     html(content: string): void {
-        this.engine.sendHtml(this.responseHandle, this._status, content);
+        this.commitSession();
+        this.engine.sendHtml(this.responseHandle, this._status, content, this.responseHeaders);
     }
 
     status(code: number): this {
@@ -461,18 +461,8 @@ export class FluentBuilder {
         this.steps.unshift({
             name: 'auth:extract',
             fn: (ctx, state) => {
-                // Native JWT middleware validates the token, but we might need to access payload
-                // The native engine doesn't automatically inject payload into ctx yet (pending feature)
-                // So we manually parse it for now to populate state.user
-                const authHeader = ctx.req.header('authorization');
-                if (authHeader) {
-                    const token = authHeader.split(' ')[1];
-                    try {
-                        const payload = JSON.parse(Buffer.from(token.split('.')[1], 'base64').toString());
-                        return { user: payload };
-                    } catch (e) {}
-                }
-                return state;
+                // The native engine verifies the token and hands over its claims
+                return ctx.user ? { user: ctx.user } : state;
             }
         });
         return this;
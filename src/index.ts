@@ -1,5 +1,5 @@
 import { NativeEngine } from '../core';
-import type { App as AppInterface, RouteBuilder, QueryBuilder, Handler, Middleware, WsHandler, WebSocket, RouteConfig, ListenOptions, RouteOptions, RequestContext } from '../types';
+import type { App as AppInterface, RouteBuilder, QueryBuilder, Handler, Middleware, WsHandler, WebSocket, RouteConfig, ListenOptions, RouteOptions, RequestContext, CorsConfig, CsrfConfig } from '../types';
 import { Context } from './context';
 import { compose } from './compose';
 import { loadEnv, env } from './env';
@@ -244,8 +244,9 @@ class App implements AppInterface {
     private middlewares: Middleware[] = [];
     private staticRoutes = new Map<string, string>(); // prefix -> dir
     private wsHandlers = new Map<string, WsHandler>();
-    private activeSockets = new Map<string, { handler: WsHandler, path: string }>();
-    private corsConfig: { origin: string, methods: string, headers: string, credentials: boolean } | null = null;
+    private activeSockets = new Map<string, { handler: WsHandler, path: string, user?: Record<string, any> }>();
+    private corsConfig: CorsConfig | null = null;
+    private csrfConfig: CsrfConfig | null = null;
     private loggingEnabled = false;
     private errorHandler: ((err: unknown, ctx?: RequestContext) => void) | null = null;
     private errorHooksRegistered = false;
@@ -527,13 +528,13 @@ class App implements AppInterface {
         this.wsHandlers.set(path, handler);
     }
 
-    cors(config: { origin?: string, methods?: string[], headers?: string[], credentials?: boolean } = {}): this {
-        this.corsConfig = {
-            origin: config.origin || '*',
-            methods: (config.methods || ['GET', 'POST', 'PUT', 'DELETE', 'OPTIONS']).join(', '),
-            headers: (config.headers || ['Content-Type', 'Authorization']).join(', '),
-            credentials: config.credentials || false
-        };
+    cors(config: CorsConfig = {}): this {
+        this.corsConfig = config;
+        return this;
+    }
+
+    csrf(config: CsrfConfig = {}): this {
+        this.csrfConfig = config;
         return this;
     }
 
@@ -664,7 +665,7 @@ class App implements AppInterface {
     listen(portOrOptions: number | ListenOptions, cb?: () => void): void {
         let port: number;
         let callback = cb;
-        let tls: { cert: string; key: string } | undefined;
+        let tls: ListenOptions['tls'];
 
         if (typeof portOrOptions === 'object') {
             port = portOrOptions.port;
@@ -685,7 +686,16 @@ class App implements AppInterface {
             }
 
             if (tls) {
-                this.engine.setTls(tls.cert, tls.key);
+                this.engine.setTls(tls.cert, tls.key, tls.ocsp);
+                if (tls.watch) {
+                    this.engine.watchTls();
+                }
+                if (tls.hsts) {
+                    this.engine.setHsts(tls.hsts);
+                }
+                if (tls.redirectFrom) {
+                    this.engine.enableHttpsRedirect(tls.redirectFrom);
+                }
             }
             
             // Register static routes
@@ -695,12 +705,17 @@ class App implements AppInterface {
 
             // Register CORS
             if (this.corsConfig) {
-                this.engine.setCors(
-                    this.corsConfig.origin, 
-                    this.corsConfig.methods, 
-                    this.corsConfig.headers, 
-                    this.corsConfig.credentials
-                );
+                const { origin, origins, headers, ...rest } = this.corsConfig;
+                this.engine.setCors({
+                    ...rest,
+                    origins: origins ?? (origin ? [origin] : undefined),
+                    allowHeaders: headers,
+                });
+            }
+
+            // Register CSRF
+            if (this.csrfConfig) {
+                this.engine.setCsrf(this.csrfConfig);
             }
 
             // Register Security Headers
@@ -711,13 +726,20 @@ class App implements AppInterface {
         
         // 1. Register Dispatcher
         this.engine.setHandler((event: any) => {
-            const { handlerId, reqId, params, query, body, headers, url, responseHandle, method } = event;
+            const { handlerId, reqId, params, query, body, headers, url, responseHandle, method, user, apiKey, clientCert, cspNonce, csrfToken, cookies, session } = event;
             const routeConfig = this.handlers.get(handlerId);
             
             if (routeConfig) {
                 const { handler, serializer } = routeConfig;
                 // Params is now array [k,v,k,v]
                 const ctx = new Context(this.engine!, reqId, params, query, body, headers, url, responseHandle, method, serializer);
+                ctx.user = user;
+                ctx.apiKey = apiKey;
+                ctx.clientCert = clientCert;
+                ctx.cspNonce = cspNonce;
+                ctx.csrfToken = csrfToken;
+                ctx.cookies = cookies ?? {};
+                ctx.session = session;
                 
                 // Wrap handler as middleware
                 const routeMiddleware: Middleware = async (c, next) => {
@@ -742,18 +764,19 @@ class App implements AppInterface {
 
         // 1.5 Register WS Dispatcher
         this.engine.setWsHandler((event: any) => {
-            const { socketId, eventType, payload, path } = event;
+            const { socketId, eventType, payload, path, user } = event;
 
             if (eventType === 'open') {
                 const handler = this.wsHandlers.get(path);
                 if (handler) {
-                    this.activeSockets.set(socketId, { handler, path });
+                    this.activeSockets.set(socketId, { handler, path, user });
                     if (handler.open) {
                         const ws: WebSocket = {
                             send: (msg: string) => this.engine!.wsSend(socketId, msg),
                             subscribe: (room: string) => this.engine!.wsSubscribe(socketId, room),
                             unsubscribe: (room: string) => this.engine!.wsUnsubscribe(socketId, room),
-                            publish: (room: string, msg: string) => this.engine!.wsPublish(room, msg)
+                            publish: (room: string, msg: string) => this.engine!.wsPublish(room, msg),
+                            user
                         };
                         handler.open(ws);
                     }
@@ -761,12 +784,13 @@ class App implements AppInterface {
             } else {
                 const ctx = this.activeSockets.get(socketId);
                 if (ctx) {
-                    const { handler } = ctx;
+                    const { handler, user } = ctx;
                     const ws: WebSocket = {
                         send: (msg: string) => this.engine!.wsSend(socketId, msg),
                         subscribe: (room: string) => this.engine!.wsSubscribe(socketId, room),
                         unsubscribe: (room: string) => this.engine!.wsUnsubscribe(socketId, room),
-                        publish: (room: string, msg: string) => this.engine!.wsPublish(room, msg)
+                        publish: (room: string, msg: string) => this.engine!.wsPublish(room, msg),
+                        user
                     };
 
                     if (eventType === 'message') {
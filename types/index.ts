@@ -11,6 +11,10 @@ export interface RequestContext {
     readonly statusCode: number; // Getter for current status
     send(data: any): void; // Send response (Native)
     html(content: string): void; // Send HTML response
+    /** Sets a response header, replacing earlier values (e.g. Cache-Tag, Cache-Control: no-store) */
+    header(name: string, value: string): this;
+    /** Adds another value for a response header, e.g. a second Set-Cookie */
+    appendHeader(name: string, value: string): this;
 
     // Request Data
     req: {
@@ -39,9 +43,62 @@ export interface RequestContext {
     db?: DatabaseContext;
     /** Performance metrics for this request */
     perf?: RequestMetrics;
+    /** Verified JWT claims (routes protected with jwt()) */
+    user?: Record<string, any>;
+    /** Metadata of the verified API key (owner, scopes, tier, ...) on api_key_auth routes */
+    apiKey?: Record<string, any>;
+    /** Verified mTLS client certificate, when the client presented one */
+    clientCert?: ClientCertificate;
+    /** Per-request CSP nonce, when the security policy's CSP contains {nonce} */
+    cspNonce?: string;
+    /** Current CSRF token when CSRF protection is on, for embedding in forms */
+    csrfToken?: string;
+    /** Request cookies by name */
+    cookies: Record<string, string>;
+    /** Adds a Set-Cookie; signed and encrypted values use the keys from setCookieKeys */
+    setCookie(name: string, value: string, options?: CookieConfig): this;
+    /** Deletes a cookie; pass the path and domain it was set with */
+    clearCookie(name: string, options?: { path?: string; domain?: string }): this;
+    /** Value of a signed cookie, or undefined if it is missing or its signature does not match */
+    signedCookie(name: string): string | undefined;
+    /** Value of an encrypted cookie, or undefined if it is missing or cannot be decrypted */
+    encryptedCookie(name: string): string | undefined;
+    /** Session data when sessions are configured; changes are saved after the response, null destroys it */
+    session?: Record<string, any> | null;
+    regenerateSession(): void;
     snapshot(): RequestSnapshot;
 }
 
+export interface CookieConfig {
+    /** Default "/" */
+    path?: string;
+    domain?: string;
+    /** Seconds; unset lasts the browser session */
+    maxAge?: number;
+    /** Default true */
+    secure?: boolean;
+    /** Default true */
+    httpOnly?: boolean;
+    /** "Strict" | "Lax" (default) | "None" */
+    sameSite?: string;
+    signed?: boolean;
+    encrypted?: boolean;
+}
+
+export interface ClientCertificate {
+    /** e.g. "CN=billing-service, O=Acme" */
+    subject: string;
+    issuer: string;
+    /** "DNS:host", "URI:spiffe://...", "IP:10.0.0.7", "email:a@b" */
+    sans: string[];
+    /** Lowercase hex SHA-256 of the DER certificate */
+    fingerprint: string;
+    serial: string;
+    /** Unix seconds */
+    notBefore: number;
+    notAfter: number;
+}
+
 export interface RequestSnapshot {
     id: string;
     method: string;
@@ -131,6 +188,8 @@ export interface WebSocket {
     subscribe(room: string): void;
     unsubscribe(room: string): void;
     publish(room: string, message: string): void;
+    /** Verified JWT claims when the upgrade path is protected with jwt() */
+    user?: Record<string, any>;
 }
 
 export interface WsHandler {
@@ -152,11 +211,47 @@ export interface QueryField {
     min(val: number): this;
 }
 
+export interface CorsConfig {
+    /** Single allowed origin (kept for compatibility; prefer origins) */
+    origin?: string;
+    /** Exact origins or patterns like "https://*.example.com"; default ["*"] */
+    origins?: string[];
+    methods?: string[];
+    /** Request headers allowed in preflights; "*" allows any */
+    headers?: string[];
+    exposeHeaders?: string[];
+    /** Cannot be combined with origin "*" */
+    credentials?: boolean;
+    /** Preflight cache in seconds (default 86400) */
+    maxAge?: number;
+    /** Answer Private Network Access preflights */
+    privateNetwork?: boolean;
+}
+
+export interface CsrfConfig {
+    /** Signs tokens (32+ bytes); random per process if unset */
+    secret?: string;
+    cookieName?: string;
+    headerName?: string;
+    formField?: string;
+    /** Other origins allowed to send unsafe requests, exact or with "*" */
+    trustedOrigins?: string[];
+    secure?: boolean;
+}
+
 export interface ListenOptions {
     port: number;
     tls?: {
         cert: string;
         key: string;
+        /** DER-encoded OCSP response to staple */
+        ocsp?: string;
+        /** Reload certificate files when they change */
+        watch?: boolean;
+        /** Strict-Transport-Security sent on TLS responses */
+        hsts?: { maxAge?: number; includeSubdomains?: boolean; preload?: boolean };
+        /** Plain-HTTP port that only redirects to HTTPS */
+        redirectFrom?: number;
     };
     callback?: () => void;
 }
@@ -264,10 +359,8 @@ export interface App {
     use(middleware: Middleware): this;
     
     // CORS
-    cors(config?: { 
-        origin?: string; 
-        methods?: string[]; 
-        headers?: string[]; 
-        credentials?: boolean; 
-    }): this;
+    cors(config?: CorsConfig): this;
+
+    // CSRF (unsafe methods need the csrf_token cookie echoed in a header or form field)
+    csrf(config?: CsrfConfig): this;
 }